@@ -0,0 +1,314 @@
+use std::ffi::CStr;
+
+use ash::vk;
+
+use crate::core::Core;
+use crate::error::{Error, Result};
+
+// Set to an adapter index or part of an adapter name to override the selection in code
+pub const ADAPTER_ENV_VAR: &str = "VRG_DEVICE";
+
+// A physical device as the driver reports it, for choosing which one a renderer runs on
+#[derive(Clone, Debug)]
+pub struct Adapter {
+    pub index: usize,
+    pub physical_device: vk::PhysicalDevice,
+
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub api_version: u32,
+
+    // The version is encoded however the vendor likes. Names are reported by Vulkan 1.2 drivers and drivers with
+    // VK_KHR_driver_properties, and are None otherwise
+    pub driver_version: u32,
+    pub driver_name: Option<String>,
+    pub driver_info: Option<String>,
+
+    pub memory_heaps: Vec<vk::MemoryHeap>,
+    pub limits: vk::PhysicalDeviceLimits,
+    pub features: vk::PhysicalDeviceFeatures,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdapterSelection {
+    // The first suitable adapter in the order the driver lists them
+    First,
+    // Discrete GPUs first, then integrated, virtual and CPU adapters
+    PreferDiscrete,
+    Index(usize),
+    // Matched case insensitively against any part of the adapter name
+    Name(String),
+}
+
+pub struct AdapterPolicy {
+    pub selection: AdapterSelection,
+    pub required_features: vk::PhysicalDeviceFeatures,
+    pub use_env_var: bool,
+}
+
+impl Adapter {
+    pub unsafe fn enumerate(c: &Core) -> Result<Vec<Adapter>> {
+        Ok(c.instance.enumerate_physical_devices()?.into_iter().enumerate().map(|(index, physical_device)| {
+            Adapter::new(c, index, physical_device)
+        }).collect())
+    }
+
+    unsafe fn new(c: &Core, index: usize, physical_device: vk::PhysicalDevice) -> Adapter {
+        let properties = c.instance.get_physical_device_properties(physical_device);
+        let memory_properties = c.instance.get_physical_device_memory_properties(physical_device);
+
+        // Driver properties are core in Vulkan 1.2 devices and come from VK_KHR_driver_properties on older ones
+        let driver_properties_supported = properties.api_version >= vk::API_VERSION_1_2 || c.instance.enumerate_device_extension_properties(physical_device).unwrap_or_default().iter().any(|extension| {
+            CStr::from_ptr(extension.extension_name.as_ptr()) == ash::khr::driver_properties::NAME
+        });
+
+        let (driver_name, driver_info) = if c.properties2 && driver_properties_supported {
+            let mut driver_properties = vk::PhysicalDeviceDriverProperties::default();
+            let mut properties2 = vk::PhysicalDeviceProperties2::default().push_next(&mut driver_properties);
+            c.get_physical_device_properties2(physical_device, &mut properties2);
+
+            (Some(CStr::from_ptr(driver_properties.driver_name.as_ptr()).to_string_lossy().into_owned()), Some(CStr::from_ptr(driver_properties.driver_info.as_ptr()).to_string_lossy().into_owned()))
+        } else {
+            (None, None)
+        };
+
+        Adapter {
+            index,
+            physical_device,
+
+            name: CStr::from_ptr(properties.device_name.as_ptr()).to_string_lossy().into_owned(),
+            device_type: properties.device_type,
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            api_version: properties.api_version,
+
+            driver_version: properties.driver_version,
+            driver_name,
+            driver_info,
+
+            memory_heaps: memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize].to_vec(),
+            limits: properties.limits,
+            features: c.instance.get_physical_device_features(physical_device),
+        }
+    }
+
+    pub fn device_local_memory(&self) -> vk::DeviceSize {
+        self.memory_heaps.iter().filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL)).map(|heap| heap.size).sum()
+    }
+
+    pub fn supports_features(&self, features: &vk::PhysicalDeviceFeatures) -> bool {
+        feature_flags(&self.features).iter().zip(feature_flags(features)).all(|(&supported, &required)| supported == vk::TRUE || required == vk::FALSE)
+    }
+
+    fn type_rank(&self) -> usize {
+        match self.device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => 0,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+            vk::PhysicalDeviceType::CPU => 3,
+            _ => 4,
+        }
+    }
+}
+
+impl AdapterPolicy {
+    pub fn new() -> AdapterPolicy {
+        AdapterPolicy {
+            selection: AdapterSelection::PreferDiscrete,
+            required_features: vk::PhysicalDeviceFeatures::default(),
+            use_env_var: true,
+        }
+    }
+
+    pub fn select(mut self, selection: AdapterSelection) -> AdapterPolicy {
+        self.selection = selection;
+
+        self
+    }
+
+    pub fn index(self, index: usize) -> AdapterPolicy {
+        self.select(AdapterSelection::Index(index))
+    }
+
+    pub fn name(self, name: &str) -> AdapterPolicy {
+        self.select(AdapterSelection::Name(name.to_string()))
+    }
+
+    // Adapters without every feature enabled here are passed over, and the chosen one has them all enabled
+    pub fn require_features(mut self, features: vk::PhysicalDeviceFeatures) -> AdapterPolicy {
+        self.required_features = features;
+
+        self
+    }
+
+    // Stops VRG_DEVICE from overriding the selection
+    pub fn ignore_env_var(mut self) -> AdapterPolicy {
+        self.use_env_var = false;
+
+        self
+    }
+
+    // An index in the environment variable selects by index, anything else by name
+    fn effective_selection(&self) -> AdapterSelection {
+        match std::env::var(ADAPTER_ENV_VAR) {
+            Ok(value) if self.use_env_var && !value.trim().is_empty() => match value.trim().parse::<usize>() {
+                Ok(index) => AdapterSelection::Index(index),
+                Err(_) => AdapterSelection::Name(value.trim().to_string()),
+            },
+            _ => self.selection.clone(),
+        }
+    }
+
+    // Picks from adapters that have already been checked for everything else the renderer needs
+    pub fn choose<'a, T>(&self, suitable: &'a Vec<(Adapter, T)>) -> Result<&'a (Adapter, T)> {
+        let suitable = suitable.iter().filter(|(adapter, _)| adapter.supports_features(&self.required_features)).collect::<Vec<_>>();
+
+        let selection = self.effective_selection();
+
+        let chosen = match &selection {
+            AdapterSelection::First => suitable.first().copied(),
+            AdapterSelection::PreferDiscrete => suitable.iter().min_by_key(|(adapter, _)| adapter.type_rank()).copied(),
+            AdapterSelection::Index(index) => suitable.iter().find(|(adapter, _)| adapter.index == *index).copied(),
+            AdapterSelection::Name(name) => suitable.iter().find(|(adapter, _)| adapter.name.to_lowercase().contains(&name.to_lowercase())).copied(),
+        };
+
+        match (chosen, selection) {
+            (Some(chosen), _) => Ok(chosen),
+            (None, AdapterSelection::First | AdapterSelection::PreferDiscrete) => Err(Error::NoSuitableDevice),
+            (None, selection) => Err(Error::Unsupported(format!("No suitable adapter matches {:?}", selection))),
+        }
+    }
+}
+
+// Every field of PhysicalDeviceFeatures is a Bool32, so the struct can be compared as a slice of them
+fn feature_flags(features: &vk::PhysicalDeviceFeatures) -> &[vk::Bool32] {
+    unsafe {
+        std::slice::from_raw_parts(features as *const vk::PhysicalDeviceFeatures as *const vk::Bool32, std::mem::size_of::<vk::PhysicalDeviceFeatures>() / std::mem::size_of::<vk::Bool32>())
+    }
+}
+
+// Turns on every feature enabled in either
+pub fn merge_features(a: &vk::PhysicalDeviceFeatures, b: &vk::PhysicalDeviceFeatures) -> vk::PhysicalDeviceFeatures {
+    let mut merged = *a;
+
+    let merged_flags = unsafe {
+        std::slice::from_raw_parts_mut(&mut merged as *mut vk::PhysicalDeviceFeatures as *mut vk::Bool32, std::mem::size_of::<vk::PhysicalDeviceFeatures>() / std::mem::size_of::<vk::Bool32>())
+    };
+
+    for (flag, &other) in merged_flags.iter_mut().zip(feature_flags(b)) {
+        *flag |= other;
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    // Tests that set VRG_DEVICE hold this, the others ignore the variable
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    fn adapter(index: usize, name: &str, device_type: vk::PhysicalDeviceType) -> (Adapter, usize) {
+        (Adapter {
+            index,
+            physical_device: vk::PhysicalDevice::null(),
+
+            name: name.to_string(),
+            device_type,
+            vendor_id: 0,
+            device_id: 0,
+            api_version: vk::API_VERSION_1_0,
+
+            driver_version: 0,
+            driver_name: None,
+            driver_info: None,
+
+            memory_heaps: Vec::new(),
+            limits: vk::PhysicalDeviceLimits::default(),
+            features: vk::PhysicalDeviceFeatures::default(),
+        }, index)
+    }
+
+    fn adapters() -> Vec<(Adapter, usize)> {
+        vec![
+            adapter(0, "Mesa llvmpipe", vk::PhysicalDeviceType::CPU),
+            adapter(1, "Intel UHD Graphics", vk::PhysicalDeviceType::INTEGRATED_GPU),
+            adapter(2, "NVIDIA GeForce RTX", vk::PhysicalDeviceType::DISCRETE_GPU),
+        ]
+    }
+
+    fn chosen(policy: AdapterPolicy, suitable: &Vec<(Adapter, usize)>) -> Result<usize> {
+        policy.choose(suitable).map(|(_, index)| *index)
+    }
+
+    #[test]
+    fn first_takes_driver_order() {
+        assert_eq!(chosen(AdapterPolicy::new().select(AdapterSelection::First).ignore_env_var(), &adapters()).unwrap(), 0);
+    }
+
+    #[test]
+    fn prefer_discrete_ranks_by_type() {
+        assert_eq!(chosen(AdapterPolicy::new().ignore_env_var(), &adapters()).unwrap(), 2);
+
+        let without_discrete = adapters().into_iter().take(2).collect();
+        assert_eq!(chosen(AdapterPolicy::new().ignore_env_var(), &without_discrete).unwrap(), 1);
+    }
+
+    #[test]
+    fn index_and_name_selection() {
+        assert_eq!(chosen(AdapterPolicy::new().index(1).ignore_env_var(), &adapters()).unwrap(), 1);
+        assert_eq!(chosen(AdapterPolicy::new().name("geforce").ignore_env_var(), &adapters()).unwrap(), 2);
+    }
+
+    #[test]
+    fn unmatched_selection_is_an_error() {
+        assert!(matches!(chosen(AdapterPolicy::new().index(5).ignore_env_var(), &adapters()), Err(Error::Unsupported(_))));
+        assert!(matches!(chosen(AdapterPolicy::new().name("radeon").ignore_env_var(), &adapters()), Err(Error::Unsupported(_))));
+        assert!(matches!(chosen(AdapterPolicy::new().ignore_env_var(), &Vec::new()), Err(Error::NoSuitableDevice)));
+    }
+
+    #[test]
+    fn adapters_missing_required_features_are_passed_over() {
+        let mut suitable = adapters();
+        suitable[0].0.features.geometry_shader = vk::TRUE;
+
+        let features = vk::PhysicalDeviceFeatures::default().geometry_shader(true);
+        assert_eq!(chosen(AdapterPolicy::new().require_features(features).ignore_env_var(), &suitable).unwrap(), 0);
+    }
+
+    #[test]
+    fn env_var_overrides_selection() {
+        let _lock = ENV_LOCK.lock().unwrap();
+
+        std::env::set_var(ADAPTER_ENV_VAR, "1");
+        let by_index = chosen(AdapterPolicy::new().name("geforce"), &adapters());
+
+        std::env::set_var(ADAPTER_ENV_VAR, " llvmpipe ");
+        let by_name = chosen(AdapterPolicy::new(), &adapters());
+
+        std::env::set_var(ADAPTER_ENV_VAR, "");
+        let empty = chosen(AdapterPolicy::new(), &adapters());
+
+        std::env::remove_var(ADAPTER_ENV_VAR);
+
+        assert_eq!(by_index.unwrap(), 1);
+        assert_eq!(by_name.unwrap(), 0);
+        assert_eq!(empty.unwrap(), 2);
+    }
+
+    #[test]
+    fn ignored_env_var_keeps_selection() {
+        let _lock = ENV_LOCK.lock().unwrap();
+
+        std::env::set_var(ADAPTER_ENV_VAR, "0");
+        let result = chosen(AdapterPolicy::new().ignore_env_var(), &adapters());
+        std::env::remove_var(ADAPTER_ENV_VAR);
+
+        assert_eq!(result.unwrap(), 2);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::allocator::MemoryUsage;
+use crate::device::Device;
+use crate::error::Result;
+use crate::layer::{Layer, LayerDependencyInfo};
+use crate::renderer_data::RendererData;
+use crate::util::graph::Graph;
+
+// Where a transient image is first and last used, as steps through every pass recorded, in recording order
+#[derive(Clone)]
+pub struct TransientLifetime {
+    pub image_ref: usize,
+    pub first: usize,
+    pub last: usize,
+    pub first_layer: String,
+    pub last_layer: String,
+    pub memory_type_bits: u32,
+    pub memory_usage: MemoryUsage,
+}
+
+// Memory held by transient images across all frames in flight, with and without aliasing
+#[derive(Copy, Clone, Default, Debug)]
+pub struct AliasingReport {
+    pub transient_images: usize,
+    pub memory_blocks: usize,
+    pub transient_bytes: u64,
+    pub aliased_bytes: u64,
+    pub saved_bytes: u64,
+}
+
+// Lifetimes of every transient image used by the graph, ordered by when they are first used.
+// Declared accesses, render targets and images bound through descriptors all count as uses
+pub unsafe fn transient_lifetimes(d: &Device, layer_graph: &Graph<usize, LayerDependencyInfo>, layers: &Vec<Layer>, data: &RendererData) -> Result<Vec<TransientLifetime>> {
+    let mut lifetimes = Vec::<TransientLifetime>::new();
+    let mut step = 0;
+
+    for node in layer_graph.topological_order(None)? {
+        let layer = &layers[node.data];
+
+        if layer.culled {
+            continue;
+        }
+
+        for (pass_name, pass_uses) in layer.image_uses(data)? {
+            if layer.culled_passes.contains(&pass_name) {
+                continue;
+            }
+
+            for image_ref in pass_uses {
+                let builder = match data.transient_image_builders.get(&image_ref) {
+                    Some(builder) => builder,
+                    None => continue,
+                };
+
+                match lifetimes.iter_mut().find(|lifetime| lifetime.image_ref == image_ref) {
+                    Some(lifetime) => {
+                        lifetime.last = step;
+                        lifetime.last_layer = node.name.clone();
+                    },
+                    None => lifetimes.push(TransientLifetime {
+                        image_ref,
+                        first: step,
+                        last: step,
+                        first_layer: node.name.clone(),
+                        last_layer: node.name.clone(),
+                        memory_type_bits: d.device.get_image_memory_requirements(data.images[image_ref][0].image).memory_type_bits,
+                        memory_usage: builder.memory_usage.unwrap_or(MemoryUsage::GpuOnly),
+                    }),
+                }
+            }
+
+            step += 1;
+        }
+    }
+
+    Ok(lifetimes)
+}
+
+// Packs lifetimes into groups that can share memory, each image joining the first group whose latest image is done
+// with it before it is first used. `ordered` says whether the GPU is guaranteed to finish one lifetime before starting the next
+pub fn alias_groups<F: Fn(&TransientLifetime, &TransientLifetime) -> bool>(lifetimes: &Vec<TransientLifetime>, ordered: F) -> Vec<Vec<usize>> {
+    let mut groups = Vec::<(Vec<&TransientLifetime>, u32)>::new();
+
+    for lifetime in lifetimes {
+        let group = groups.iter_mut().find(|(members, memory_type_bits)| {
+            let latest = members.last().unwrap();
+
+            latest.last < lifetime.first
+                && latest.memory_usage == lifetime.memory_usage
+                && memory_type_bits & lifetime.memory_type_bits != 0
+                && ordered(latest, lifetime)
+        });
+
+        match group {
+            Some((members, memory_type_bits)) => {
+                members.push(lifetime);
+                *memory_type_bits &= lifetime.memory_type_bits;
+            },
+            None => groups.push((vec![lifetime], lifetime.memory_type_bits)),
+        }
+    }
+
+    groups.into_iter().map(|(members, _)| members.iter().map(|lifetime| lifetime.image_ref).collect()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lifetime(image_ref: usize, first: usize, last: usize) -> TransientLifetime {
+        TransientLifetime {
+            image_ref,
+            first,
+            last,
+            first_layer: "main".to_string(),
+            last_layer: "main".to_string(),
+            memory_type_bits: 0b11,
+            memory_usage: MemoryUsage::GpuOnly,
+        }
+    }
+
+    fn groups(lifetimes: &Vec<TransientLifetime>) -> Vec<Vec<usize>> {
+        alias_groups(lifetimes, |_, _| true)
+    }
+
+    #[test]
+    fn disjoint_lifetimes_share_memory() {
+        let lifetimes = vec![lifetime(0, 0, 1), lifetime(1, 2, 3), lifetime(2, 4, 4)];
+
+        assert_eq!(groups(&lifetimes), vec![vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn overlapping_lifetimes_get_their_own_memory() {
+        let lifetimes = vec![lifetime(0, 0, 2), lifetime(1, 1, 3), lifetime(2, 3, 4)];
+
+        // The third image starts where the second ends, but after the first is done with, so it can join the first
+        assert_eq!(groups(&lifetimes), vec![vec![0, 2], vec![1]]);
+    }
+
+    #[test]
+    fn lifetimes_meeting_in_one_pass_overlap() {
+        let lifetimes = vec![lifetime(0, 0, 1), lifetime(1, 1, 2)];
+
+        assert_eq!(groups(&lifetimes), vec![vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn images_read_and_written_by_the_same_pass_never_alias() {
+        // A pass reading one transient image and writing another uses both at the same step
+        let lifetimes = vec![lifetime(0, 3, 3), lifetime(1, 3, 3)];
+
+        assert_eq!(groups(&lifetimes), vec![vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn images_read_and_written_by_one_pass_end_there() {
+        // An image both read and written by a single pass lives for that step only, so the next pass can reuse its memory
+        let lifetimes = vec![lifetime(0, 3, 3), lifetime(1, 4, 5)];
+
+        assert_eq!(groups(&lifetimes), vec![vec![0, 1]]);
+    }
+
+    #[test]
+    fn unordered_lifetimes_get_their_own_memory() {
+        let lifetimes = vec![lifetime(0, 0, 0), lifetime(1, 1, 1)];
+
+        assert_eq!(alias_groups(&lifetimes, |_, _| false), vec![vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn incompatible_memory_is_never_shared() {
+        let mut cpu_visible = lifetime(1, 1, 1);
+        cpu_visible.memory_usage = MemoryUsage::CpuToGpu;
+
+        let mut other_type = lifetime(2, 2, 2);
+        other_type.memory_type_bits = 0b100;
+
+        let lifetimes = vec![lifetime(0, 0, 0), cpu_visible, other_type];
+
+        assert_eq!(groups(&lifetimes), vec![vec![0], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn groups_keep_only_the_memory_types_every_member_allows() {
+        let mut narrow = lifetime(1, 1, 1);
+        narrow.memory_type_bits = 0b01;
+
+        let mut other_bit = lifetime(2, 2, 2);
+        other_bit.memory_type_bits = 0b10;
+
+        let lifetimes = vec![lifetime(0, 0, 0), narrow, other_bit];
+
+        assert_eq!(groups(&lifetimes), vec![vec![0, 1], vec![2]]);
+    }
+}
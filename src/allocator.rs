@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::sync::Mutex;
+
+use ash::vk::{self, Handle};
+use vk_mem::Alloc;
+
+use crate::core::Core;
+use crate::error::{Error, Result};
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum MemoryUsage {
+    GpuOnly,
+    CpuToGpu,
+    GpuToCpu,
+}
+
+#[derive(Copy, Clone)]
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: u64,
+    pub p_mapped: Option<*mut c_void>,
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct AllocationStats {
+    pub block_count: u32,
+    pub allocation_count: u32,
+    pub block_bytes: u64,
+    pub allocation_bytes: u64,
+    pub usage_bytes: u64,
+    pub budget_bytes: u64,
+}
+
+// Allocations are keyed on the raw handle of the buffer or image they back, so those can stay Copy
+pub struct Allocator {
+    pub allocator: vk_mem::Allocator,
+    allocations: Mutex<HashMap<u64, vk_mem::Allocation>>,
+
+    // Aliased images map to the handle their shared allocation is keyed on, which is freed along with the last of them
+    aliases: Mutex<HashMap<u64, u64>>,
+}
+
+impl MemoryUsage {
+    pub fn from_properties(properties: vk::MemoryPropertyFlags) -> MemoryUsage {
+        match properties.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            true => MemoryUsage::CpuToGpu,
+            false => MemoryUsage::GpuOnly,
+        }
+    }
+
+    pub fn host_visible(&self) -> bool {
+        *self != MemoryUsage::GpuOnly
+    }
+
+    fn allocation_ci(&self) -> vk_mem::AllocationCreateInfo {
+        let (required_flags, preferred_flags) = match self {
+            MemoryUsage::GpuOnly => (vk::MemoryPropertyFlags::DEVICE_LOCAL, vk::MemoryPropertyFlags::empty()),
+            MemoryUsage::CpuToGpu => (vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, vk::MemoryPropertyFlags::DEVICE_LOCAL),
+            MemoryUsage::GpuToCpu => (vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, vk::MemoryPropertyFlags::HOST_CACHED),
+        };
+
+        // Host visible memory stays mapped for the lifetime of the allocation
+        let flags = match self.host_visible() {
+            true => vk_mem::AllocationCreateFlags::MAPPED,
+            false => vk_mem::AllocationCreateFlags::empty(),
+        };
+
+        vk_mem::AllocationCreateInfo {
+            flags,
+            usage: vk_mem::MemoryUsage::Unknown,
+            required_flags,
+            preferred_flags,
+            ..Default::default()
+        }
+    }
+}
+
+impl Allocator {
+    // vk-mem copies the function pointers it needs out of the instance and device
+    pub unsafe fn new(c: &Core, device: &ash::Device, physical_device: vk::PhysicalDevice) -> Result<Allocator> {
+        let allocator_ci = vk_mem::AllocatorCreateInfo::new(&c.instance, device, physical_device);
+
+        Ok(Allocator {
+            allocator: vk_mem::Allocator::new(allocator_ci)?,
+            allocations: Mutex::new(HashMap::new()),
+            aliases: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub unsafe fn allocate_buffer(&self, device: &ash::Device, buffer: vk::Buffer, usage: MemoryUsage) -> Result<Allocation> {
+        let allocation = self.allocator.allocate_memory_for_buffer(buffer, &usage.allocation_ci())?;
+        let allocation_info = self.insert(buffer.as_raw(), allocation);
+
+        device.bind_buffer_memory(buffer, allocation_info.memory, allocation_info.offset)?;
+
+        Ok(allocation_info)
+    }
+
+    pub unsafe fn allocate_image(&self, device: &ash::Device, image: vk::Image, usage: MemoryUsage) -> Result<Allocation> {
+        let allocation = self.allocator.allocate_memory_for_image(image, &usage.allocation_ci())?;
+        let allocation_info = self.insert(image.as_raw(), allocation);
+
+        device.bind_image_memory(image, allocation_info.memory, allocation_info.offset)?;
+
+        Ok(allocation_info)
+    }
+
+    // Binds every image to the start of one allocation, large enough and aligned for the biggest of them
+    pub unsafe fn allocate_aliased_images(&self, device: &ash::Device, images: &Vec<vk::Image>, usage: MemoryUsage) -> Result<Allocation> {
+        let requirements = images.iter().map(|&image| device.get_image_memory_requirements(image)).collect::<Vec<vk::MemoryRequirements>>();
+
+        let memory_requirements = vk::MemoryRequirements {
+            size: requirements.iter().map(|r| r.size).max().unwrap_or(0),
+            alignment: requirements.iter().map(|r| r.alignment).max().unwrap_or(1),
+            memory_type_bits: requirements.iter().fold(u32::MAX, |bits, r| bits & r.memory_type_bits),
+        };
+
+        if memory_requirements.memory_type_bits == 0 {
+            return Err(Error::Unsupported("Aliased images have no memory type in common".to_string()));
+        }
+
+        let owner = images[0].as_raw();
+
+        let allocation = self.allocator.allocate_memory(&memory_requirements, &usage.allocation_ci())?;
+        let allocation_info = self.insert(owner, allocation);
+
+        let mut aliases = self.aliases.lock().unwrap();
+        for &image in images {
+            device.bind_image_memory(image, allocation_info.memory, allocation_info.offset)?;
+            aliases.insert(image.as_raw(), owner);
+        }
+
+        Ok(allocation_info)
+    }
+
+    pub unsafe fn free_buffer(&self, buffer: vk::Buffer) {
+        self.free(buffer.as_raw());
+    }
+
+    pub unsafe fn free_image(&self, image: vk::Image) {
+        self.free(image.as_raw());
+    }
+
+    pub fn stats(&self) -> Result<AllocationStats> {
+        let total = self.allocator.calculate_statistics()?.total.statistics;
+        let budgets = self.allocator.get_heap_budgets()?;
+
+        Ok(AllocationStats {
+            block_count: total.blockCount,
+            allocation_count: total.allocationCount,
+            block_bytes: total.blockBytes,
+            allocation_bytes: total.allocationBytes,
+            usage_bytes: budgets.iter().map(|budget| budget.usage).sum(),
+            budget_bytes: budgets.iter().map(|budget| budget.budget).sum(),
+        })
+    }
+
+    fn insert(&self, handle: u64, allocation: vk_mem::Allocation) -> Allocation {
+        let allocation_info = self.allocator.get_allocation_info(&allocation);
+
+        self.allocations.lock().unwrap().insert(handle, allocation);
+
+        Allocation {
+            memory: allocation_info.device_memory,
+            offset: allocation_info.offset,
+            p_mapped: match allocation_info.mapped_data.is_null() {
+                true => None,
+                false => Some(allocation_info.mapped_data),
+            },
+        }
+    }
+
+    // Handles with no allocation here, like images owned by the swapchain or ones already freed, are ignored
+    unsafe fn free(&self, handle: u64) {
+        let mut aliases = self.aliases.lock().unwrap();
+
+        let handle = match aliases.remove(&handle) {
+            Some(owner) if aliases.values().any(|&o| o == owner) => return,
+            Some(owner) => owner,
+            None => handle,
+        };
+
+        drop(aliases);
+
+        if let Some(mut allocation) = self.allocations.lock().unwrap().remove(&handle) {
+            self.allocator.free_memory(&mut allocation);
+        }
+    }
+}
@@ -2,8 +2,10 @@ use std::ffi::c_void;
 
 use ash::vk;
 
-use crate::{core::Core, commands::Commands};
+use crate::core::Core;
 use crate::device::Device;
+use crate::allocator::MemoryUsage;
+use crate::error::{Error, Result};
 
 #[derive(Copy, Clone)]
 pub struct BufferBuilder {
@@ -11,6 +13,7 @@ pub struct BufferBuilder {
     usage: Option<vk::BufferUsageFlags>,
     sharing_mode: Option<vk::SharingMode>,
     properties: Option<vk::MemoryPropertyFlags>,
+    memory_usage: Option<MemoryUsage>,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -29,6 +32,7 @@ impl BufferBuilder {
             usage: None,
             sharing_mode: None,
             properties: None,
+            memory_usage: None,
         }
     }
 
@@ -38,6 +42,7 @@ impl BufferBuilder {
             usage: self.usage,
             sharing_mode: self.sharing_mode,
             properties: self.properties,
+            memory_usage: self.memory_usage,
         }
     }
 
@@ -47,6 +52,7 @@ impl BufferBuilder {
             usage: Some(usage),
             sharing_mode: self.sharing_mode,
             properties: self.properties,
+            memory_usage: self.memory_usage,
         }
     }
 
@@ -56,6 +62,7 @@ impl BufferBuilder {
             usage: self.usage,
             sharing_mode: Some(sharing_mode),
             properties: self.properties,
+            memory_usage: self.memory_usage,
         }
     }
 
@@ -65,124 +72,133 @@ impl BufferBuilder {
             usage: self.usage,
             sharing_mode: self.sharing_mode,
             properties: Some(properties),
+            memory_usage: self.memory_usage,
         }
     }
 
-    pub unsafe fn build(&self, c: &Core, d: &Device) -> Buffer {
-        Buffer::new(c, d, None, self.size.expect("Error: BufferBuilder is missing size"), self.usage.expect("Error: BufferBuilder is missing usage"), self.sharing_mode.expect("Error: BufferBuilder is missing sharing_mode"), self.properties.expect("Error: BufferBuilder is missing property"))
+    pub fn memory_usage(&self, memory_usage: MemoryUsage) -> BufferBuilder {
+        BufferBuilder {
+            size: self.size,
+            usage: self.usage,
+            sharing_mode: self.sharing_mode,
+            properties: self.properties,
+            memory_usage: Some(memory_usage),
+        }
+    }
+
+    pub fn get_sharing_mode(&self) -> Option<vk::SharingMode> {
+        self.sharing_mode
+    }
+
+    // An explicit memory usage wins, otherwise it is inferred from the requested memory properties
+    fn get_memory_usage(&self) -> Result<MemoryUsage> {
+        match (self.memory_usage, self.properties) {
+            (Some(memory_usage), _) => Ok(memory_usage),
+            (None, Some(properties)) => Ok(MemoryUsage::from_properties(properties)),
+            (None, None) => Err(Error::IncompleteBuilder("BufferBuilder is missing memory usage".to_string())),
+        }
+    }
+
+    unsafe fn build_one(&self, c: &Core, d: &Device, data: Option<*const c_void>) -> Result<Buffer> {
+        let size = self.size.ok_or(Error::IncompleteBuilder("BufferBuilder is missing size".to_string()))?;
+        let usage = self.usage.ok_or(Error::IncompleteBuilder("BufferBuilder is missing usage".to_string()))?;
+        let sharing_mode = self.sharing_mode.ok_or(Error::IncompleteBuilder("BufferBuilder is missing sharing_mode".to_string()))?;
+
+        Buffer::new(c, d, data, size, usage, sharing_mode, self.get_memory_usage()?)
     }
 
-    pub unsafe fn build_many(&self, c: &Core, d: &Device, count: usize) -> Vec<Buffer> {
+    pub unsafe fn build(&self, c: &Core, d: &Device) -> Result<Buffer> {
+        self.build_one(c, d, None)
+    }
+
+    pub unsafe fn build_many(&self, c: &Core, d: &Device, count: usize) -> Result<Vec<Buffer>> {
         let mut buffers = Vec::<Buffer>::new();
         for _ in 0..count {
-            buffers.push(Buffer::new(c, d, None, self.size.expect("Error: BufferBuilder is missing size"), self.usage.expect("Error: BufferBuilder is missing usage"), self.sharing_mode.expect("Error: BufferBuilder is missing sharing_mode"), self.properties.expect("Error: BufferBuilder is missing property")));
+            buffers.push(self.build_one(c, d, None)?);
         }
 
-        buffers
+        Ok(buffers)
     }
 
-    pub unsafe fn build_with_data(&self, c: &Core, d: &Device, data: *const c_void) -> Buffer {
-        Buffer::new(c, d, Some(data), self.size.expect("Error: BufferBuilder is missing size"), self.usage.expect("Error: BufferBuilder is missing usage"), self.sharing_mode.expect("Error: BufferBuilder is missing sharing_mode"), self.properties.expect("Error: BufferBuilder is missing property"))
+    pub unsafe fn build_with_data(&self, c: &Core, d: &Device, data: *const c_void) -> Result<Buffer> {
+        self.build_one(c, d, Some(data))
     }
 
-    pub unsafe fn build_many_with_data(&self, c: &Core, d: &Device, data: Vec<*const c_void>, count: usize) -> Vec<Buffer> {
+    pub unsafe fn build_many_with_data(&self, c: &Core, d: &Device, data: Vec<*const c_void>, count: usize) -> Result<Vec<Buffer>> {
         let mut buffers = Vec::<Buffer>::new();
         for i in 0..count {
-            buffers.push(Buffer::new(c, d, Some(data[i]), self.size.expect("Error: BufferBuilder is missing size"), self.usage.expect("Error: BufferBuilder is missing usage"), self.sharing_mode.expect("Error: BufferBuilder is missing sharing_mode"), self.properties.expect("Error: BufferBuilder is missing property")));
+            buffers.push(self.build_one(c, d, Some(data[i]))?);
         }
 
-        buffers
+        Ok(buffers)
     }
 }
 impl Buffer {
-    pub unsafe fn new(c: &Core, d: &Device, data: Option<*const c_void>, size: usize, usage: vk::BufferUsageFlags, sm: vk::SharingMode, properties: vk::MemoryPropertyFlags) -> Buffer {
-        let host_visible = properties & vk::MemoryPropertyFlags::HOST_VISIBLE == vk::MemoryPropertyFlags::HOST_VISIBLE;
+    pub unsafe fn new(_c: &Core, d: &Device, data: Option<*const c_void>, size: usize, usage: vk::BufferUsageFlags, sm: vk::SharingMode, memory_usage: MemoryUsage) -> Result<Buffer> {
+        let host_visible = memory_usage.host_visible();
         
         let mut usage = usage;
 
-        let mut staging_buffer: Option<Buffer> = None;
-
+        // Device local buffers are written through the staging uploader and read back through a copy
         if !host_visible {
-            assert!(data.is_some(), "Buffer is not host visible but no data is provided");
-
-            staging_buffer = Some(BufferBuilder::new()
-                .size(size)
-                .usage(vk::BufferUsageFlags::TRANSFER_SRC)
-                .sharing_mode(sm)
-                .properties(vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT)
-                .build(c, d));
-
-            usage |= vk::BufferUsageFlags::TRANSFER_DST;
-
-            staging_buffer.unwrap().fill_from_ptr(d, data.unwrap(), size);
+            usage |= vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::TRANSFER_SRC;
         }
         
-        let buffer_ci = vk::BufferCreateInfo::builder()
+        // Concurrent sharing is only meaningful across more than one queue family, otherwise it falls back to exclusive
+        let queue_families = d.queue_families();
+        let sm = if queue_families.len() > 1 { sm } else { vk::SharingMode::EXCLUSIVE };
+
+        let mut buffer_ci = vk::BufferCreateInfo::default()
             .size(size as u64)
             .usage(usage)
             .sharing_mode(sm);
 
-        let buffer = d.device.create_buffer(&buffer_ci, None).unwrap();
-
-        let memory_requirements = d.device.get_buffer_memory_requirements(buffer);
-        let memory_type_index = d.get_memory_type(c, properties, memory_requirements);
-
-        let memory_alloc_i = vk::MemoryAllocateInfo::builder()
-            .allocation_size(size as u64)
-            .memory_type_index(memory_type_index as u32);
+        if sm == vk::SharingMode::CONCURRENT {
+            buffer_ci = buffer_ci.queue_family_indices(&queue_families);
+        }
 
-        let memory = d.device.allocate_memory(&memory_alloc_i, None).unwrap();
-        d.device.bind_buffer_memory(buffer, memory, 0).unwrap();
+        let buffer = d.device.create_buffer(&buffer_ci, None)?;
 
-        let p_dst = match host_visible {
-            true => Some(d.device.map_memory(memory, 0, size as u64, vk::MemoryMapFlags::empty()).unwrap()),
-            false => None,
-        };
+        let allocation = d.allocator.allocate_buffer(&d.device, buffer, memory_usage)?;
 
         let buffer = Buffer {
             buffer,
-            memory,
+            memory: allocation.memory,
             size: size as u64,
             host_visible,
-            p_dst,
+            p_dst: allocation.p_mapped,
         };
 
-        if data.is_some() && host_visible {
-            buffer.fill_from_ptr(d, data.unwrap(), size);
+        if let Some(p) = data {
+            buffer.fill_from_ptr(d, p, size)?;
         }
 
-        if !host_visible {
-            let transfer_commands = Commands::new(d, d.queue_main.1, 1, true);
-
-            transfer_commands.record_one(d, 0, |b| {
-                let buffer_copy = vk::BufferCopy::builder()
-                    .size(size as u64)
-                    .build();
-
-                d.device.cmd_copy_buffer(b, staging_buffer.unwrap().buffer, buffer.buffer, &[buffer_copy])
-            });
-
-            let submit_i = vk::SubmitInfo::builder()
-                .command_buffers(&[transfer_commands.buffers[0]])
-                .build();
+        Ok(buffer)
+    }
 
-            d.device.queue_submit(d.queue_main.0, &[submit_i], vk::Fence::null()).unwrap();
-            d.device.queue_wait_idle(d.queue_main.0).unwrap();
-        }
+    pub unsafe fn fill<T>(&self, d: &Device, data: &Vec<T>) -> Result<()> {
+        let size = data.len() * std::mem::size_of::<T>();
 
-        buffer
+        self.fill_from_ptr(d, data.as_ptr() as *const c_void, size)
     }
 
-    pub unsafe fn fill<T>(&self, d: &Device, data: &Vec<T>) {
-        assert!(self.host_visible, "Error: Buffer is not host visible");
+    pub unsafe fn fill_from_ptr(&self, d: &Device, p: *const c_void, s: usize) -> Result<()> {
+        if s as u64 > self.size {
+            return Err(Error::Unsupported(format!("Can't fill a buffer of {} bytes with {} bytes", self.size, s)));
+        }
 
-        let size = data.len() * std::mem::size_of::<T>();
-        std::ptr::copy(data.as_ptr() as *const c_void, self.p_dst.unwrap(), size);
+        match self.host_visible {
+            true => {
+                std::ptr::copy(p, self.p_dst.unwrap(), s);
+                Ok(())
+            },
+            false => d.uploader.lock().unwrap().upload_buffer(d, self.buffer, 0, p, s),
+        }
     }
 
-    pub unsafe fn fill_from_ptr(&self, d: &Device, p: *const c_void, s: usize) {
-        assert!(self.host_visible, "Error: Buffer is not host visible");
-        
-        std::ptr::copy(p, self.p_dst.unwrap(), s);
+    // Freeing the allocation also unmaps it, so host visible buffers need no extra handling
+    pub unsafe fn destroy(&self, d: &Device) {
+        d.device.destroy_buffer(self.buffer, None);
+        d.allocator.free_buffer(self.buffer);
     }
 }
\ No newline at end of file
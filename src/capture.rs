@@ -0,0 +1,222 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use ash::vk;
+
+use crate::error::{Error, Result};
+
+// Captures the named image after each of the next `remaining` frames, numbering files from `next`
+#[derive(Clone)]
+pub struct CaptureSequence {
+    pub name: String,
+    pub path: PathBuf,
+    pub next: usize,
+    pub remaining: usize,
+}
+
+impl CaptureSequence {
+    pub fn new(name: &str, path: &str, count: usize) -> CaptureSequence {
+        CaptureSequence {
+            name: name.to_string(),
+            path: PathBuf::from(path),
+            next: 0,
+            remaining: count,
+        }
+    }
+
+    // frame.png becomes frame_0000.png, frame_0001.png, ...
+    pub fn next_path(&mut self) -> PathBuf {
+        let stem = self.path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        let extension = self.path.extension().map(|extension| extension.to_string_lossy().to_string()).unwrap_or_default();
+
+        let path = self.path.with_file_name(format!("{}_{:04}.{}", stem, self.next, extension));
+
+        self.next += 1;
+        self.remaining -= 1;
+
+        path
+    }
+}
+
+// Writes tightly packed texels (as returned by read_image) to disk. The file type is picked from the extension:
+// .png for 8 bit colour formats and .pfm for float formats (8 bit formats are also accepted and written normalised)
+pub fn write_image<P: AsRef<Path>>(path: P, width: u32, height: u32, format: vk::Format, data: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("png") => write_png(path, width, height, format, data),
+        Some("pfm") => write_pfm(path, width, height, format, data),
+        _ => Err(Error::Unsupported(format!("Unsupported capture file type {:?}, expected .png or .pfm", path))),
+    }
+}
+
+fn write_png(path: &Path, width: u32, height: u32, format: vk::Format, data: &[u8]) -> Result<()> {
+    let (color_type, pixels) = png_pixels(format, data)?;
+
+    // sRGB formats already hold encoded values, so the file is tagged rather than converted
+    let srgb = matches!(format, vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_SRGB | vk::Format::R8_SRGB);
+
+    encode_png(path, width, height, color_type, srgb, &pixels)
+}
+
+// Converts texels to the channel order PNG expects, swizzling BGRA formats
+pub fn png_pixels(format: vk::Format, data: &[u8]) -> Result<(png::ColorType, Vec<u8>)> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => Ok((png::ColorType::Rgba, data.to_vec())),
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => Ok((png::ColorType::Rgba, data.chunks_exact(4).flat_map(|bgra| [bgra[2], bgra[1], bgra[0], bgra[3]]).collect())),
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB => Ok((png::ColorType::Grayscale, data.to_vec())),
+        _ => Err(Error::UnsupportedFormat(format)),
+    }
+}
+
+pub fn encode_png(path: &Path, width: u32, height: u32, color_type: png::ColorType, srgb: bool, pixels: &[u8]) -> Result<()> {
+    let file = File::create(path).map_err(|e| io_error(path, e))?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(color_type);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    if srgb {
+        encoder.set_srgb(png::SrgbRenderingIntent::Perceptual);
+    }
+
+    let mut writer = encoder.write_header().map_err(|e| io_error(path, e.into()))?;
+    writer.write_image_data(pixels).map_err(|e| io_error(path, e.into()))?;
+
+    Ok(())
+}
+
+// PFM stores either one or three float channels, bottom row first. Alpha is dropped
+fn write_pfm(path: &Path, width: u32, height: u32, format: vk::Format, data: &[u8]) -> Result<()> {
+    let (channels, texels) = match format {
+        vk::Format::R32G32B32A32_SFLOAT => (3, data.chunks_exact(16).flat_map(|t| [f32_at(t, 0), f32_at(t, 4), f32_at(t, 8)]).collect::<Vec<f32>>()),
+        vk::Format::R32G32B32_SFLOAT => (3, data.chunks_exact(12).flat_map(|t| [f32_at(t, 0), f32_at(t, 4), f32_at(t, 8)]).collect()),
+        vk::Format::R16G16B16A16_SFLOAT => (3, data.chunks_exact(8).flat_map(|t| [f16_at(t, 0), f16_at(t, 2), f16_at(t, 4)]).collect()),
+        vk::Format::R32_SFLOAT | vk::Format::D32_SFLOAT => (1, data.chunks_exact(4).map(|t| f32_at(t, 0)).collect()),
+        vk::Format::R16_SFLOAT => (1, data.chunks_exact(2).map(|t| f16_at(t, 0)).collect()),
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => (3, data.chunks_exact(4).flat_map(|t| [unorm8(t[0]), unorm8(t[1]), unorm8(t[2])]).collect()),
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => (3, data.chunks_exact(4).flat_map(|t| [unorm8(t[2]), unorm8(t[1]), unorm8(t[0])]).collect()),
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB => (1, data.iter().map(|&t| unorm8(t)).collect()),
+        _ => return Err(Error::UnsupportedFormat(format)),
+    };
+
+    let file = File::create(path).map_err(|e| io_error(path, e))?;
+    let mut writer = BufWriter::new(file);
+
+    // A negative scale marks the data as little endian
+    let header = if channels == 3 { "PF" } else { "Pf" };
+    write!(writer, "{}\n{} {}\n-1.0\n", header, width, height).map_err(|e| io_error(path, e))?;
+
+    let row_length = width as usize * channels;
+    for row in texels.chunks_exact(row_length).rev() {
+        for value in row {
+            writer.write_all(&value.to_le_bytes()).map_err(|e| io_error(path, e))?;
+        }
+    }
+
+    writer.flush().map_err(|e| io_error(path, e))
+}
+
+fn io_error(path: &Path, error: std::io::Error) -> Error {
+    Error::Io(path.to_string_lossy().to_string(), error)
+}
+
+fn f32_at(texel: &[u8], offset: usize) -> f32 {
+    f32::from_le_bytes([texel[offset], texel[offset + 1], texel[offset + 2], texel[offset + 3]])
+}
+
+fn f16_at(texel: &[u8], offset: usize) -> f32 {
+    let bits = u16::from_le_bytes([texel[offset], texel[offset + 1]]) as u32;
+
+    let sign = (bits & 0x8000) << 16;
+    let exponent = (bits >> 10) & 0x1f;
+    let mantissa = bits & 0x3ff;
+
+    let value = match exponent {
+        0 => return (mantissa as f32 / 1024.0) * 2f32.powi(-14) * if sign != 0 { -1.0 } else { 1.0 },
+        0x1f => sign | 0x7f800000 | (mantissa << 13),
+        _ => sign | ((exponent + 112) << 23) | (mantissa << 13),
+    };
+
+    f32::from_bits(value)
+}
+
+fn unorm8(value: u8) -> f32 {
+    value as f32 / 255.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn f16_bytes(bits: u16) -> [u8; 2] {
+        bits.to_le_bytes()
+    }
+
+    #[test]
+    fn bgra_is_swizzled_to_rgba() {
+        let (color_type, pixels) = png_pixels(vk::Format::B8G8R8A8_UNORM, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+
+        assert_eq!(color_type, png::ColorType::Rgba);
+        assert_eq!(pixels, vec![3, 2, 1, 4, 7, 6, 5, 8]);
+    }
+
+    #[test]
+    fn rgba_and_grayscale_are_copied() {
+        assert_eq!(png_pixels(vk::Format::R8G8B8A8_SRGB, &[1, 2, 3, 4]).unwrap(), (png::ColorType::Rgba, vec![1, 2, 3, 4]));
+        assert_eq!(png_pixels(vk::Format::R8_UNORM, &[9, 10]).unwrap(), (png::ColorType::Grayscale, vec![9, 10]));
+    }
+
+    #[test]
+    fn float_formats_have_no_png_pixels() {
+        assert!(matches!(png_pixels(vk::Format::R32G32B32A32_SFLOAT, &[0; 16]), Err(Error::UnsupportedFormat(vk::Format::R32G32B32A32_SFLOAT))));
+    }
+
+    #[test]
+    fn f16_normal_values() {
+        assert_eq!(f16_at(&f16_bytes(0x3c00), 0), 1.0);
+        assert_eq!(f16_at(&f16_bytes(0xc000), 0), -2.0);
+        assert_eq!(f16_at(&f16_bytes(0x3555), 0), 0.33325195);
+        assert_eq!(f16_at(&f16_bytes(0x7bff), 0), 65504.0);
+    }
+
+    #[test]
+    fn f16_zero_and_subnormals() {
+        assert_eq!(f16_at(&f16_bytes(0x0000), 0), 0.0);
+        assert_eq!(f16_at(&f16_bytes(0x0001), 0), 2f32.powi(-24));
+        assert_eq!(f16_at(&f16_bytes(0x8001), 0), -2f32.powi(-24));
+        assert_eq!(f16_at(&f16_bytes(0x03ff), 0), 1023.0 * 2f32.powi(-24));
+    }
+
+    #[test]
+    fn f16_infinity_and_nan() {
+        assert_eq!(f16_at(&f16_bytes(0x7c00), 0), f32::INFINITY);
+        assert_eq!(f16_at(&f16_bytes(0xfc00), 0), f32::NEG_INFINITY);
+        assert!(f16_at(&f16_bytes(0x7e00), 0).is_nan());
+    }
+
+    #[test]
+    fn f16_reads_at_offset() {
+        assert_eq!(f16_at(&[0xff, 0xff, 0x00, 0x3c], 2), 1.0);
+    }
+
+    #[test]
+    fn sequence_numbers_files_next_to_path() {
+        let mut sequence = CaptureSequence::new("swapchain_image", "out/frame.png", 3);
+
+        assert_eq!(sequence.next_path(), PathBuf::from("out/frame_0000.png"));
+        assert_eq!(sequence.next_path(), PathBuf::from("out/frame_0001.png"));
+        assert_eq!(sequence.remaining, 1);
+
+        assert_eq!(sequence.next_path(), PathBuf::from("out/frame_0002.png"));
+        assert_eq!(sequence.remaining, 0);
+    }
+
+    #[test]
+    fn sequence_keeps_other_extensions() {
+        let mut sequence = CaptureSequence::new("hdr", "frame.pfm", 1);
+
+        assert_eq!(sequence.next_path(), PathBuf::from("frame_0000.pfm"));
+    }
+}
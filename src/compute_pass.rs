@@ -1,7 +1,10 @@
+use std::collections::HashMap;
+
 use ash::vk;
 
-use crate::{core::Core, descriptors::CreationReference, renderer_data::RendererData, layer::Pass};
+use crate::{core::Core, descriptors::CreationReference, image::Image, renderer_data::RendererData, layer::{Pass, ResourceAccess, ResourceUsage}};
 use crate::device::Device;
+use crate::error::{Error, Result};
 use crate::descriptors::{Descriptors, DescriptorsBuilder};
 use crate::compute_pipeline::ComputePipeline;
 use crate::push_constant::{PushConstant, PushConstantBuilder};
@@ -10,6 +13,7 @@ pub struct ComputePassDispatchInfo {
     pub x: u32,
     pub y: u32,
     pub z: u32,
+    pub image: Option<String>,
 }
 
 pub struct ComputePassBuilder<'a> {
@@ -17,6 +21,8 @@ pub struct ComputePassBuilder<'a> {
     cs: Option<&'a str>,
     push_constant_builder: Option<PushConstantBuilder>,
     descriptors_builder: Option<DescriptorsBuilder>,
+    accesses: Vec<ResourceAccess>,
+    error: Option<Error>,
 }
 
 pub struct ComputePass {
@@ -24,23 +30,29 @@ pub struct ComputePass {
     pub descriptors: Option<Descriptors>,
     pub pipeline: ComputePipeline,
     pub dispatch_info: ComputePassDispatchInfo,
+    pub accesses: Vec<ResourceAccess>,
 }
 
-impl Pass for ComputePass {}
+impl Pass for ComputePass {
+    fn accesses(&self) -> &Vec<ResourceAccess> {
+        &self.accesses
+    }
+}
 
 impl ComputePassDispatchInfo {
     pub fn new(x: u32, y: u32, z: u32) -> ComputePassDispatchInfo {
-        ComputePassDispatchInfo { x, y, z }
+        ComputePassDispatchInfo { x, y, z, image: None }
     }
 
-    pub fn for_image(name: &str, data: &RendererData) -> ComputePassDispatchInfo {
-        let image = data.get_images(name)[0];
+    pub fn for_image(name: &str, data: &RendererData) -> Result<ComputePassDispatchInfo> {
+        let image = data.get_images(name)?[0];
 
-        ComputePassDispatchInfo {
+        Ok(ComputePassDispatchInfo {
             x: image.width / 16 + 1,
             y: image.height / 16 + 1,
             z: 1,
-        }
+            image: Some(name.to_string()),
+        })
     }
 }
 
@@ -51,6 +63,8 @@ impl <'a> ComputePassBuilder<'a> {
             cs: None,
             push_constant_builder: None,
             descriptors_builder: None,
+            accesses: Vec::new(),
+            error: None,
         }
     }
 
@@ -66,8 +80,12 @@ impl <'a> ComputePassBuilder<'a> {
         self
     }
 
-    pub fn push_constant<T>(mut self) -> ComputePassBuilder<'a> {
-        self.push_constant_builder = Some(PushConstantBuilder::new().stage(vk::ShaderStageFlags::COMPUTE).size(std::mem::size_of::<T>()));
+    pub fn push_constant<T>(self) -> ComputePassBuilder<'a> {
+        self.push_constant_size(std::mem::size_of::<T>())
+    }
+
+    pub fn push_constant_size(mut self, size: usize) -> ComputePassBuilder<'a> {
+        self.push_constant_builder = Some(PushConstantBuilder::new().stage(vk::ShaderStageFlags::COMPUTE).size(size));
 
         self
     }
@@ -78,34 +96,44 @@ impl <'a> ComputePassBuilder<'a> {
         self
     }
 
+    // A missing resource is held onto and returned from build, so the builder can still be chained
     pub fn descriptors(mut self, create_refs: Vec<CreationReference>, data: &RendererData) -> ComputePassBuilder<'a> {
-        let mut descriptors_builder = DescriptorsBuilder::new()
-            .stage(vk::ShaderStageFlags::COMPUTE)
-            .count(data.count);
-
-        for create_ref in create_refs {
-            match create_ref {
-                CreationReference::Uniform(name) => { descriptors_builder = descriptors_builder.add_uniform_simple(data.get_buffers(&name)); },
-                CreationReference::Storage(name) => { descriptors_builder = descriptors_builder.add_storage_simple(data.get_buffers(&name)); },
-                CreationReference::Image(name) => { descriptors_builder = descriptors_builder.add_image_simple(data.get_images(&name)); },
-                CreationReference::Sampler(name) => { descriptors_builder = descriptors_builder.add_sampler_simple(data.get_images(&name)); },
-            }
+        match DescriptorsBuilder::from_references(create_refs, data, vk::ShaderStageFlags::COMPUTE) {
+            Ok(descriptors_builder) => self.descriptors_builder = Some(descriptors_builder),
+            Err(e) => self.error = Some(e),
         }
 
-        self.descriptors_builder = Some(descriptors_builder);
-        
         self
     }
 
-    pub unsafe fn build(self, c: &Core, d: &Device) -> ComputePass {
-        ComputePass::new(c, d, self.descriptors_builder, self.push_constant_builder, self.cs.expect("Error: Compute pass builder has no compute shader"), self.dispatch_info.expect("Error: Compute pass builder has no dispatch info"))
+    pub fn reads(mut self, name: &str, usage: ResourceUsage) -> ComputePassBuilder<'a> {
+        self.accesses.push(ResourceAccess::new(name, usage, false));
+
+        self
+    }
+
+    pub fn writes(mut self, name: &str, usage: ResourceUsage) -> ComputePassBuilder<'a> {
+        self.accesses.push(ResourceAccess::new(name, usage, true));
+
+        self
+    }
+
+    pub unsafe fn build(self, c: &Core, d: &Device) -> Result<ComputePass> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        let cs = self.cs.ok_or(Error::IncompleteBuilder("Compute pass builder has no compute shader".to_string()))?;
+        let dispatch_info = self.dispatch_info.ok_or(Error::IncompleteBuilder("Compute pass builder has no dispatch info".to_string()))?;
+
+        ComputePass::new(c, d, self.descriptors_builder, self.push_constant_builder, cs, dispatch_info, self.accesses)
     }
 }
 
 impl ComputePass {
-    pub unsafe fn new(c: &Core, d: &Device, descriptors_builder: Option<DescriptorsBuilder>, push_constant_builder: Option<PushConstantBuilder>, cs: &str, dispatch_info: ComputePassDispatchInfo) -> ComputePass {
+    pub unsafe fn new(c: &Core, d: &Device, descriptors_builder: Option<DescriptorsBuilder>, push_constant_builder: Option<PushConstantBuilder>, cs: &str, dispatch_info: ComputePassDispatchInfo, accesses: Vec<ResourceAccess>) -> Result<ComputePass> {
         let descriptors = match descriptors_builder {
-            Some(de_b) => Some(de_b.build(c, d)),
+            Some(de_b) => Some(de_b.build(c, d)?),
             None => None
         };
 
@@ -115,17 +143,39 @@ impl ComputePass {
         };
 
         let push_constant = match push_constant_builder {
-            Some(builder) => Some(builder.build()),
+            Some(builder) => Some(builder.build()?),
             None => None
         };
         
-        let pipeline = ComputePipeline::new(c, d, descriptor_set_layout, push_constant.as_ref(), cs);
+        let pipeline = ComputePipeline::new(c, d, descriptor_set_layout, push_constant.as_ref(), cs)?;
 
-        ComputePass {
+        Ok(ComputePass {
             push_constant,
             descriptors,
             pipeline,
             dispatch_info,
+            accesses,
+        })
+    }
+
+    // Dispatches sized from an image are recomputed, as the image may have been rebuilt at a new size
+    pub unsafe fn replace_images(&mut self, d: &Device, data: &RendererData, replaced: &HashMap<vk::Image, Image>) -> Result<()> {
+        if let Some(descriptors) = &mut self.descriptors {
+            descriptors.replace_images(d, replaced);
         }
+
+        if let Some(name) = &self.dispatch_info.image {
+            self.dispatch_info = ComputePassDispatchInfo::for_image(name, data)?;
+        }
+
+        Ok(())
+    }
+
+    pub unsafe fn destroy(&self, d: &Device) {
+        if let Some(descriptors) = &self.descriptors {
+            descriptors.destroy(d);
+        }
+
+        self.pipeline.destroy(d);
     }
 }
\ No newline at end of file
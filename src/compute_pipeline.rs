@@ -4,6 +4,7 @@ use ash::vk;
 
 use crate::core::Core;
 use crate::device::Device;
+use crate::error::Result;
 use crate::push_constant::PushConstant;
 use crate::shader::Shader;
 
@@ -13,24 +14,23 @@ pub struct ComputePipeline {
 }
 
 impl ComputePipeline {
-    pub unsafe fn new(c: &Core, d: &Device, descriptor_set_layout: Option<vk::DescriptorSetLayout>, push_constant: Option<&PushConstant>, cs: &str) -> ComputePipeline {
-        let comp_shader = Shader::new(d, cs, vk::ShaderStageFlags::COMPUTE);
+    pub unsafe fn new(c: &Core, d: &Device, descriptor_set_layout: Option<vk::DescriptorSetLayout>, push_constant: Option<&PushConstant>, cs: &str) -> Result<ComputePipeline> {
+        let comp_shader = Shader::new(d, cs, vk::ShaderStageFlags::COMPUTE)?;
 
         let shader_entry_name = CString::new("main").unwrap();
 
-        let shader_stage_ci = vk::PipelineShaderStageCreateInfo::builder()
+        let shader_stage_ci = vk::PipelineShaderStageCreateInfo::default()
             .module(comp_shader.module)
             .name(&shader_entry_name)
-            .stage(vk::ShaderStageFlags::COMPUTE)
-            .build();
+            .stage(vk::ShaderStageFlags::COMPUTE);
 
         let push_constant_ranges = match push_constant {
             Some(pc) => {
-                vec![vk::PushConstantRange::builder()
+                vec![vk::PushConstantRange::default()
                     .size(pc.size as u32)
                     .offset(0)
                     .stage_flags(pc.stage)
-                    .build()]
+                    ]
             },
             None => vec![]
         };
@@ -42,23 +42,28 @@ impl ComputePipeline {
             None => vec![]
         };
 
-        let pipeline_layout_ci = vk::PipelineLayoutCreateInfo::builder()
+        let pipeline_layout_ci = vk::PipelineLayoutCreateInfo::default()
             .set_layouts(&descriptor_set_layouts)
-            .push_constant_ranges(&push_constant_ranges)
-            .build();
+            .push_constant_ranges(&push_constant_ranges);
 
-        let pipeline_layout = d.device.create_pipeline_layout(&pipeline_layout_ci, None).unwrap();
+        let pipeline_layout = d.device.create_pipeline_layout(&pipeline_layout_ci, None)?;
 
-        let pipeline_ci = vk::ComputePipelineCreateInfo::builder()
+        let pipeline_ci = vk::ComputePipelineCreateInfo::default()
             .stage(shader_stage_ci)
-            .layout(pipeline_layout)
-            .build();
+            .layout(pipeline_layout);
 
-        let pipeline = d.device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_ci], None).unwrap()[0];
+        let pipeline = d.device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_ci], None).map_err(|(_, e)| e)?[0];
 
-        ComputePipeline {
+        comp_shader.destroy(d);
+
+        Ok(ComputePipeline {
             pipeline,
             pipeline_layout,
-        }
+        })
+    }
+
+    pub unsafe fn destroy(&self, d: &Device) {
+        d.device.destroy_pipeline(self.pipeline, None);
+        d.device.destroy_pipeline_layout(self.pipeline_layout, None);
     }
 }
\ No newline at end of file
@@ -1,13 +1,15 @@
-use ash::{vk, extensions::ext::DebugUtils};
+use ash::{vk, ext::debug_utils};
 use raw_window_handle::RawDisplayHandle;
 
+use crate::error::Result;
+
 use std::borrow::Cow;
 use std::ffi::{CStr, CString};
 
 unsafe extern "system" fn debug_callback_fn(
     msg_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     msg_type: vk::DebugUtilsMessageTypeFlagsEXT,
-    p_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    p_data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
     _u_data: *mut std::os::raw::c_void) -> vk::Bool32 {
     let data = *p_data;
     let msg_id_num = data.message_id_number;
@@ -30,8 +32,7 @@ unsafe extern "system" fn debug_callback_fn(
         msg_type,
         msg_id_name,
         &msg_id_num.to_string(),
-        msg,
-    );
+        msg,);
 
     vk::FALSE
 }
@@ -39,13 +40,33 @@ unsafe extern "system" fn debug_callback_fn(
 pub struct Core {
     pub entry: ash::Entry,
     pub instance: ash::Instance,
+    pub api_version: u32,
+    // Whether vkGetPhysicalDeviceProperties2 can be called, through Vulkan 1.1 or VK_KHR_get_physical_device_properties2
+    pub properties2: bool,
 
-    debug_utils_init: DebugUtils,
+    debug_utils_init: debug_utils::Instance,
     debug_callback: vk::DebugUtilsMessengerEXT,
 }
 
 impl Core {
-    pub unsafe fn new(validation_enabled: bool, display: RawDisplayHandle) -> Core {
+    pub unsafe fn new(validation_enabled: bool, display: RawDisplayHandle, api_version: u32) -> Result<Core> {
+        let mut extension_names = crate::surface::required_extensions(display)?;
+
+        // Any colour space other than plain sRGB needs this, so it's enabled wherever the loader has it
+        let colorspace_name = ash::ext::swapchain_colorspace::NAME;
+        if Core::instance_extension_available(colorspace_name)? {
+            extension_names.push(colorspace_name.as_ptr());
+        }
+
+        Core::with_extensions(validation_enabled, extension_names, api_version)
+    }
+
+    pub unsafe fn new_headless(validation_enabled: bool, api_version: u32) -> Result<Core> {
+        Core::with_extensions(validation_enabled, vec![], api_version)
+    }
+
+    // Devices are only given features from versions up to api_version, so it is kept at 1.0 unless something needs more
+    unsafe fn with_extensions(validation_enabled: bool, extension_names: Vec<*const i8>, api_version: u32) -> Result<Core> {
         //let entry = ash::Entry::new().unwrap();
         let entry = ash::Entry::linked();
 
@@ -54,36 +75,62 @@ impl Core {
         let layer_names = if validation_enabled { vec![CString::new("VK_LAYER_KHRONOS_validation").unwrap()] } else { vec![] } ;
         let layer_names_raw: Vec<*const i8> = layer_names.iter().map(|layer| layer.as_ptr()).collect();
 
-        let extension_names = ash_window::enumerate_required_extensions(display).unwrap();
-        let mut extension_names_raw: Vec<*const i8> = extension_names.iter().map(|extension| *extension).collect();
+        let mut extension_names_raw = extension_names;
         
-        extension_names_raw.push(DebugUtils::name().as_ptr());
+        extension_names_raw.push(debug_utils::NAME.as_ptr());
+
+        // Extended physical device properties, such as the driver's name, are core from Vulkan 1.1
+        let properties2_name = ash::khr::get_physical_device_properties2::NAME;
+        let properties2 = api_version >= vk::API_VERSION_1_1 || Core::instance_extension_available(properties2_name)?;
 
-        let app_i = vk::ApplicationInfo::builder()
-            .api_version(vk::make_version(1, 0, 0))
+        if api_version < vk::API_VERSION_1_1 && properties2 {
+            extension_names_raw.push(properties2_name.as_ptr());
+        }
+
+        let app_i = vk::ApplicationInfo::default()
+            .api_version(api_version)
             .application_name(&name);
 
-        let instance_ci = vk::InstanceCreateInfo::builder()
+        let instance_ci = vk::InstanceCreateInfo::default()
             .application_info(&app_i)
             .enabled_layer_names(&layer_names_raw)
             .enabled_extension_names(&extension_names_raw);
 
-        let instance = entry.create_instance(&instance_ci, None).unwrap();
+        let instance = entry.create_instance(&instance_ci, None)?;
 
-        let debug_ci = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+        let debug_ci = vk::DebugUtilsMessengerCreateInfoEXT::default()
             .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING)
             .message_type(vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION)
             .pfn_user_callback(Some(debug_callback_fn));
 
-        let debug_utils_init = DebugUtils::new(&entry, &instance);
-        let debug_callback = debug_utils_init.create_debug_utils_messenger(&debug_ci, None).unwrap();
+        let debug_utils_init = debug_utils::Instance::new(&entry, &instance);
+        let debug_callback = debug_utils_init.create_debug_utils_messenger(&debug_ci, None)?;
 
-        Core {
+        Ok(Core {
             entry,
             instance,
+            api_version,
+            properties2,
 
             debug_utils_init,
             debug_callback,
+        })
+    }
+
+    unsafe fn instance_extension_available(name: &CStr) -> Result<bool> {
+        Ok(ash::Entry::linked().enumerate_instance_extension_properties(None)?.iter().any(|extension| CStr::from_ptr(extension.extension_name.as_ptr()) == name))
+    }
+
+    // Reads properties chained onto `properties`, which the physical device has to support through its version or an extension
+    pub unsafe fn get_physical_device_properties2(&self, physical_device: vk::PhysicalDevice, properties: &mut vk::PhysicalDeviceProperties2) {
+        match self.api_version >= vk::API_VERSION_1_1 {
+            true => self.instance.get_physical_device_properties2(physical_device, properties),
+            false => ash::khr::get_physical_device_properties2::Instance::new(&self.entry, &self.instance).get_physical_device_properties2(physical_device, properties),
         }
     }
+
+    pub unsafe fn destroy(&self) {
+        self.debug_utils_init.destroy_debug_utils_messenger(self.debug_callback, None);
+        self.instance.destroy_instance(None);
+    }
 }
\ No newline at end of file
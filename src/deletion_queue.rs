@@ -0,0 +1,48 @@
+use crate::buffer::Buffer;
+use crate::device::Device;
+use crate::image::Image;
+
+#[derive(Copy, Clone)]
+pub enum DeferredResource {
+    Buffer(Buffer),
+    Image(Image),
+}
+
+// Resources that may still be in use by frames in flight, bucketed by the frame they were retired in.
+// A bucket is only flushed once that frame's fence has been waited on again
+pub struct DeletionQueue {
+    pub pending: Vec<Vec<DeferredResource>>,
+}
+
+impl DeferredResource {
+    pub unsafe fn destroy(&self, d: &Device) {
+        match self {
+            DeferredResource::Buffer(buffer) => buffer.destroy(d),
+            DeferredResource::Image(image) => image.destroy(d),
+        }
+    }
+}
+
+impl DeletionQueue {
+    pub fn new(count: usize) -> DeletionQueue {
+        DeletionQueue {
+            pending: vec![Vec::new(); count],
+        }
+    }
+
+    pub fn push(&mut self, frame: usize, resource: DeferredResource) {
+        self.pending[frame].push(resource);
+    }
+
+    pub unsafe fn flush(&mut self, d: &Device, frame: usize) {
+        for resource in self.pending[frame].drain(..) {
+            resource.destroy(d);
+        }
+    }
+
+    pub unsafe fn flush_all(&mut self, d: &Device) {
+        for frame in 0..self.pending.len() {
+            self.flush(d, frame);
+        }
+    }
+}
@@ -3,10 +3,13 @@ pub mod uniform_descriptor;
 pub mod image_descriptor;
 pub mod sampler_descriptor;
 
+use std::collections::HashMap;
+
 use ash::vk;
 
-use crate::{core::Core, buffer::Buffer, image::Image};
+use crate::{core::Core, buffer::Buffer, image::Image, renderer_data::RendererData};
 use crate::device::Device;
+use crate::error::{Error, Result};
 use crate::descriptors::uniform_descriptor::UniformDescriptorBuilder;
 use crate::descriptors::storage_descriptor::StorageDescriptorBuilder;
 use crate::descriptors::image_descriptor::ImageDescriptorBuilder;
@@ -157,66 +160,83 @@ impl  DescriptorsBuilder {
         self.add_sampler_builder(SamplerDescriptorBuilder::new().images(images))
     }
 
-    pub unsafe fn build(self, c: &Core, d: &Device) -> Descriptors {
+    // Builds one descriptor per frame from resources registered by name
+    pub fn from_references(create_refs: Vec<CreationReference>, data: &RendererData, stage: vk::ShaderStageFlags) -> Result<DescriptorsBuilder> {
+        let mut descriptors_builder = DescriptorsBuilder::new()
+            .stage(stage)
+            .count(data.count);
+
+        for create_ref in create_refs {
+            match create_ref {
+                CreationReference::Uniform(name) => { descriptors_builder = descriptors_builder.add_uniform_simple(data.get_buffers(&name)?); },
+                CreationReference::Storage(name) => { descriptors_builder = descriptors_builder.add_storage_simple(data.get_buffers(&name)?); },
+                CreationReference::Image(name) => { descriptors_builder = descriptors_builder.add_image_simple(data.get_images(&name)?); },
+                CreationReference::Sampler(name) => { descriptors_builder = descriptors_builder.add_sampler_simple(data.get_images(&name)?); },
+            }
+        }
+
+        Ok(descriptors_builder)
+    }
+
+    pub unsafe fn build(self, c: &Core, d: &Device) -> Result<Descriptors> {
         Descriptors::new(c, d, self)
     }
 }
 
 impl Descriptors {
-    pub unsafe fn new(c: &Core, d: &Device, builder: DescriptorsBuilder) -> Descriptors {
+    pub unsafe fn new(c: &Core, d: &Device, builder: DescriptorsBuilder) -> Result<Descriptors> {
+        let stage = builder.stage.ok_or(Error::IncompleteBuilder("Descriptors builder has no stage flags".to_string()))?;
+        let count = builder.count.ok_or(Error::IncompleteBuilder("Descriptors builder has no count".to_string()))?;
+
         let mut layout_bindings = Vec::<vk::DescriptorSetLayoutBinding>::new();
 
         for descriptor_builder in &builder.uniform_builders {
             layout_bindings.push(
-                vk::DescriptorSetLayoutBinding::builder()
+                vk::DescriptorSetLayoutBinding::default()
                     .binding(descriptor_builder.0)
                     .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
                     .descriptor_count(1)
-                    .stage_flags(builder.stage.expect("Error: descriptors builder has no stage flags"))
-                    .build()
-            )
+                    .stage_flags(stage)
+)
         }
 
         for descriptor_builder in &builder.storage_builders {
             layout_bindings.push(
-                vk::DescriptorSetLayoutBinding::builder()
+                vk::DescriptorSetLayoutBinding::default()
                     .binding(descriptor_builder.0)
                     .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
                     .descriptor_count(1)
-                    .stage_flags(builder.stage.expect("Error: descriptors builder has no stage flags"))
-                    .build()
-            )
+                    .stage_flags(stage)
+)
         }
 
         for descriptor_builder in &builder.image_builders {
             layout_bindings.push(
-                vk::DescriptorSetLayoutBinding::builder()
+                vk::DescriptorSetLayoutBinding::default()
                     .binding(descriptor_builder.0)
                     .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
                     .descriptor_count(1)
-                    .stage_flags(builder.stage.expect("Error: descriptors builder has no stage flags"))
-                    .build()
-            )
+                    .stage_flags(stage)
+)
         }
 
         for descriptor_builder in &builder.sampler_builders {
             layout_bindings.push(
-                vk::DescriptorSetLayoutBinding::builder()
+                vk::DescriptorSetLayoutBinding::default()
                     .binding(descriptor_builder.0)
                     .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
                     .descriptor_count(1)
-                    .stage_flags(builder.stage.expect("Error: descriptors builder has no stage flags"))
-                    .build()
-            )
+                    .stage_flags(stage)
+)
         }
         
-        let set_layout_ci = vk::DescriptorSetLayoutCreateInfo::builder()
+        let set_layout_ci = vk::DescriptorSetLayoutCreateInfo::default()
             .bindings(&layout_bindings);
 
-        let set_layout = d.device.create_descriptor_set_layout(&set_layout_ci, None).unwrap();
+        let set_layout = d.device.create_descriptor_set_layout(&set_layout_ci, None)?;
         let mut set_layouts = Vec::<vk::DescriptorSetLayout>::new();
 
-        for _ in 0..builder.count.expect("Error: descriptors builder has no count") {
+        for _ in 0..count {
             set_layouts.push(set_layout);
         }
 
@@ -225,51 +245,47 @@ impl Descriptors {
 
         if builder.uniform_builders.len() > 0 {
             pool_sizes.push(
-                vk::DescriptorPoolSize::builder()
+                vk::DescriptorPoolSize::default()
                     .ty(vk::DescriptorType::UNIFORM_BUFFER)
-                    .descriptor_count((builder.uniform_builders.len() * builder.count.expect("Error: descriptors builder has no count") * temp_constant) as u32)
-                    .build()
-            );
+                    .descriptor_count((builder.uniform_builders.len() * count * temp_constant) as u32)
+);
         }
 
         if builder.storage_builders.len() > 0 {
             pool_sizes.push(
-                vk::DescriptorPoolSize::builder()
+                vk::DescriptorPoolSize::default()
                     .ty(vk::DescriptorType::STORAGE_BUFFER)
-                    .descriptor_count((builder.storage_builders.len() * builder.count.expect("Error: descriptors builder has no count") * temp_constant) as u32)
-                    .build()
-            );
+                    .descriptor_count((builder.storage_builders.len() * count * temp_constant) as u32)
+);
         }
 
         if builder.image_builders.len() > 0 {
             pool_sizes.push(
-                vk::DescriptorPoolSize::builder()
+                vk::DescriptorPoolSize::default()
                     .ty(vk::DescriptorType::STORAGE_IMAGE)
-                    .descriptor_count((builder.image_builders.len() * builder.count.expect("Error: descriptors builder has no count") * temp_constant) as u32)
-                    .build()
-            );
+                    .descriptor_count((builder.image_builders.len() * count * temp_constant) as u32)
+);
         }
 
         if builder.sampler_builders.len() > 0 {
             pool_sizes.push(
-                vk::DescriptorPoolSize::builder()
+                vk::DescriptorPoolSize::default()
                     .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                    .descriptor_count((builder.sampler_builders.len() * builder.count.expect("Error: descriptors builder has no count") * temp_constant) as u32)
-                    .build()
-            );
+                    .descriptor_count((builder.sampler_builders.len() * count * temp_constant) as u32)
+);
         }
         
-        let pool_ci = vk::DescriptorPoolCreateInfo::builder()
+        let pool_ci = vk::DescriptorPoolCreateInfo::default()
             .pool_sizes(&pool_sizes)
             .max_sets(256);
 
-        let pool = d.device.create_descriptor_pool(&pool_ci, None).unwrap();
+        let pool = d.device.create_descriptor_pool(&pool_ci, None)?;
 
-        let set_ai = vk::DescriptorSetAllocateInfo::builder()
+        let set_ai = vk::DescriptorSetAllocateInfo::default()
             .descriptor_pool(pool)
             .set_layouts(&set_layouts);
 
-        let sets = d.device.allocate_descriptor_sets(&set_ai).unwrap();
+        let sets = d.device.allocate_descriptor_sets(&set_ai)?;
 
         let uniforms = Vec::<uniform_descriptor::UniformDescriptor>::new();
         let ssbos = Vec::<storage_descriptor::StorageDescriptor>::new();
@@ -291,25 +307,55 @@ impl Descriptors {
         };
 
         for descriptor_builder in &builder.uniform_builders {
-            descriptors.uniforms.push(descriptor_builder.1.build(c, d, descriptor_builder.0, &descriptors.sets));
+            descriptors.uniforms.push(descriptor_builder.1.build(c, d, descriptor_builder.0, &descriptors.sets)?);
         }
 
         for descriptor_builder in &builder.storage_builders {
-            descriptors.ssbos.push(descriptor_builder.1.build(c, d, descriptor_builder.0, &descriptors.sets));
+            descriptors.ssbos.push(descriptor_builder.1.build(c, d, descriptor_builder.0, &descriptors.sets)?);
         }
 
         for descriptor_builder in &builder.image_builders {
-            descriptors.images.push(descriptor_builder.1.build(c, d, descriptor_builder.0, &descriptors.sets));
+            descriptors.images.push(descriptor_builder.1.build(c, d, descriptor_builder.0, &descriptors.sets)?);
         }
 
         for descriptor_builder in &builder.sampler_builders {
-            descriptors.samplers.push(descriptor_builder.1.build(c, d, descriptor_builder.0, &descriptors.sets));
+            descriptors.samplers.push(descriptor_builder.1.build(c, d, descriptor_builder.0, &descriptors.sets)?);
         }
 
-        descriptors
+        Ok(descriptors)
     }
 
     pub unsafe fn bind(&self, d: &Device, b: &vk::CommandBuffer, bp: vk::PipelineBindPoint, pl: &vk::PipelineLayout, i: usize) {
         d.device.cmd_bind_descriptor_sets(*b, bp, *pl, 0, &[self.sets[i]], &[]);
     }
+
+    // Every image bound to a storage image or sampler binding, for every frame in flight
+    pub fn bound_images(&self) -> Vec<vk::Image> {
+        let images = self.images.iter().flat_map(|descriptor| &descriptor.data);
+        let samplers = self.samplers.iter().flat_map(|descriptor| &descriptor.data);
+
+        images.chain(samplers).map(|data| data.image).collect()
+    }
+
+    pub unsafe fn replace_images(&mut self, d: &Device, replaced: &HashMap<vk::Image, Image>) {
+        for image in &mut self.images {
+            image.replace_images(d, replaced, &self.sets);
+        }
+
+        for sampler in &mut self.samplers {
+            sampler.replace_images(d, replaced, &self.sets);
+        }
+    }
+
+    // Freeing the pool frees the sets allocated from it
+    pub unsafe fn destroy(&self, d: &Device) {
+        for sampler_descriptor in &self.samplers {
+            for sampler in &sampler_descriptor.samplers {
+                sampler.destroy(d);
+            }
+        }
+
+        d.device.destroy_descriptor_pool(self.pool, None);
+        d.device.destroy_descriptor_set_layout(self.set_layout, None);
+    }
 }
\ No newline at end of file
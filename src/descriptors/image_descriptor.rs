@@ -1,15 +1,19 @@
+use std::collections::HashMap;
+
 use ash::vk;
 
-use crate::{core::Core, layer::LayerExecution};
+use crate::core::Core;
+use crate::layer::ResourceUsage;
 use crate::device::Device;
+use crate::error::{Error, Result};
 use crate::image::{Image, ImageData};
-use crate::commands::Commands;
 
 pub struct ImageDescriptorBuilder {
     image_datas: Option<Vec<ImageData>>,
 }
 
 pub struct ImageDescriptor {
+    pub binding: u32,
     pub data: Vec<ImageData>,
 }
 
@@ -27,64 +31,58 @@ impl  ImageDescriptorBuilder {
         }
     }
 
-    pub unsafe fn build(&self, c: &Core, d: &Device, binding: u32, sets: &Vec<vk::DescriptorSet>) -> ImageDescriptor {
-        ImageDescriptor::new(c, d, binding, self.image_datas.as_ref().expect("Error: Image descriptor builder has no images"), sets)
+    pub unsafe fn build(&self, c: &Core, d: &Device, binding: u32, sets: &Vec<vk::DescriptorSet>) -> Result<ImageDescriptor> {
+        let image_datas = self.image_datas.as_ref().ok_or(Error::IncompleteBuilder("Image descriptor builder has no images".to_string()))?;
+
+        Ok(ImageDescriptor::new(c, d, binding, image_datas, sets))
     }
 }
 
 impl ImageDescriptor {
     unsafe fn new(c: &Core, d: &Device, binding: u32, images: &Vec<ImageData>, sets: &Vec<vk::DescriptorSet>) -> ImageDescriptor {
-        let mut write_sets = Vec::<vk::WriteDescriptorSet>::new();
-        let layout = images[0].layout;
-
-        for i in 0..images.len() {
-            let image_is = [vk::DescriptorImageInfo::builder()
-                .image_view(images[i as usize].view)
-                .image_layout(layout)
-                .build()];
+        ImageDescriptor::write(d, binding, images, sets);
 
-            let write_set = vk::WriteDescriptorSet::builder()
-                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-                .dst_binding(binding)
-                .dst_set(sets[i as usize])
-                .image_info(&image_is)
-                .build();
-
-            write_sets.push(write_set);
+        ImageDescriptor {
+            binding,
+            data: images.clone(),
         }
+    }
 
-        d.device.update_descriptor_sets(&write_sets, &[]);
-
-        let layout_transition_buffer = Commands::new(d, d.get_queue(LayerExecution::Main).1, images.len(), false);
+    // Points the descriptor at the new images for any that were recreated, e.g. after a resize
+    pub unsafe fn replace_images(&mut self, d: &Device, replaced: &HashMap<vk::Image, Image>, sets: &Vec<vk::DescriptorSet>) {
+        let mut changed = false;
 
-        layout_transition_buffer.record_all(d, |i, b| {
-            let subresource_range = vk::ImageSubresourceRange::builder()
-                .aspect_mask(vk::ImageAspectFlags::COLOR)
-                .layer_count(1)
-                .level_count(1)
-                .build();
+        for image_data in &mut self.data {
+            if let Some(image) = replaced.get(&image_data.image) {
+                *image_data = ImageData::from_image(image);
+                changed = true;
+            }
+        }
 
-            let barrier = vk::ImageMemoryBarrier::builder()
-                .image(images[i].image)
-                .old_layout(vk::ImageLayout::UNDEFINED)
-                .new_layout(layout)
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .subresource_range(subresource_range)
-                .build();
+        if changed {
+            ImageDescriptor::write(d, self.binding, &self.data, sets);
+        }
+    }
 
-            d.device.cmd_pipeline_barrier(b, vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::TRANSFER, vk::DependencyFlags::empty(), &[], &[], &[barrier]);
-        });
+    unsafe fn write(d: &Device, binding: u32, images: &Vec<ImageData>, sets: &Vec<vk::DescriptorSet>) {
+        // Storage images are always accessed in GENERAL, the layer transitions them before each use
+        let layout = ResourceUsage::Storage.image_layout(false, images[0].layout);
 
-        let submit_is = [vk::SubmitInfo::builder()
-            .command_buffers(&layout_transition_buffer.buffers)
-            .build()];
+        // Write sets borrow their infos, so the infos are all built before any write set
+        let image_is = (0..images.len()).map(|i| {
+            vk::DescriptorImageInfo::default()
+                .image_view(images[i].view)
+                .image_layout(layout)
+        }).collect::<Vec<_>>();
 
-        d.device.queue_submit(d.get_queue(LayerExecution::Main).0, &submit_is, vk::Fence::null()).unwrap();
-        d.device.queue_wait_idle(d.get_queue(LayerExecution::Main).0).unwrap();
+        let write_sets = (0..images.len()).map(|i| {
+            vk::WriteDescriptorSet::default()
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .dst_binding(binding)
+                .dst_set(sets[i])
+                .image_info(std::slice::from_ref(&image_is[i]))
+        }).collect::<Vec<_>>();
 
-        ImageDescriptor {
-            data: images.clone(),
-        }
+        d.device.update_descriptor_sets(&write_sets, &[]);
     }
 }
\ No newline at end of file
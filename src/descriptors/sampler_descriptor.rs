@@ -1,11 +1,16 @@
+use std::collections::HashMap;
+
 use ash::vk;
 
 use crate::core::Core;
 use crate::device::Device;
+use crate::error::{Error, Result};
 use crate::image::{Image, ImageData};
+use crate::layer::ResourceUsage;
 use crate::sampler::Sampler;
 
 pub struct SamplerDescriptor {
+    pub binding: u32,
     pub samplers: Vec<Sampler>,
     pub data: Vec<ImageData>,
 }
@@ -22,53 +27,75 @@ impl SamplerDescriptorBuilder {
     }
 
     pub fn images(&self, images: &Vec<Image>) -> SamplerDescriptorBuilder {
-        let image_datas = images.iter().map(|image| { ImageData { image: image.image, view: image.view, layout: image.layout} }).collect();
+        let image_datas = images.iter().map(|image| { SamplerDescriptor::image_data(image) }).collect();
         SamplerDescriptorBuilder {
             image_datas: Some(image_datas),
         }
     }
 
-    pub unsafe fn build(&self, c: &Core, d: &Device, binding: u32, sets: &Vec<vk::DescriptorSet>) -> SamplerDescriptor {
-        if self.image_datas.is_none() {
-            panic!("Error: Sampler descriptor builder has no images");
-        }
-        
+    pub unsafe fn build(&self, c: &Core, d: &Device, binding: u32, sets: &Vec<vk::DescriptorSet>) -> Result<SamplerDescriptor> {
+        let image_datas = self.image_datas.as_ref().ok_or(Error::IncompleteBuilder("Sampler descriptor builder has no images".to_string()))?;
+
         let mut samplers = Vec::<Sampler>::new();
 
-        for image_data in self.image_datas.as_ref().unwrap() {
-            samplers.push(Sampler::new(c, d, image_data));
+        for image_data in image_datas {
+            samplers.push(Sampler::new(c, d, image_data)?);
         }
 
-        SamplerDescriptor::new(c, d, binding, self.image_datas.as_ref().unwrap(), &samplers, sets)
+        Ok(SamplerDescriptor::new(c, d, binding, image_datas, &samplers, sets))
     }
 }
 
 impl SamplerDescriptor {
     pub unsafe fn new(c: &Core, d: &Device, binding: u32, images: &Vec<ImageData>, samplers: &Vec<Sampler>, sets: &Vec<vk::DescriptorSet>) -> SamplerDescriptor {
-        let mut write_sets = Vec::<vk::WriteDescriptorSet>::new();
+        SamplerDescriptor::write(d, binding, samplers, sets);
 
-        for i in 0..samplers.len() {
-            let image_is = [vk::DescriptorImageInfo::builder()
-                .sampler(samplers[i as usize].sampler)
-                .image_view(samplers[i as usize].view)
-                .image_layout(samplers[i as usize].layout)
-                .build()];
+        SamplerDescriptor {
+            binding,
+            samplers: samplers.clone(),
+            data: images.clone(),
+        }
+    }
 
-            let write_set = vk::WriteDescriptorSet::builder()
-                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                .dst_binding(binding)
-                .dst_set(sets[i as usize])
-                .image_info(&image_is)
-                .build();
+    // Keeps the existing samplers but points them at the new views for any images that were recreated
+    pub unsafe fn replace_images(&mut self, d: &Device, replaced: &HashMap<vk::Image, Image>, sets: &Vec<vk::DescriptorSet>) {
+        let mut changed = false;
 
-            write_sets.push(write_set);
+        for (image_data, sampler) in self.data.iter_mut().zip(self.samplers.iter_mut()) {
+            if let Some(image) = replaced.get(&image_data.image) {
+                *image_data = SamplerDescriptor::image_data(image);
+                sampler.view = image_data.view;
+                sampler.layout = image_data.layout;
+                changed = true;
+            }
         }
 
-        d.device.update_descriptor_sets(&write_sets, &[]);
-
-        SamplerDescriptor {
-            samplers: samplers.clone(),
-            data: images.clone(),
+        if changed {
+            SamplerDescriptor::write(d, self.binding, &self.samplers, sets);
         }
     }
+
+    fn image_data(image: &Image) -> ImageData {
+        ImageData { image: image.image, view: image.view, layout: ResourceUsage::Sampled.image_layout(false, image.layout) }
+    }
+
+    unsafe fn write(d: &Device, binding: u32, samplers: &Vec<Sampler>, sets: &Vec<vk::DescriptorSet>) {
+        // Write sets borrow their infos, so the infos are all built before any write set
+        let image_is = (0..samplers.len()).map(|i| {
+            vk::DescriptorImageInfo::default()
+                .sampler(samplers[i].sampler)
+                .image_view(samplers[i].view)
+                .image_layout(samplers[i].layout)
+        }).collect::<Vec<_>>();
+
+        let write_sets = (0..samplers.len()).map(|i| {
+            vk::WriteDescriptorSet::default()
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .dst_binding(binding)
+                .dst_set(sets[i])
+                .image_info(std::slice::from_ref(&image_is[i]))
+        }).collect::<Vec<_>>();
+
+        d.device.update_descriptor_sets(&write_sets, &[]);
+    }
 }
\ No newline at end of file
@@ -2,6 +2,7 @@ use ash::vk;
 
 use crate::core::Core;
 use crate::device::Device;
+use crate::error::{Error, Result};
 use crate::buffer::Buffer;
 
 #[derive(Copy, Clone)]
@@ -30,30 +31,29 @@ impl UniformDescriptorBuilder {
         }
     }
 
-    pub unsafe fn build(&self, c: &Core, d: &Device, binding: u32, sets: &Vec<vk::DescriptorSet>) -> UniformDescriptor {
-        UniformDescriptor::new(d, binding, self.buffer_datas.as_ref().expect("Error: Uniform descriptor builder has no buffers"), sets)
+    pub unsafe fn build(&self, c: &Core, d: &Device, binding: u32, sets: &Vec<vk::DescriptorSet>) -> Result<UniformDescriptor> {
+        let buffer_datas = self.buffer_datas.as_ref().ok_or(Error::IncompleteBuilder("Uniform descriptor builder has no buffers".to_string()))?;
+
+        Ok(UniformDescriptor::new(d, binding, buffer_datas, sets))
     }
 }
 
 impl UniformDescriptor {
     unsafe fn new(d: &Device, binding: u32, buffers: &Vec<BufferData>, sets: &Vec<vk::DescriptorSet>) -> UniformDescriptor {
-        let mut write_sets = Vec::<vk::WriteDescriptorSet>::new();
-
-        for i in 0..buffers.len() {
-            let buffer_is = [vk::DescriptorBufferInfo::builder()
+        // Write sets borrow their infos, so the infos are all built before any write set
+        let buffer_is = (0..buffers.len()).map(|i| {
+            vk::DescriptorBufferInfo::default()
                 .buffer(buffers[i].buffer)
                 .range(buffers[i].size as u64)
-                .build()];
+        }).collect::<Vec<_>>();
 
-            let write_set = vk::WriteDescriptorSet::builder()
+        let write_sets = (0..buffers.len()).map(|i| {
+            vk::WriteDescriptorSet::default()
                 .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
                 .dst_binding(binding)
                 .dst_set(sets[i])
-                .buffer_info(&buffer_is)
-                .build();
-
-            write_sets.push(write_set);
-        }
+                .buffer_info(std::slice::from_ref(&buffer_is[i]))
+        }).collect::<Vec<_>>();
 
         d.device.update_descriptor_sets(&write_sets, &[]);
 
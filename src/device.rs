@@ -1,13 +1,19 @@
+use std::mem::ManuallyDrop;
+use std::sync::Mutex;
+
 use ash::vk;
 use raw_window_handle::{RawWindowHandle, RawDisplayHandle};
 
-use crate::{core::Core, layer::LayerExecution};
+use crate::{adapter::{self, Adapter, AdapterPolicy}, allocator::{Allocator, AllocationStats}, core::Core, layer::LayerExecution, surface, swapchain::SwapchainConfig, upload::Uploader};
+use crate::error::{Error, Result};
 
 pub struct Device {
     pub device: ash::Device,
+    pub allocator: ManuallyDrop<Allocator>,
+    pub uploader: Mutex<Uploader>,
 
-    pub surface_init: ash::extensions::khr::Surface,
-    pub surface: vk::SurfaceKHR,
+    pub surface_init: ash::khr::surface::Instance,
+    pub surface: Option<vk::SurfaceKHR>,
     pub surface_format: vk::SurfaceFormatKHR,
     pub surface_capabilities: vk::SurfaceCapabilitiesKHR,
     pub surface_extent: vk::Extent2D,
@@ -15,49 +21,184 @@ pub struct Device {
     pub extension_names: Vec<*const i8>,
 
     pub physical_device: vk::PhysicalDevice,
+    pub adapter: Adapter,
 
     pub queue_present: (vk::Queue, u32),
     pub queue_main: (vk::Queue, u32),
     pub queue_async: (vk::Queue, u32),
+    pub queue_transfer: (vk::Queue, u32),
+
+    pub timeline_semaphores: bool,
 }
 
 impl Device {
-    pub unsafe fn new(c: &Core, window: RawWindowHandle, display: RawDisplayHandle) -> Device {
-        let surface_init = ash::extensions::khr::Surface::new(&c.entry, &c.instance);
-        let surface = ash_window::create_surface(&c.entry, &c.instance, display, window, None).unwrap();
+    pub unsafe fn new(c: &Core, window: RawWindowHandle, display: RawDisplayHandle, timeline_semaphores: bool, swapchain_config: &SwapchainConfig, policy: &AdapterPolicy) -> Result<Device> {
+        let surface_init = ash::khr::surface::Instance::new(&c.entry, &c.instance);
+        let surface = surface::create_surface(&c.entry, &c.instance, display, window)?;
+
+        let (adapter, queue_index_present, queue_index_main, queue_index_async, queue_index_transfer) = Device::select_physical_device(c, Some((&surface_init, surface)), timeline_semaphores, policy)?;
+        let physical_device = adapter.physical_device;
+
+        let extension_names = vec![ash::khr::swapchain::NAME.as_ptr()];
+
+        let device = Device::create_logical_device(c, physical_device, &[queue_index_present, queue_index_main, queue_index_async, queue_index_transfer], &extension_names, timeline_semaphores, &policy.required_features)?;
+
+        let allocator = Allocator::new(c, &device, physical_device)?;
+
+        let queue_present = (device.get_device_queue(queue_index_present, 0), queue_index_present);
+        let queue_main = (device.get_device_queue(queue_index_main, 0), queue_index_main);
+        let queue_async = (device.get_device_queue(queue_index_async, 0), queue_index_async);
+        let queue_transfer = (device.get_device_queue(queue_index_transfer, 0), queue_index_transfer);
+
+        let surface_format = swapchain_config.choose_format(&surface_init.get_physical_device_surface_formats(physical_device, surface)?)?;
+
+        let surface_capabilities = surface_init.get_physical_device_surface_capabilities(physical_device, surface)?;
+
+        let surface_extent = if surface_capabilities.current_extent.width == std::u32::MAX {
+            vk::Extent2D {
+                width: 1280,
+                height: 720,
+            }
+        } else {
+            surface_capabilities.current_extent
+        };
+
+        Ok(Device {
+            device,
+            allocator: ManuallyDrop::new(allocator),
+            uploader: Mutex::new(Uploader::new()),
+
+            surface_init,
+            surface: Some(surface),
+            surface_format,
+            surface_capabilities,
+            surface_extent,
+
+            extension_names,
+
+            physical_device,
+            adapter,
+
+            queue_present,
+            queue_main,
+            queue_async,
+            queue_transfer,
+
+            timeline_semaphores,
+        })
+    }
+
+    pub unsafe fn new_headless(c: &Core, extent: vk::Extent2D, format: vk::Format, timeline_semaphores: bool, policy: &AdapterPolicy) -> Result<Device> {
+        let surface_init = ash::khr::surface::Instance::new(&c.entry, &c.instance);
+
+        let (adapter, _, queue_index_main, queue_index_async, queue_index_transfer) = Device::select_physical_device(c, None, timeline_semaphores, policy)?;
+        let physical_device = adapter.physical_device;
+
+        let extension_names = vec![];
+
+        let device = Device::create_logical_device(c, physical_device, &[queue_index_main, queue_index_async, queue_index_transfer], &extension_names, timeline_semaphores, &policy.required_features)?;
+
+        let allocator = Allocator::new(c, &device, physical_device)?;
+
+        let queue_main = (device.get_device_queue(queue_index_main, 0), queue_index_main);
+        let queue_async = (device.get_device_queue(queue_index_async, 0), queue_index_async);
+        let queue_transfer = (device.get_device_queue(queue_index_transfer, 0), queue_index_transfer);
+
+        let surface_format = vk::SurfaceFormatKHR {
+            format,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        };
+
+        Ok(Device {
+            device,
+            allocator: ManuallyDrop::new(allocator),
+            uploader: Mutex::new(Uploader::new()),
+
+            surface_init,
+            surface: None,
+            surface_format,
+            surface_capabilities: vk::SurfaceCapabilitiesKHR::default(),
+            surface_extent: extent,
+
+            extension_names,
+
+            physical_device,
+            adapter,
+
+            queue_present: queue_main,
+            queue_main,
+            queue_async,
+            queue_transfer,
+
+            timeline_semaphores,
+        })
+    }
+
+    // Adapters without the queues the renderer needs are left out before the policy chooses. Without a surface, the
+    // present queue is the main queue
+    unsafe fn select_physical_device(c: &Core, surface: Option<(&ash::khr::surface::Instance, vk::SurfaceKHR)>, timeline_semaphores: bool, policy: &AdapterPolicy) -> Result<(Adapter, u32, u32, u32, u32)> {
+        let suitable = Adapter::enumerate(c)?.into_iter().filter_map(|adapter| {
+            let pd = adapter.physical_device;
 
-        let available_physical_devices = c.instance.enumerate_physical_devices().unwrap();
+            if timeline_semaphores && !Device::supports_timeline_semaphores(c, pd) {
+                return None;
+            }
 
-        let (physical_device, queue_index_present, queue_index_main, queue_index_async) = available_physical_devices.iter().filter_map(|&pd| {
             let queue_family_properties = c.instance.get_physical_device_queue_family_properties(pd);
 
-            let queue_index_properties_present = queue_family_properties.iter().enumerate().filter(|(i, ref q)| {
-                surface_init.get_physical_device_surface_support(pd, *i as u32, surface).unwrap()
-            }).next();
-            let queue_index_properties_main = queue_family_properties.iter().enumerate().filter(|(i, ref q)| {
+            let queue_index_properties_main = queue_family_properties.iter().enumerate().find(|(_, q)| {
                 q.queue_flags.contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
-            }).next();
-            let queue_index_properties_async = queue_family_properties.iter().enumerate().filter(|(i, ref q)| {
+            });
+            // A compute family without graphics usually maps to separate hardware queues, so async layers can overlap the main ones
+            let queue_index_properties_async = queue_family_properties.iter().enumerate().find(|(_, q)| {
+                q.queue_flags.contains(vk::QueueFlags::COMPUTE) && !q.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+            }).or_else(|| queue_family_properties.iter().enumerate().find(|(_, q)| {
                 q.queue_flags.contains(vk::QueueFlags::COMPUTE)
-            }).next();
-
-            if queue_index_properties_present.is_some() && queue_index_properties_main.is_some() && queue_index_properties_async.is_some() {
-                Some((pd, queue_index_properties_present.unwrap().0 as u32, queue_index_properties_main.unwrap().0 as u32, queue_index_properties_async.unwrap().0 as u32))
-            } else {
-                None
+            }));
+            // Transfer-only families are the dedicated copy engines. Without one, transfers share the async family, as
+            // compute families can always transfer
+            let queue_index_properties_transfer = queue_family_properties.iter().enumerate().find(|(_, q)| {
+                q.queue_flags.contains(vk::QueueFlags::TRANSFER) && !q.queue_flags.intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+            }).or(queue_index_properties_async);
+            let queue_index_properties_present = match surface {
+                Some((surface_init, surface)) => queue_family_properties.iter().enumerate().find(|(i, _)| {
+                    surface_init.get_physical_device_surface_support(pd, *i as u32, surface).unwrap_or(false)
+                }),
+                None => queue_index_properties_main,
+            };
+
+            match (queue_index_properties_present, queue_index_properties_main, queue_index_properties_async, queue_index_properties_transfer) {
+                (Some((present, _)), Some((main, _)), Some((async_compute, _)), Some((transfer, _))) => Some((adapter, (present as u32, main as u32, async_compute as u32, transfer as u32))),
+                _ => None,
             }
-        }).next().expect("Suitable physical device not found");
+        }).collect::<Vec<_>>();
 
-        let extension_names = vec![ash::extensions::khr::Swapchain::name().as_ptr()];
+        let (adapter, (present, main, async_compute, transfer)) = policy.choose(&suitable)?.clone();
+
+        Ok((adapter, present, main, async_compute, transfer))
+    }
+
+    // Needs both the instance and the device to be at least Vulkan 1.2
+    unsafe fn supports_timeline_semaphores(c: &Core, physical_device: vk::PhysicalDevice) -> bool {
+        if c.api_version < vk::API_VERSION_1_2 || c.instance.get_physical_device_properties(physical_device).api_version < vk::API_VERSION_1_2 {
+            return false;
+        }
+
+        let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::default();
+        let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut vulkan_12_features);
+        c.instance.get_physical_device_features2(physical_device, &mut features);
+
+        vulkan_12_features.timeline_semaphore == vk::TRUE
+    }
 
-        let physical_device_features = vk::PhysicalDeviceFeatures {
+    unsafe fn create_logical_device(c: &Core, physical_device: vk::PhysicalDevice, queue_indices: &[u32], extension_names: &[*const i8], timeline_semaphores: bool, required_features: &vk::PhysicalDeviceFeatures) -> Result<ash::Device> {
+        let physical_device_features = adapter::merge_features(&vk::PhysicalDeviceFeatures {
             shader_clip_distance: 1,
             ..Default::default()
-        };
+        }, required_features);
 
         let priorities = [1.0];
 
-        let queue_indices = vec![queue_index_present, queue_index_main, queue_index_async];
         let mut unique_indices = Vec::<u32>::new();
         let mut queue_cis = Vec::<vk::DeviceQueueCreateInfo>::new();
 
@@ -68,76 +209,102 @@ impl Device {
         });
 
         unique_indices.iter().for_each(|&i| {
-            let queue_ci = vk::DeviceQueueCreateInfo::builder()
+            let queue_ci = vk::DeviceQueueCreateInfo::default()
                 .queue_family_index(i)
-                .queue_priorities(&priorities)
-                .build();
+                .queue_priorities(&priorities);
 
             queue_cis.push(queue_ci);
         });
 
-        let device_ci = vk::DeviceCreateInfo::builder()
+        let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::default()
+            .timeline_semaphore(true);
+
+        let mut device_ci = vk::DeviceCreateInfo::default()
             .queue_create_infos(&queue_cis)
-            .enabled_extension_names(&extension_names)
+            .enabled_extension_names(extension_names)
             .enabled_features(&physical_device_features);
 
-        let device = c.instance.create_device(physical_device, &device_ci, None).unwrap();
+        if timeline_semaphores {
+            device_ci = device_ci.push_next(&mut vulkan_12_features);
+        }
 
-        let queue_present = (device.get_device_queue(queue_index_present, 0), queue_index_present);
-        let queue_main = (device.get_device_queue(queue_index_main, 0), queue_index_main);
-        let queue_async = (device.get_device_queue(queue_index_async, 0), queue_index_async);
+        Ok(c.instance.create_device(physical_device, &device_ci, None)?)
+    }
 
-        let available_surface_formats = surface_init.get_physical_device_surface_formats(physical_device, surface).unwrap();
-        let surface_format = available_surface_formats.iter().filter(|format| {
-            format.format == vk::Format::B8G8R8A8_SRGB && format.color_space == vk::ColorSpaceKHR::EXTENDED_SRGB_NONLINEAR_EXT
-        }).next().unwrap_or(&available_surface_formats[0]);
+    // Surfaces that leave the extent up to the swapchain take the preferred extent, clamped to what the surface supports
+    pub unsafe fn update_surface_extent(&mut self, preferred_extent: vk::Extent2D) -> Result<()> {
+        let surface = match self.surface {
+            Some(surface) => surface,
+            None => {
+                self.surface_extent = preferred_extent;
+                return Ok(());
+            }
+        };
 
-        let surface_capabilities = surface_init.get_physical_device_surface_capabilities(physical_device, surface).unwrap();
+        self.surface_capabilities = self.surface_init.get_physical_device_surface_capabilities(self.physical_device, surface)?;
 
-        let surface_extent = if surface_capabilities.current_extent.width == std::u32::MAX {
+        self.surface_extent = if self.surface_capabilities.current_extent.width == u32::MAX {
             vk::Extent2D {
-                width: 1280,
-                height: 720,
+                width: preferred_extent.width.clamp(self.surface_capabilities.min_image_extent.width, self.surface_capabilities.max_image_extent.width),
+                height: preferred_extent.height.clamp(self.surface_capabilities.min_image_extent.height, self.surface_capabilities.max_image_extent.height),
             }
         } else {
-            surface_capabilities.current_extent
+            self.surface_capabilities.current_extent
         };
 
-        Device {
-            device,
-
-            surface_init,
-            surface,
-            surface_format: *surface_format,
-            surface_capabilities,
-            surface_extent,
-
-            extension_names,
-
-            physical_device,
-
-            queue_present,
-            queue_main,
-            queue_async,
-        }
+        Ok(())
     }
 
     pub fn get_queue(&self, exec: LayerExecution) -> (vk::Queue, u32) {
         match exec {
             LayerExecution::Main => self.queue_main,
             LayerExecution::Async => self.queue_async,
+            LayerExecution::Transfer => self.queue_transfer,
+        }
+    }
+
+    pub unsafe fn queue_flags(&self, c: &Core, exec: LayerExecution) -> vk::QueueFlags {
+        c.instance.get_physical_device_queue_family_properties(self.physical_device)[self.get_queue(exec).1 as usize].queue_flags
+    }
+
+    // The distinct families layers submit to, which is what concurrently shared resources are created for
+    pub fn queue_families(&self) -> Vec<u32> {
+        let mut families = vec![self.queue_main.1];
+        for family in [self.queue_async.1, self.queue_transfer.1] {
+            if !families.contains(&family) {
+                families.push(family);
+            }
         }
+
+        families
     }
 
-    pub unsafe fn get_memory_type(&self, c: &Core, property_flags: vk::MemoryPropertyFlags, memory_requirements: vk::MemoryRequirements) -> usize {
+    pub unsafe fn get_memory_type(&self, c: &Core, property_flags: vk::MemoryPropertyFlags, memory_requirements: vk::MemoryRequirements) -> Result<usize> {
         let memory_type_index = c.instance.get_physical_device_memory_properties(self.physical_device).memory_types.iter().enumerate().find_map(|(i, m)| {
             if (memory_requirements.memory_type_bits & (1 << i)) != 0 && (m.property_flags & property_flags == property_flags) {
                 Some(i)
             } else {
                 None
             }
-        }).unwrap();
+        }).ok_or_else(|| Error::Unsupported(format!("No memory type with properties {:?} found", property_flags)))?;
+
+        Ok(memory_type_index)
+    }
+
+    pub fn allocation_stats(&self) -> Result<AllocationStats> {
+        self.allocator.stats()
+    }
+
+    // The allocator has to go before the device it allocates from
+    pub unsafe fn destroy(&mut self) {
+        self.uploader.lock().unwrap().destroy(self);
 
-        memory_type_index
+        ManuallyDrop::drop(&mut self.allocator);
+
+        self.device.destroy_device(None);
+
+        if let Some(surface) = self.surface {
+            self.surface_init.destroy_surface(surface, None);
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,58 @@
+use std::fmt;
+
+use ash::vk;
+
+#[derive(Debug)]
+pub enum Error {
+    Vulkan(vk::Result),
+    Parse(String, String),
+    Io(String, std::io::Error),
+    MissingResource(String),
+    MissingPass(String),
+    MissingLayer(String),
+    MissingNode(String),
+    IncompleteBuilder(String),
+    InvalidGraph(String),
+    Validation(Vec<String>),
+    UnsupportedFormat(vk::Format),
+    Unsupported(String),
+    NoSuitableDevice,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Vulkan(result) => write!(f, "Vulkan call failed: {}", result),
+            Error::Parse(path, message) => write!(f, "Failed to parse '{}': {}", path, message),
+            Error::Io(path, error) => write!(f, "I/O error on '{}': {}", path, error),
+            Error::MissingResource(name) => write!(f, "No resource with name '{}' found", name),
+            Error::MissingPass(name) => write!(f, "No pass with name '{}' found", name),
+            Error::MissingLayer(name) => write!(f, "No layer with name '{}' found", name),
+            Error::MissingNode(name) => write!(f, "No node with name '{}' found", name),
+            Error::IncompleteBuilder(message) => write!(f, "{}", message),
+            Error::InvalidGraph(message) => write!(f, "Invalid graph: {}", message),
+            Error::Validation(problems) => write!(f, "Render graph failed validation:\n  {}", problems.join("\n  ")),
+            Error::UnsupportedFormat(format) => write!(f, "Unsupported image format {:?}", format),
+            Error::Unsupported(message) => write!(f, "{}", message),
+            Error::NoSuitableDevice => write!(f, "Suitable physical device not found"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Vulkan(result) => Some(result),
+            Error::Io(_, error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<vk::Result> for Error {
+    fn from(result: vk::Result) -> Error {
+        Error::Vulkan(result)
+    }
+}
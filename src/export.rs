@@ -0,0 +1,72 @@
+use crate::layer::{Layer, LayerDependencyInfo, PassDependency};
+use crate::renderer_data::RendererData;
+use crate::util::graph::{quote, Graph, GraphLabel};
+
+// Pass dependencies are labelled with the name of the resource they guard rather than its index
+fn pass_dependency_label(data: &RendererData, dependency: &Option<PassDependency>) -> String {
+    match dependency {
+        Some(dependency) => match data.get_reference_name(dependency.resource) {
+            Some(name) => format!("{}\n{}", name, dependency.masks_label()),
+            None => dependency.label(),
+        },
+        None => String::new(),
+    }
+}
+
+// Every layer becomes a cluster holding its pass graph. Layer dependencies are drawn between the clusters
+// through an invisible anchor node in each, since DOT edges have to join nodes
+pub fn render_graph_dot(layer_graph: &Graph<usize, LayerDependencyInfo>, layers: &Vec<Layer>, data: &RendererData) -> String {
+    let mut dot = String::from("digraph \"render_graph\" {\n    compound=true;\n    node [shape=box];\n");
+
+    for node in layer_graph.get_nodes() {
+        let layer = &layers[node.data];
+        let prefix = format!("{}/", node.name);
+
+        let mut label = format!("{} ({:?})", node.name, layer.exec);
+        if layer.present {
+            label.push_str(" present");
+        }
+
+        dot.push_str(&format!("\n    subgraph {} {{\n", quote(&format!("cluster_{}", node.name))));
+        dot.push_str(&format!("        label={};\n", quote(&label)));
+        dot.push_str(&format!("        {} [shape=point, style=invis];\n", quote(&prefix)));
+        dot.push_str(&layer.pass_graph.dot_statements("        ", &prefix, |pass| pass.data.label(), |edge| pass_dependency_label(data, &edge.info)));
+        dot.push_str("    }\n");
+    }
+
+    dot.push('\n');
+
+    for edge in layer_graph.get_edges() {
+        let src = &layer_graph.get_src_node(edge).name;
+        let dst = &layer_graph.get_dst_node(edge).name;
+
+        dot.push_str(&format!("    {} -> {} [ltail={}, lhead={}, label={}];\n",
+            quote(&format!("{}/", src)), quote(&format!("{}/", dst)),
+            quote(&format!("cluster_{}", src)), quote(&format!("cluster_{}", dst)),
+            quote(&edge.info.label())));
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+pub fn render_graph_json(layer_graph: &Graph<usize, LayerDependencyInfo>, layers: &Vec<Layer>, data: &RendererData) -> String {
+    let layer_entries = layer_graph.get_nodes().iter().map(|node| {
+        let layer = &layers[node.data];
+
+        let passes = layer.pass_graph.to_json_with(|pass| pass.data.label(), |edge| pass_dependency_label(data, &edge.info));
+
+        format!("{{\"name\":{},\"exec\":{},\"present\":{},\"passes\":{}}}", quote(&node.name), quote(&format!("{:?}", layer.exec)), layer.present, passes)
+    }).collect::<Vec<String>>();
+
+    let dependencies = layer_graph.get_edges().iter().map(|edge| {
+        format!("{{\"src\":{},\"dst\":{},\"stage\":{}}}", quote(&layer_graph.get_src_node(edge).name), quote(&layer_graph.get_dst_node(edge).name), quote(&edge.info.label()))
+    }).collect::<Vec<String>>();
+
+    let root = match layer_graph.get_root() {
+        Ok(root) => quote(&root),
+        Err(_) => "null".to_string(),
+    };
+
+    format!("{{\"root\":{},\"layers\":[{}],\"dependencies\":[{}]}}\n", root, layer_entries.join(","), dependencies.join(","))
+}
@@ -1,4 +1,5 @@
 use crate::device::Device;
+use crate::error::Result;
 use crate::semaphore::Semaphore;
 use crate::fence::Fence;
 
@@ -6,17 +7,29 @@ use crate::fence::Fence;
 pub struct Frame {
     pub image_available_semaphore: Semaphore,
     pub in_flight_fence: Fence,
+
+    // Presentation can only wait on binary semaphores, so with timeline semaphores the present layer also signals this
+    pub render_finished_semaphore: Semaphore,
 }
 
 impl Frame {
-    pub unsafe fn new(d: &Device) -> Frame {
-        let image_available_semaphore = Semaphore::new(d);
+    pub unsafe fn new(d: &Device) -> Result<Frame> {
+        let image_available_semaphore = Semaphore::new(d)?;
+
+        let in_flight_fence = Fence::new(d, true)?;
 
-        let in_flight_fence = Fence::new(d, true);
+        let render_finished_semaphore = Semaphore::new(d)?;
 
-        Frame {
+        Ok(Frame {
             image_available_semaphore,
             in_flight_fence,
-        }
+            render_finished_semaphore,
+        })
+    }
+
+    pub unsafe fn destroy(&self, d: &Device) {
+        self.image_available_semaphore.destroy(d);
+        self.in_flight_fence.destroy(d);
+        self.render_finished_semaphore.destroy(d);
     }
 }
\ No newline at end of file
@@ -1,6 +1,7 @@
 use ash::vk;
 
 use crate::device::Device;
+use crate::error::Result;
 use crate::graphics_pipeline::GraphicsPipeline;
 use crate::image::Image;
 
@@ -9,7 +10,7 @@ pub struct Framebuffer {
 }
 
 impl Framebuffer {
-    pub unsafe fn new(d: &Device, g: &GraphicsPipeline, target: &Image, extent: Option<vk::Extent2D>) -> Framebuffer {
+    pub unsafe fn new(d: &Device, g: &GraphicsPipeline, target: &Image, extent: Option<vk::Extent2D>) -> Result<Framebuffer> {
         let mut views = vec![target.view];
 
         if let Some(depth_image) = g.depth_image {
@@ -21,28 +22,31 @@ impl Framebuffer {
             None => (target.width, target.height),
         };
             
-        let framebuffer_ci = vk::FramebufferCreateInfo::builder()
+        let framebuffer_ci = vk::FramebufferCreateInfo::default()
             .render_pass(g.render_pass)
             .attachments(&views)
             .width(target.width)
             .height(target.height)
-            .layers(1)
-            .build();
+            .layers(1);
 
-        let framebuffer = d.device.create_framebuffer(&framebuffer_ci, None).unwrap();
+        let framebuffer = d.device.create_framebuffer(&framebuffer_ci, None)?;
 
-        Framebuffer {
+        Ok(Framebuffer {
             framebuffer,
-        }
+        })
     }
 
-    pub unsafe fn new_many(d: &Device, g: &GraphicsPipeline, targets: &Vec<Image>, extent: Option<vk::Extent2D>) -> Vec<Framebuffer> {
+    pub unsafe fn new_many(d: &Device, g: &GraphicsPipeline, targets: &Vec<Image>, extent: Option<vk::Extent2D>) -> Result<Vec<Framebuffer>> {
         let mut framebuffers = Vec::<Framebuffer>::new();
 
         for target in targets {
-            framebuffers.push(Framebuffer::new(d, g, target, extent));
+            framebuffers.push(Framebuffer::new(d, g, target, extent)?);
         }
 
-        framebuffers
+        Ok(framebuffers)
+    }
+
+    pub unsafe fn destroy(&self, d: &Device) {
+        d.device.destroy_framebuffer(self.framebuffer, None);
     }
 }
\ No newline at end of file
@@ -0,0 +1,1073 @@
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+
+use ash::vk;
+
+use crate::allocator::MemoryUsage;
+use crate::buffer::BufferBuilder;
+use crate::compute_pass::{ComputePassBuilder, ComputePassDispatchInfo};
+use crate::descriptors::CreationReference;
+use crate::error::{Error, Result};
+use crate::graphics_pass::{GraphicsPassBuilder, GraphicsPassDrawInfo};
+use crate::image::ImageBuilder;
+use crate::layer::{LayerExecution, PassDependency, ResourceAccess, ResourceUsage};
+use crate::math::vec::Vec4;
+use crate::renderer_data::ResourceReference;
+use crate::shader::ShaderType;
+use crate::transfer_pass::{TransferOperation, TransferPassBuilder};
+use crate::util::json::JsonValue;
+use crate::vertex_buffer::NoVertices;
+use crate::Renderer;
+
+// A render graph as data, so it can be loaded from and saved to a file instead of being built in code.
+// Graphics passes described this way draw without vertex buffers, generating their geometry in the vertex shader
+#[derive(Clone)]
+pub struct GraphDescription {
+    pub buffers: Vec<BufferDescription>,
+    pub images: Vec<ImageDescription>,
+    pub layers: Vec<LayerDescription>,
+    pub layer_dependencies: Vec<LayerDependencyDescription>,
+    pub root_layer: Option<String>,
+    pub exported: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct BufferDescription {
+    pub name: String,
+    pub size: usize,
+    pub usage: vk::BufferUsageFlags,
+    pub memory_usage: MemoryUsage,
+    pub concurrent: bool,
+}
+
+#[derive(Copy, Clone)]
+pub enum ImageSize {
+    Fixed(u32, u32),
+    Relative(f32, f32),
+}
+
+#[derive(Clone)]
+pub struct ImageDescription {
+    pub name: String,
+    pub size: ImageSize,
+    pub format: vk::Format,
+    pub usage: vk::ImageUsageFlags,
+    pub layout: Option<vk::ImageLayout>,
+    pub memory_usage: MemoryUsage,
+    pub transient: bool,
+    pub concurrent: bool,
+    pub mip_levels: u32,
+}
+
+#[derive(Clone)]
+pub struct LayerDescription {
+    pub name: String,
+    pub present: bool,
+    pub exec: LayerExecution,
+    pub root_pass: Option<String>,
+    pub passes: Vec<PassDescription>,
+    pub dependencies: Vec<PassDependencyDescription>,
+    pub keep: Vec<String>,
+}
+
+#[derive(Clone)]
+pub enum PassDescription {
+    Compute(ComputePassDescription),
+    Graphics(GraphicsPassDescription),
+    Transfer(TransferPassDescription),
+}
+
+#[derive(Clone)]
+pub enum DispatchDescription {
+    Fixed(u32, u32, u32),
+    Image(String),
+}
+
+#[derive(Clone)]
+pub struct ComputePassDescription {
+    pub name: String,
+    pub shader: String,
+    pub dispatch: DispatchDescription,
+    pub push_constant_size: Option<usize>,
+    pub descriptors: Vec<CreationReference>,
+    pub accesses: Vec<ResourceAccess>,
+}
+
+#[derive(Clone)]
+pub struct GraphicsPassDescription {
+    pub name: String,
+    pub vertex_shader: String,
+    pub fragment_shader: String,
+    pub targets: String,
+    pub draws: Vec<GraphicsPassDrawInfo>,
+    pub vertex_push_constant_size: Option<usize>,
+    pub fragment_push_constant_size: Option<usize>,
+    pub vertex_descriptors: Vec<CreationReference>,
+    pub fragment_descriptors: Vec<CreationReference>,
+    pub depth_buffer: bool,
+    pub clear_colour: Option<[f32; 4]>,
+    pub accesses: Vec<ResourceAccess>,
+}
+
+#[derive(Clone)]
+pub struct TransferPassDescription {
+    pub name: String,
+    pub operations: Vec<TransferOperation>,
+}
+
+// Resources are named here rather than referenced by index, the index is looked up when the graph is built
+#[derive(Clone)]
+pub enum ResourceName {
+    Buffer(String),
+    Image(String),
+}
+
+#[derive(Clone)]
+pub struct PassBarrierDescription {
+    pub resource: ResourceName,
+
+    pub src_access: vk::AccessFlags,
+    pub src_stage: vk::PipelineStageFlags,
+    pub src_shader: ShaderType,
+
+    pub dst_access: vk::AccessFlags,
+    pub dst_stage: vk::PipelineStageFlags,
+    pub dst_shader: ShaderType,
+}
+
+#[derive(Clone)]
+pub struct PassDependencyDescription {
+    pub src: String,
+    pub dst: String,
+    pub barrier: Option<PassBarrierDescription>,
+}
+
+#[derive(Clone)]
+pub struct LayerDependencyDescription {
+    pub src: String,
+    pub dst: String,
+    pub stage: vk::PipelineStageFlags,
+}
+
+impl GraphDescription {
+    pub fn new() -> GraphDescription {
+        GraphDescription {
+            buffers: Vec::new(),
+            images: Vec::new(),
+            layers: Vec::new(),
+            layer_dependencies: Vec::new(),
+            root_layer: None,
+            exported: Vec::new(),
+        }
+    }
+
+    // Only .json files are understood
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<GraphDescription> {
+        let path = path.as_ref();
+        let source = path.to_string_lossy().to_string();
+
+        check_extension(path)?;
+
+        let text = fs::read_to_string(path).map_err(|e| Error::Io(source.clone(), e))?;
+        let json = JsonValue::parse(&text).map_err(|e| Error::Parse(source.clone(), e))?;
+
+        GraphDescription::from_json(&json).map_err(|e| Error::Parse(source, e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+
+        check_extension(path)?;
+
+        fs::write(path, self.to_json().to_string_pretty()).map_err(|e| Error::Io(path.to_string_lossy().to_string(), e))
+    }
+
+    pub fn extend(&mut self, other: GraphDescription) {
+        self.buffers.extend(other.buffers);
+        self.images.extend(other.images);
+        self.layers.extend(other.layers);
+        self.layer_dependencies.extend(other.layer_dependencies);
+        self.exported.extend(other.exported);
+
+        if other.root_layer.is_some() {
+            self.root_layer = other.root_layer;
+        }
+    }
+
+    // Resources are added first so the passes can find them, then layers with their passes, then the dependencies between layers
+    pub unsafe fn build(&self, renderer: &mut Renderer) -> Result<()> {
+        for buffer in &self.buffers {
+            let builder = BufferBuilder::new()
+                .size(buffer.size)
+                .usage(buffer.usage)
+                .sharing_mode(if buffer.concurrent { vk::SharingMode::CONCURRENT } else { vk::SharingMode::EXCLUSIVE })
+                .memory_usage(buffer.memory_usage);
+
+            renderer.add_buffers::<u8>(&buffer.name, builder, None)?;
+        }
+
+        for image in &self.images {
+            let builder = ImageBuilder::new()
+                .format(image.format)
+                .usage(image.usage)
+                .memory_usage(image.memory_usage);
+
+            let builder = match image.size {
+                ImageSize::Fixed(width, height) => builder.width(width).height(height),
+                ImageSize::Relative(width_scale, height_scale) => builder.relative_size(width_scale, height_scale),
+            };
+
+            let builder = match image.layout {
+                Some(layout) => builder.layout(layout),
+                None => builder,
+            };
+
+            let builder = match image.transient {
+                true => builder.transient(),
+                false => builder,
+            };
+
+            let builder = match image.concurrent {
+                true => builder.concurrent(),
+                false => builder,
+            };
+
+            let builder = match image.mip_levels {
+                1 => builder,
+                mip_levels => builder.mip_levels(mip_levels),
+            };
+
+            renderer.add_images(&image.name, builder)?;
+        }
+
+        for layer in &self.layers {
+            renderer.add_layer(&layer.name, layer.present, layer.exec)?;
+
+            for pass in &layer.passes {
+                match pass {
+                    PassDescription::Compute(pass) => {
+                        let builder = pass.builder(renderer)?;
+                        renderer.add_compute_pass(&layer.name, &pass.name, builder)?;
+                    },
+                    PassDescription::Graphics(pass) => {
+                        let builder = pass.builder(renderer)?;
+                        renderer.add_graphics_pass(&layer.name, &pass.name, builder)?;
+                    },
+                    PassDescription::Transfer(pass) => {
+                        let builder = pass.operations.iter().cloned().fold(TransferPassBuilder::new(), TransferPassBuilder::operation);
+                        renderer.add_transfer_pass(&layer.name, &pass.name, builder)?;
+                    },
+                }
+            }
+
+            for dependency in &layer.dependencies {
+                let barrier = match &dependency.barrier {
+                    Some(barrier) => Some(barrier.pass_dependency(renderer)?),
+                    None => None,
+                };
+
+                renderer.add_pass_dependency(&layer.name, &dependency.src, &dependency.dst, barrier)?;
+            }
+
+            if let Some(root_pass) = &layer.root_pass {
+                renderer.get_layer_mut(&layer.name)?.set_root_pass(root_pass);
+            }
+
+            for pass in &layer.keep {
+                renderer.keep_pass(&layer.name, pass)?;
+            }
+        }
+
+        for dependency in &self.layer_dependencies {
+            renderer.add_layer_dependency(&dependency.src, &dependency.dst, dependency.stage)?;
+        }
+
+        if let Some(root_layer) = &self.root_layer {
+            renderer.set_root_layer(root_layer);
+        }
+
+        for name in &self.exported {
+            renderer.mark_exported(name)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn from_json(json: &JsonValue) -> std::result::Result<GraphDescription, String> {
+        Ok(GraphDescription {
+            buffers: array(json, "buffers", "graph")?.iter().map(BufferDescription::from_json).collect::<std::result::Result<_, _>>()?,
+            images: array(json, "images", "graph")?.iter().map(ImageDescription::from_json).collect::<std::result::Result<_, _>>()?,
+            layers: array(json, "layers", "graph")?.iter().map(LayerDescription::from_json).collect::<std::result::Result<_, _>>()?,
+            layer_dependencies: array(json, "layer_dependencies", "graph")?.iter().map(|dependency| {
+                Ok(LayerDependencyDescription {
+                    src: string(dependency, "src", "layer dependency")?,
+                    dst: string(dependency, "dst", "layer dependency")?,
+                    stage: vk::PipelineStageFlags::from_raw(flags_from_json(dependency, "stage", "layer dependency", vk::PipelineStageFlags::from_raw)?),
+                })
+            }).collect::<std::result::Result<_, String>>()?,
+            root_layer: optional(json, "root_layer").map(|_| string(json, "root_layer", "graph")).transpose()?,
+            exported: strings(json, "exported", "graph")?,
+        })
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::Object(vec![
+            ("root_layer".to_string(), self.root_layer.as_ref().map(|root| JsonValue::String(root.clone())).unwrap_or(JsonValue::Null)),
+            ("buffers".to_string(), JsonValue::Array(self.buffers.iter().map(|buffer| buffer.to_json()).collect())),
+            ("images".to_string(), JsonValue::Array(self.images.iter().map(|image| image.to_json()).collect())),
+            ("layers".to_string(), JsonValue::Array(self.layers.iter().map(|layer| layer.to_json()).collect())),
+            ("layer_dependencies".to_string(), JsonValue::Array(self.layer_dependencies.iter().map(|dependency| {
+                JsonValue::Object(vec![
+                    ("src".to_string(), JsonValue::String(dependency.src.clone())),
+                    ("dst".to_string(), JsonValue::String(dependency.dst.clone())),
+                    ("stage".to_string(), flags_to_json(dependency.stage.as_raw(), vk::PipelineStageFlags::from_raw)),
+                ])
+            }).collect())),
+            ("exported".to_string(), JsonValue::Array(self.exported.iter().map(|name| JsonValue::String(name.clone())).collect())),
+        ])
+    }
+}
+
+impl BufferDescription {
+    fn from_json(json: &JsonValue) -> std::result::Result<BufferDescription, String> {
+        let name = string(json, "name", "buffer")?;
+        let context = format!("buffer '{}'", name);
+
+        Ok(BufferDescription {
+            size: number(json, "size", &context)? as usize,
+            usage: vk::BufferUsageFlags::from_raw(flags_from_json(json, "usage", &context, vk::BufferUsageFlags::from_raw)?),
+            memory_usage: memory_usage_from_json(json, &context)?,
+            concurrent: optional(json, "concurrent").map(|_| boolean(json, "concurrent", &context)).transpose()?.unwrap_or(false),
+            name,
+        })
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut fields = vec![
+            ("name".to_string(), JsonValue::String(self.name.clone())),
+            ("size".to_string(), JsonValue::Number(self.size as f64)),
+            ("usage".to_string(), flags_to_json(self.usage.as_raw(), vk::BufferUsageFlags::from_raw)),
+            ("memory_usage".to_string(), memory_usage_to_json(self.memory_usage)),
+        ];
+
+        if self.concurrent {
+            fields.push(("concurrent".to_string(), JsonValue::Bool(true)));
+        }
+
+        JsonValue::Object(fields)
+    }
+}
+
+impl ImageDescription {
+    fn from_json(json: &JsonValue) -> std::result::Result<ImageDescription, String> {
+        let name = string(json, "name", "image")?;
+        let context = format!("image '{}'", name);
+
+        let size = match optional(json, "relative_size") {
+            Some(_) => {
+                let scale = numbers(json, "relative_size", &context, 2)?;
+                ImageSize::Relative(scale[0] as f32, scale[1] as f32)
+            },
+            None => ImageSize::Fixed(number(json, "width", &context)? as u32, number(json, "height", &context)? as u32),
+        };
+
+        Ok(ImageDescription {
+            size,
+            format: enum_from_json(json, "format", &context, FORMATS, vk::Format::from_raw)?,
+            usage: vk::ImageUsageFlags::from_raw(flags_from_json(json, "usage", &context, vk::ImageUsageFlags::from_raw)?),
+            layout: optional(json, "layout").map(|_| enum_from_json(json, "layout", &context, IMAGE_LAYOUTS, vk::ImageLayout::from_raw)).transpose()?,
+            memory_usage: memory_usage_from_json(json, &context)?,
+            transient: optional(json, "transient").map(|_| boolean(json, "transient", &context)).transpose()?.unwrap_or(false),
+            concurrent: optional(json, "concurrent").map(|_| boolean(json, "concurrent", &context)).transpose()?.unwrap_or(false),
+            mip_levels: optional(json, "mip_levels").map(|_| number(json, "mip_levels", &context)).transpose()?.unwrap_or(1.0) as u32,
+            name,
+        })
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut fields = vec![("name".to_string(), JsonValue::String(self.name.clone()))];
+
+        match self.size {
+            ImageSize::Fixed(width, height) => {
+                fields.push(("width".to_string(), JsonValue::Number(width as f64)));
+                fields.push(("height".to_string(), JsonValue::Number(height as f64)));
+            },
+            ImageSize::Relative(width_scale, height_scale) => {
+                fields.push(("relative_size".to_string(), JsonValue::Array(vec![JsonValue::Number(width_scale as f64), JsonValue::Number(height_scale as f64)])));
+            },
+        }
+
+        fields.push(("format".to_string(), enum_to_json(self.format.as_raw(), FORMATS, vk::Format::from_raw)));
+        fields.push(("usage".to_string(), flags_to_json(self.usage.as_raw(), vk::ImageUsageFlags::from_raw)));
+
+        if let Some(layout) = self.layout {
+            fields.push(("layout".to_string(), enum_to_json(layout.as_raw(), IMAGE_LAYOUTS, vk::ImageLayout::from_raw)));
+        }
+
+        fields.push(("memory_usage".to_string(), memory_usage_to_json(self.memory_usage)));
+
+        if self.transient {
+            fields.push(("transient".to_string(), JsonValue::Bool(true)));
+        }
+
+        if self.concurrent {
+            fields.push(("concurrent".to_string(), JsonValue::Bool(true)));
+        }
+
+        if self.mip_levels != 1 {
+            fields.push(("mip_levels".to_string(), JsonValue::Number(self.mip_levels as f64)));
+        }
+
+        JsonValue::Object(fields)
+    }
+}
+
+impl LayerDescription {
+    fn from_json(json: &JsonValue) -> std::result::Result<LayerDescription, String> {
+        let name = string(json, "name", "layer")?;
+        let context = format!("layer '{}'", name);
+
+        let exec = match optional(json, "exec").map(|_| string(json, "exec", &context)).transpose()?.as_deref() {
+            None | Some("Main") => LayerExecution::Main,
+            Some("Async") => LayerExecution::Async,
+            Some("Transfer") => LayerExecution::Transfer,
+            Some(exec) => return Err(format!("{} has unknown exec '{}', expected \"Main\", \"Async\" or \"Transfer\"", context, exec)),
+        };
+
+        Ok(LayerDescription {
+            present: optional(json, "present").map(|_| boolean(json, "present", &context)).transpose()?.unwrap_or(false),
+            exec,
+            root_pass: optional(json, "root_pass").map(|_| string(json, "root_pass", &context)).transpose()?,
+            passes: array(json, "passes", &context)?.iter().map(|pass| PassDescription::from_json(pass, &context)).collect::<std::result::Result<_, _>>()?,
+            dependencies: array(json, "dependencies", &context)?.iter().map(|dependency| PassDependencyDescription::from_json(dependency, &context)).collect::<std::result::Result<_, _>>()?,
+            keep: strings(json, "keep", &context)?,
+            name,
+        })
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut fields = vec![
+            ("name".to_string(), JsonValue::String(self.name.clone())),
+            ("present".to_string(), JsonValue::Bool(self.present)),
+            ("exec".to_string(), JsonValue::String(format!("{:?}", self.exec))),
+        ];
+
+        if let Some(root_pass) = &self.root_pass {
+            fields.push(("root_pass".to_string(), JsonValue::String(root_pass.clone())));
+        }
+
+        fields.push(("passes".to_string(), JsonValue::Array(self.passes.iter().map(|pass| pass.to_json()).collect())));
+        fields.push(("dependencies".to_string(), JsonValue::Array(self.dependencies.iter().map(|dependency| dependency.to_json()).collect())));
+
+        if !self.keep.is_empty() {
+            fields.push(("keep".to_string(), JsonValue::Array(self.keep.iter().map(|name| JsonValue::String(name.clone())).collect())));
+        }
+
+        JsonValue::Object(fields)
+    }
+}
+
+impl PassDescription {
+    fn from_json(json: &JsonValue, layer_context: &str) -> std::result::Result<PassDescription, String> {
+        let name = string(json, "name", &format!("pass in {}", layer_context))?;
+        let context = format!("pass '{}' in {}", name, layer_context);
+
+        match string(json, "type", &context)?.as_str() {
+            "compute" => Ok(PassDescription::Compute(ComputePassDescription {
+                shader: string(json, "shader", &context)?,
+                dispatch: match field(json, "dispatch", &context)? {
+                    JsonValue::Array(_) => {
+                        let size = numbers(json, "dispatch", &context, 3)?;
+                        DispatchDescription::Fixed(size[0] as u32, size[1] as u32, size[2] as u32)
+                    },
+                    dispatch => DispatchDescription::Image(string(dispatch, "image", &format!("dispatch of {}", context))?),
+                },
+                push_constant_size: optional(json, "push_constant_size").map(|_| number(json, "push_constant_size", &context).map(|size| size as usize)).transpose()?,
+                descriptors: descriptors_from_json(json, "descriptors", &context)?,
+                accesses: accesses_from_json(json, &context)?,
+                name,
+            })),
+            "graphics" => Ok(PassDescription::Graphics(GraphicsPassDescription {
+                vertex_shader: string(json, "vertex_shader", &context)?,
+                fragment_shader: string(json, "fragment_shader", &context)?,
+                targets: string(json, "targets", &context)?,
+                draws: array(json, "draws", &context)?.iter().map(|draw| {
+                    let draw_context = format!("draw of {}", context);
+
+                    Ok(GraphicsPassDrawInfo {
+                        vertex_count: number(draw, "vertex_count", &draw_context)? as u32,
+                        index_count: 0,
+                        instance_count: optional(draw, "instance_count").map(|_| number(draw, "instance_count", &draw_context)).transpose()?.unwrap_or(1.0) as u32,
+                        first_vertex: optional(draw, "first_vertex").map(|_| number(draw, "first_vertex", &draw_context)).transpose()?.unwrap_or(0.0) as u32,
+                        first_instance: optional(draw, "first_instance").map(|_| number(draw, "first_instance", &draw_context)).transpose()?.unwrap_or(0.0) as u32,
+                        vertex_offset: 0,
+                    })
+                }).collect::<std::result::Result<_, String>>()?,
+                vertex_push_constant_size: optional(json, "vertex_push_constant_size").map(|_| number(json, "vertex_push_constant_size", &context).map(|size| size as usize)).transpose()?,
+                fragment_push_constant_size: optional(json, "fragment_push_constant_size").map(|_| number(json, "fragment_push_constant_size", &context).map(|size| size as usize)).transpose()?,
+                vertex_descriptors: descriptors_from_json(json, "vertex_descriptors", &context)?,
+                fragment_descriptors: descriptors_from_json(json, "fragment_descriptors", &context)?,
+                depth_buffer: optional(json, "depth_buffer").map(|_| boolean(json, "depth_buffer", &context)).transpose()?.unwrap_or(false),
+                clear_colour: optional(json, "clear_colour").map(|_| {
+                    numbers(json, "clear_colour", &context, 4).map(|c| [c[0] as f32, c[1] as f32, c[2] as f32, c[3] as f32])
+                }).transpose()?,
+                accesses: accesses_from_json(json, &context)?,
+                name,
+            })),
+            "transfer" => Ok(PassDescription::Transfer(TransferPassDescription {
+                operations: array(json, "operations", &context)?.iter().map(|operation| transfer_operation_from_json(operation, &context)).collect::<std::result::Result<_, _>>()?,
+                name,
+            })),
+            pass_type => Err(format!("{} has unknown type '{}', expected \"compute\", \"graphics\" or \"transfer\"", context, pass_type)),
+        }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        match self {
+            PassDescription::Compute(pass) => {
+                let mut fields = vec![
+                    ("name".to_string(), JsonValue::String(pass.name.clone())),
+                    ("type".to_string(), JsonValue::String("compute".to_string())),
+                    ("shader".to_string(), JsonValue::String(pass.shader.clone())),
+                    ("dispatch".to_string(), match &pass.dispatch {
+                        DispatchDescription::Fixed(x, y, z) => JsonValue::Array(vec![JsonValue::Number(*x as f64), JsonValue::Number(*y as f64), JsonValue::Number(*z as f64)]),
+                        DispatchDescription::Image(image) => JsonValue::Object(vec![("image".to_string(), JsonValue::String(image.clone()))]),
+                    }),
+                ];
+
+                if let Some(size) = pass.push_constant_size {
+                    fields.push(("push_constant_size".to_string(), JsonValue::Number(size as f64)));
+                }
+
+                fields.push(("descriptors".to_string(), descriptors_to_json(&pass.descriptors)));
+                fields.push(("accesses".to_string(), accesses_to_json(&pass.accesses)));
+
+                JsonValue::Object(fields)
+            },
+            PassDescription::Graphics(pass) => {
+                let mut fields = vec![
+                    ("name".to_string(), JsonValue::String(pass.name.clone())),
+                    ("type".to_string(), JsonValue::String("graphics".to_string())),
+                    ("vertex_shader".to_string(), JsonValue::String(pass.vertex_shader.clone())),
+                    ("fragment_shader".to_string(), JsonValue::String(pass.fragment_shader.clone())),
+                    ("targets".to_string(), JsonValue::String(pass.targets.clone())),
+                    ("draws".to_string(), JsonValue::Array(pass.draws.iter().map(|draw| {
+                        JsonValue::Object(vec![
+                            ("vertex_count".to_string(), JsonValue::Number(draw.vertex_count as f64)),
+                            ("instance_count".to_string(), JsonValue::Number(draw.instance_count as f64)),
+                            ("first_vertex".to_string(), JsonValue::Number(draw.first_vertex as f64)),
+                            ("first_instance".to_string(), JsonValue::Number(draw.first_instance as f64)),
+                        ])
+                    }).collect())),
+                ];
+
+                if let Some(size) = pass.vertex_push_constant_size {
+                    fields.push(("vertex_push_constant_size".to_string(), JsonValue::Number(size as f64)));
+                }
+
+                if let Some(size) = pass.fragment_push_constant_size {
+                    fields.push(("fragment_push_constant_size".to_string(), JsonValue::Number(size as f64)));
+                }
+
+                fields.push(("vertex_descriptors".to_string(), descriptors_to_json(&pass.vertex_descriptors)));
+                fields.push(("fragment_descriptors".to_string(), descriptors_to_json(&pass.fragment_descriptors)));
+                fields.push(("depth_buffer".to_string(), JsonValue::Bool(pass.depth_buffer)));
+
+                if let Some(clear_colour) = pass.clear_colour {
+                    fields.push(("clear_colour".to_string(), JsonValue::Array(clear_colour.iter().map(|&c| JsonValue::Number(c as f64)).collect())));
+                }
+
+                fields.push(("accesses".to_string(), accesses_to_json(&pass.accesses)));
+
+                JsonValue::Object(fields)
+            },
+            PassDescription::Transfer(pass) => {
+                JsonValue::Object(vec![
+                    ("name".to_string(), JsonValue::String(pass.name.clone())),
+                    ("type".to_string(), JsonValue::String("transfer".to_string())),
+                    ("operations".to_string(), JsonValue::Array(pass.operations.iter().map(transfer_operation_to_json).collect())),
+                ])
+            },
+        }
+    }
+}
+
+impl ComputePassDescription {
+    fn builder(&self, renderer: &Renderer) -> Result<ComputePassBuilder<'_>> {
+        let dispatch_info = match &self.dispatch {
+            DispatchDescription::Fixed(x, y, z) => ComputePassDispatchInfo::new(*x, *y, *z),
+            DispatchDescription::Image(image) => ComputePassDispatchInfo::for_image(image, &renderer.data)?,
+        };
+
+        let mut builder = ComputePassBuilder::new()
+            .compute_shader(&self.shader)
+            .dispatch_info(dispatch_info);
+
+        if let Some(size) = self.push_constant_size {
+            builder = builder.push_constant_size(size);
+        }
+
+        if !self.descriptors.is_empty() {
+            builder = builder.descriptors(self.descriptors.clone(), &renderer.data);
+        }
+
+        for access in &self.accesses {
+            builder = match access.write {
+                true => builder.writes(&access.name, access.usage),
+                false => builder.reads(&access.name, access.usage),
+            };
+        }
+
+        Ok(builder)
+    }
+}
+
+impl GraphicsPassDescription {
+    fn builder(&self, renderer: &Renderer) -> Result<GraphicsPassBuilder<'_, NoVertices, u32>> {
+        let mut builder = GraphicsPassBuilder::new()
+            .vertex_shader(&self.vertex_shader)
+            .fragment_shader(&self.fragment_shader)
+            .targets(renderer.get_images(&self.targets)?)
+            .draw_infos(self.draws.clone());
+
+        if let Some(size) = self.vertex_push_constant_size {
+            builder = builder.vertex_push_constant_size(size);
+        }
+
+        if let Some(size) = self.fragment_push_constant_size {
+            builder = builder.fragment_push_constant_size(size);
+        }
+
+        if !self.vertex_descriptors.is_empty() {
+            builder = builder.vertex_descriptors(self.vertex_descriptors.clone(), &renderer.data);
+        }
+
+        if !self.fragment_descriptors.is_empty() {
+            builder = builder.fragment_descriptors(self.fragment_descriptors.clone(), &renderer.data);
+        }
+
+        if self.depth_buffer {
+            builder = builder.with_depth_buffer();
+        }
+
+        if let Some(c) = self.clear_colour {
+            builder = builder.clear_col(Vec4::new(c[0], c[1], c[2], c[3]));
+        }
+
+        for access in &self.accesses {
+            builder = match access.write {
+                true => builder.writes(&access.name, access.usage),
+                false => builder.reads(&access.name, access.usage),
+            };
+        }
+
+        Ok(builder)
+    }
+}
+
+impl PassBarrierDescription {
+    fn pass_dependency(&self, renderer: &Renderer) -> Result<PassDependency> {
+        let resource = match &self.resource {
+            ResourceName::Buffer(name) => ResourceReference::Buffer(renderer.data.get_buffer_refs(name)?),
+            ResourceName::Image(name) => ResourceReference::Image(renderer.data.get_image_refs(name)?),
+        };
+
+        Ok(PassDependency {
+            resource,
+            src_access: self.src_access,
+            src_stage: self.src_stage,
+            src_shader: self.src_shader,
+            dst_access: self.dst_access,
+            dst_stage: self.dst_stage,
+            dst_shader: self.dst_shader,
+        })
+    }
+}
+
+impl PassDependencyDescription {
+    fn from_json(json: &JsonValue, layer_context: &str) -> std::result::Result<PassDependencyDescription, String> {
+        let src = string(json, "src", &format!("pass dependency in {}", layer_context))?;
+        let dst = string(json, "dst", &format!("pass dependency in {}", layer_context))?;
+        let context = format!("pass dependency from '{}' to '{}' in {}", src, dst, layer_context);
+
+        let resource = match (optional(json, "buffer"), optional(json, "image")) {
+            (Some(_), Some(_)) => return Err(format!("{} names both a buffer and an image", context)),
+            (Some(_), None) => Some(ResourceName::Buffer(string(json, "buffer", &context)?)),
+            (None, Some(_)) => Some(ResourceName::Image(string(json, "image", &context)?)),
+            (None, None) => None,
+        };
+
+        let barrier = match resource {
+            Some(resource) => Some(PassBarrierDescription {
+                resource,
+                src_access: vk::AccessFlags::from_raw(flags_from_json(json, "src_access", &context, vk::AccessFlags::from_raw)?),
+                src_stage: vk::PipelineStageFlags::from_raw(flags_from_json(json, "src_stage", &context, vk::PipelineStageFlags::from_raw)?),
+                src_shader: shader_type_from_json(json, "src_shader", &context)?,
+                dst_access: vk::AccessFlags::from_raw(flags_from_json(json, "dst_access", &context, vk::AccessFlags::from_raw)?),
+                dst_stage: vk::PipelineStageFlags::from_raw(flags_from_json(json, "dst_stage", &context, vk::PipelineStageFlags::from_raw)?),
+                dst_shader: shader_type_from_json(json, "dst_shader", &context)?,
+            }),
+            None => None,
+        };
+
+        Ok(PassDependencyDescription { src, dst, barrier })
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut fields = vec![
+            ("src".to_string(), JsonValue::String(self.src.clone())),
+            ("dst".to_string(), JsonValue::String(self.dst.clone())),
+        ];
+
+        if let Some(barrier) = &self.barrier {
+            fields.push(match &barrier.resource {
+                ResourceName::Buffer(name) => ("buffer".to_string(), JsonValue::String(name.clone())),
+                ResourceName::Image(name) => ("image".to_string(), JsonValue::String(name.clone())),
+            });
+
+            fields.push(("src_access".to_string(), flags_to_json(barrier.src_access.as_raw(), vk::AccessFlags::from_raw)));
+            fields.push(("src_stage".to_string(), flags_to_json(barrier.src_stage.as_raw(), vk::PipelineStageFlags::from_raw)));
+            fields.push(("src_shader".to_string(), shader_type_to_json(barrier.src_shader)));
+            fields.push(("dst_access".to_string(), flags_to_json(barrier.dst_access.as_raw(), vk::AccessFlags::from_raw)));
+            fields.push(("dst_stage".to_string(), flags_to_json(barrier.dst_stage.as_raw(), vk::PipelineStageFlags::from_raw)));
+            fields.push(("dst_shader".to_string(), shader_type_to_json(barrier.dst_shader)));
+        }
+
+        JsonValue::Object(fields)
+    }
+}
+
+fn check_extension(path: &Path) -> Result<()> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => Ok(()),
+        _ => Err(Error::Unsupported(format!("Unsupported graph description file type {:?}, expected .json", path))),
+    }
+}
+
+// Absent fields and explicit nulls are treated the same
+fn optional<'a>(json: &'a JsonValue, key: &str) -> Option<&'a JsonValue> {
+    json.get(key).filter(|value| !value.is_null())
+}
+
+fn field<'a>(json: &'a JsonValue, key: &str, context: &str) -> std::result::Result<&'a JsonValue, String> {
+    optional(json, key).ok_or_else(|| format!("{} is missing '{}'", context, key))
+}
+
+fn string(json: &JsonValue, key: &str, context: &str) -> std::result::Result<String, String> {
+    field(json, key, context)?.as_str().map(|s| s.to_string()).ok_or_else(|| format!("{} has a '{}' that is not a string", context, key))
+}
+
+fn number(json: &JsonValue, key: &str, context: &str) -> std::result::Result<f64, String> {
+    field(json, key, context)?.as_f64().ok_or_else(|| format!("{} has a '{}' that is not a number", context, key))
+}
+
+fn boolean(json: &JsonValue, key: &str, context: &str) -> std::result::Result<bool, String> {
+    field(json, key, context)?.as_bool().ok_or_else(|| format!("{} has a '{}' that is not true or false", context, key))
+}
+
+// Missing arrays are empty, so small graphs can leave out the sections they don't use
+fn array<'a>(json: &'a JsonValue, key: &str, context: &str) -> std::result::Result<&'a [JsonValue], String> {
+    match optional(json, key) {
+        Some(value) => value.as_array().map(|values| values.as_slice()).ok_or_else(|| format!("{} has a '{}' that is not an array", context, key)),
+        None => Ok(&[]),
+    }
+}
+
+fn strings(json: &JsonValue, key: &str, context: &str) -> std::result::Result<Vec<String>, String> {
+    array(json, key, context)?.iter().map(|value| {
+        value.as_str().map(|s| s.to_string()).ok_or_else(|| format!("{} has a '{}' that is not an array of strings", context, key))
+    }).collect()
+}
+
+fn numbers(json: &JsonValue, key: &str, context: &str, count: usize) -> std::result::Result<Vec<f64>, String> {
+    let values = field(json, key, context)?.as_array().map(|values| values.iter().filter_map(|value| value.as_f64()).collect::<Vec<f64>>());
+
+    match values {
+        Some(values) if values.len() == count => Ok(values),
+        _ => Err(format!("{} has a '{}' that is not an array of {} numbers", context, key, count)),
+    }
+}
+
+// Flags are written as the names of their bits, so the file reads like the code that built the graph
+fn flag_name<F: Debug>(bit: u32, from_raw: fn(u32) -> F) -> Option<String> {
+    let name = format!("{:?}", from_raw(bit));
+
+    match name.chars().next() {
+        Some(c) if c.is_ascii_uppercase() && !name.contains(' ') => Some(name),
+        _ => None,
+    }
+}
+
+fn flags_to_json<F: Debug>(raw: u32, from_raw: fn(u32) -> F) -> JsonValue {
+    JsonValue::Array((0..32).map(|i| 1u32 << i).filter(|bit| raw & bit != 0).map(|bit| {
+        match flag_name(bit, from_raw) {
+            Some(name) => JsonValue::String(name),
+            None => JsonValue::Number(bit as f64),
+        }
+    }).collect())
+}
+
+fn flags_from_json<F: Debug>(json: &JsonValue, key: &str, context: &str, from_raw: fn(u32) -> F) -> std::result::Result<u32, String> {
+    let values = field(json, key, context)?.as_array().ok_or_else(|| format!("{} has a '{}' that is not an array of flags", context, key))?;
+
+    values.iter().map(|value| match value {
+        JsonValue::String(name) => (0..32).map(|i| 1u32 << i).find(|&bit| flag_name(bit, from_raw).as_deref() == Some(name.as_str())).ok_or_else(|| format!("{} has an unknown '{}' flag '{}'", context, key, name)),
+        JsonValue::Number(bit) => Ok(*bit as u32),
+        _ => Err(format!("{} has a '{}' flag that is not a name or number", context, key)),
+    }).try_fold(0, |flags, bit| bit.map(|bit| flags | bit))
+}
+
+// Raw values that enums are written by name for, covering the core and YCbCr formats and the core layouts plus PRESENT_SRC_KHR.
+// Anything else is written as its number so it can still be read back
+const FORMATS: &[std::ops::Range<i32>] = &[0..185, 1000156000..1000156034];
+const IMAGE_LAYOUTS: &[std::ops::Range<i32>] = &[0..9, 1000001002..1000001003];
+
+fn enum_to_json<E: Debug>(raw: i32, ranges: &[std::ops::Range<i32>], from_raw: fn(i32) -> E) -> JsonValue {
+    match ranges.iter().any(|range| range.contains(&raw)) {
+        true => JsonValue::String(format!("{:?}", from_raw(raw))),
+        false => JsonValue::Number(raw as f64),
+    }
+}
+
+fn enum_from_json<E: Debug>(json: &JsonValue, key: &str, context: &str, ranges: &[std::ops::Range<i32>], from_raw: fn(i32) -> E) -> std::result::Result<E, String> {
+    match field(json, key, context)? {
+        JsonValue::String(name) => ranges.iter().flat_map(|range| range.clone()).find(|&raw| format!("{:?}", from_raw(raw)) == *name).map(from_raw).ok_or_else(|| format!("{} has an unknown '{}' value '{}'", context, key, name)),
+        JsonValue::Number(raw) => Ok(from_raw(*raw as i32)),
+        _ => Err(format!("{} has a '{}' that is not a name or number", context, key)),
+    }
+}
+
+fn memory_usage_from_json(json: &JsonValue, context: &str) -> std::result::Result<MemoryUsage, String> {
+    match optional(json, "memory_usage").map(|_| string(json, "memory_usage", context)).transpose()?.as_deref() {
+        None | Some("GpuOnly") => Ok(MemoryUsage::GpuOnly),
+        Some("CpuToGpu") => Ok(MemoryUsage::CpuToGpu),
+        Some("GpuToCpu") => Ok(MemoryUsage::GpuToCpu),
+        Some(memory_usage) => Err(format!("{} has unknown memory_usage '{}', expected \"GpuOnly\", \"CpuToGpu\" or \"GpuToCpu\"", context, memory_usage)),
+    }
+}
+
+fn memory_usage_to_json(memory_usage: MemoryUsage) -> JsonValue {
+    JsonValue::String(match memory_usage {
+        MemoryUsage::GpuOnly => "GpuOnly",
+        MemoryUsage::CpuToGpu => "CpuToGpu",
+        MemoryUsage::GpuToCpu => "GpuToCpu",
+    }.to_string())
+}
+
+fn shader_type_from_json(json: &JsonValue, key: &str, context: &str) -> std::result::Result<ShaderType, String> {
+    match string(json, key, context)?.as_str() {
+        "compute" => Ok(ShaderType::Compute),
+        "vertex" => Ok(ShaderType::Vertex),
+        "fragment" => Ok(ShaderType::Fragment),
+        shader => Err(format!("{} has unknown '{}' '{}', expected \"compute\", \"vertex\" or \"fragment\"", context, key, shader)),
+    }
+}
+
+fn shader_type_to_json(shader: ShaderType) -> JsonValue {
+    JsonValue::String(match shader {
+        ShaderType::Compute => "compute",
+        ShaderType::Vertex => "vertex",
+        ShaderType::Fragment => "fragment",
+    }.to_string())
+}
+
+fn descriptors_from_json(json: &JsonValue, key: &str, context: &str) -> std::result::Result<Vec<CreationReference>, String> {
+    array(json, key, context)?.iter().map(|descriptor| {
+        let name = string(descriptor, "name", &format!("descriptor of {}", context))?;
+
+        match string(descriptor, "type", &format!("descriptor '{}' of {}", name, context))?.as_str() {
+            "uniform" => Ok(CreationReference::Uniform(name)),
+            "storage" => Ok(CreationReference::Storage(name)),
+            "image" => Ok(CreationReference::Image(name)),
+            "sampler" => Ok(CreationReference::Sampler(name)),
+            descriptor_type => Err(format!("descriptor '{}' of {} has unknown type '{}', expected \"uniform\", \"storage\", \"image\" or \"sampler\"", name, context, descriptor_type)),
+        }
+    }).collect()
+}
+
+fn descriptors_to_json(descriptors: &Vec<CreationReference>) -> JsonValue {
+    JsonValue::Array(descriptors.iter().map(|descriptor| {
+        let (descriptor_type, name) = match descriptor {
+            CreationReference::Uniform(name) => ("uniform", name),
+            CreationReference::Storage(name) => ("storage", name),
+            CreationReference::Image(name) => ("image", name),
+            CreationReference::Sampler(name) => ("sampler", name),
+        };
+
+        JsonValue::Object(vec![
+            ("type".to_string(), JsonValue::String(descriptor_type.to_string())),
+            ("name".to_string(), JsonValue::String(name.clone())),
+        ])
+    }).collect())
+}
+
+const RESOURCE_USAGES: [ResourceUsage; 8] = [
+    ResourceUsage::Sampled,
+    ResourceUsage::Storage,
+    ResourceUsage::Uniform,
+    ResourceUsage::ColorAttachment,
+    ResourceUsage::DepthAttachment,
+    ResourceUsage::Transfer,
+    ResourceUsage::Vertex,
+    ResourceUsage::Index,
+];
+
+fn accesses_from_json(json: &JsonValue, context: &str) -> std::result::Result<Vec<ResourceAccess>, String> {
+    array(json, "accesses", context)?.iter().map(|access| {
+        let name = string(access, "name", &format!("access of {}", context))?;
+        let access_context = format!("access of '{}' by {}", name, context);
+
+        let usage_name = string(access, "usage", &access_context)?;
+        let usage = RESOURCE_USAGES.iter().find(|usage| format!("{:?}", usage) == usage_name).copied().ok_or_else(|| format!("{} has unknown usage '{}'", access_context, usage_name))?;
+
+        let write = optional(access, "write").map(|_| boolean(access, "write", &access_context)).transpose()?.unwrap_or(false);
+
+        Ok(ResourceAccess::new(&name, usage, write))
+    }).collect()
+}
+
+fn accesses_to_json(accesses: &Vec<ResourceAccess>) -> JsonValue {
+    JsonValue::Array(accesses.iter().map(|access| {
+        JsonValue::Object(vec![
+            ("name".to_string(), JsonValue::String(access.name.clone())),
+            ("usage".to_string(), JsonValue::String(format!("{:?}", access.usage))),
+            ("write".to_string(), JsonValue::Bool(access.write)),
+        ])
+    }).collect())
+}
+
+fn transfer_operation_from_json(json: &JsonValue, pass_context: &str) -> std::result::Result<TransferOperation, String> {
+    let context = format!("operation of {}", pass_context);
+
+    match string(json, "op", &context)?.as_str() {
+        "copy_buffer" => Ok(TransferOperation::CopyBuffer { src: string(json, "src", &context)?, dst: string(json, "dst", &context)? }),
+        "copy_buffer_to_image" => Ok(TransferOperation::CopyBufferToImage { src: string(json, "src", &context)?, dst: string(json, "dst", &context)? }),
+        "copy_image" => Ok(TransferOperation::CopyImage { src: string(json, "src", &context)?, dst: string(json, "dst", &context)? }),
+        "blit_image" => Ok(TransferOperation::BlitImage {
+            src: string(json, "src", &context)?,
+            dst: string(json, "dst", &context)?,
+            filter: match optional(json, "filter").map(|_| string(json, "filter", &context)).transpose()?.as_deref() {
+                None | Some("LINEAR") => vk::Filter::LINEAR,
+                Some("NEAREST") => vk::Filter::NEAREST,
+                Some(filter) => return Err(format!("{} has unknown filter '{}', expected \"LINEAR\" or \"NEAREST\"", context, filter)),
+            },
+        }),
+        "fill_buffer" => Ok(TransferOperation::FillBuffer { dst: string(json, "dst", &context)?, value: optional(json, "value").map(|_| number(json, "value", &context)).transpose()?.unwrap_or(0.0) as u32 }),
+        "generate_mips" => Ok(TransferOperation::GenerateMips { image: string(json, "image", &context)? }),
+        op => Err(format!("{} has unknown op '{}', expected \"copy_buffer\", \"copy_buffer_to_image\", \"copy_image\", \"blit_image\", \"fill_buffer\" or \"generate_mips\"", context, op)),
+    }
+}
+
+fn transfer_operation_to_json(operation: &TransferOperation) -> JsonValue {
+    let (op, mut fields) = match operation {
+        TransferOperation::CopyBuffer { src, dst } => ("copy_buffer", vec![("src", JsonValue::String(src.clone())), ("dst", JsonValue::String(dst.clone()))]),
+        TransferOperation::CopyBufferToImage { src, dst } => ("copy_buffer_to_image", vec![("src", JsonValue::String(src.clone())), ("dst", JsonValue::String(dst.clone()))]),
+        TransferOperation::CopyImage { src, dst } => ("copy_image", vec![("src", JsonValue::String(src.clone())), ("dst", JsonValue::String(dst.clone()))]),
+        TransferOperation::BlitImage { src, dst, filter } => ("blit_image", vec![("src", JsonValue::String(src.clone())), ("dst", JsonValue::String(dst.clone())), ("filter", JsonValue::String(format!("{:?}", filter)))]),
+        TransferOperation::FillBuffer { dst, value } => ("fill_buffer", vec![("dst", JsonValue::String(dst.clone())), ("value", JsonValue::Number(*value as f64))]),
+        TransferOperation::GenerateMips { image } => ("generate_mips", vec![("image", JsonValue::String(image.clone()))]),
+    };
+
+    fields.insert(0, ("op", JsonValue::String(op.to_string())));
+
+    JsonValue::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAPH: &str = r#"{
+        "root_layer": "main",
+        "buffers": [{"name": "particles", "size": 1024, "usage": ["STORAGE_BUFFER", "TRANSFER_DST"], "memory_usage": "CpuToGpu"}],
+        "images": [
+            {"name": "hdr", "relative_size": [0.5, 0.5], "format": "R16G16B16A16_SFLOAT", "usage": ["STORAGE", 4], "transient": true},
+            {"name": "albedo", "width": 64, "height": 32, "format": "R8G8B8A8_UNORM", "usage": ["SAMPLED"], "layout": "SHADER_READ_ONLY_OPTIMAL", "mip_levels": 4}
+        ],
+        "layers": [{
+            "name": "main",
+            "present": true,
+            "passes": [
+                {"name": "simulate", "type": "compute", "shader": "simulate.spv", "dispatch": [8, 1, 1],
+                    "descriptors": [{"type": "storage", "name": "particles"}], "accesses": [{"name": "particles", "usage": "Storage", "write": true}]},
+                {"name": "mips", "type": "transfer", "operations": [{"op": "generate_mips", "image": "albedo"}, {"op": "blit_image", "src": "albedo", "dst": "hdr", "filter": "NEAREST"}]}
+            ],
+            "dependencies": [{"src": "simulate", "dst": "mips"}]
+        }],
+        "exported": ["hdr"]
+    }"#;
+
+    fn parse(text: &str) -> std::result::Result<GraphDescription, String> {
+        GraphDescription::from_json(&JsonValue::parse(text).unwrap())
+    }
+
+    #[test]
+    fn parses_a_full_graph() {
+        let graph = parse(GRAPH).unwrap();
+
+        assert_eq!(graph.root_layer.as_deref(), Some("main"));
+        assert_eq!(graph.exported, vec!["hdr"]);
+
+        assert_eq!(graph.buffers[0].size, 1024);
+        assert_eq!(graph.buffers[0].usage, vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST);
+        assert!(matches!(graph.buffers[0].memory_usage, MemoryUsage::CpuToGpu));
+
+        assert!(matches!(graph.images[0].size, ImageSize::Relative(scale, _) if scale == 0.5));
+        assert_eq!(graph.images[0].usage, vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED);
+        assert!(graph.images[0].transient);
+        assert!(matches!(graph.images[1].size, ImageSize::Fixed(64, 32)));
+        assert_eq!(graph.images[1].layout, Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL));
+        assert_eq!(graph.images[1].mip_levels, 4);
+
+        let layer = &graph.layers[0];
+        assert!(layer.present);
+        assert!(matches!(layer.exec, LayerExecution::Main));
+        assert!(matches!(&layer.passes[0], PassDescription::Compute(pass) if matches!(pass.dispatch, DispatchDescription::Fixed(8, 1, 1))));
+        assert!(matches!(&layer.passes[1], PassDescription::Transfer(pass) if pass.operations.len() == 2));
+        assert_eq!(layer.dependencies[0].src, "simulate");
+    }
+
+    #[test]
+    fn saved_graphs_load_back_the_same() {
+        let json = parse(GRAPH).unwrap().to_json();
+
+        assert_eq!(parse(&json.to_string_pretty()).unwrap().to_json(), json);
+    }
+
+    #[test]
+    fn ignores_unknown_fields() {
+        let graph = parse(r#"{"version": 2, "layers": [{"name": "main", "colour": "blue", "passes": [{"name": "fill", "type": "transfer", "comment": "clears", "operations": [{"op": "fill_buffer", "dst": "b", "extra": [1]}]}]}]}"#).unwrap();
+
+        assert_eq!(graph.layers[0].passes.len(), 1);
+    }
+
+    #[test]
+    fn missing_sections_are_empty() {
+        let graph = parse("{}").unwrap();
+
+        assert!(graph.buffers.is_empty() && graph.images.is_empty() && graph.layers.is_empty() && graph.layer_dependencies.is_empty());
+        assert_eq!(graph.root_layer, None);
+    }
+
+    #[test]
+    fn reports_missing_and_mistyped_fields() {
+        assert_eq!(parse(r#"{"buffers": [{"size": 4, "usage": []}]}"#).err().unwrap(), "buffer is missing 'name'");
+        assert_eq!(parse(r#"{"buffers": [{"name": "b", "size": "4", "usage": []}]}"#).err().unwrap(), "buffer 'b' has a 'size' that is not a number");
+        assert_eq!(parse(r#"{"layers": {}}"#).err().unwrap(), "graph has a 'layers' that is not an array");
+        assert_eq!(parse(r#"{"images": [{"name": "i", "relative_size": [1], "format": "R8_UNORM", "usage": []}]}"#).err().unwrap(), "image 'i' has a 'relative_size' that is not an array of 2 numbers");
+    }
+
+    #[test]
+    fn reports_unknown_values() {
+        assert_eq!(parse(r#"{"layers": [{"name": "l", "exec": "Compute"}]}"#).err().unwrap(), "layer 'l' has unknown exec 'Compute', expected \"Main\", \"Async\" or \"Transfer\"");
+        assert!(parse(r#"{"layers": [{"name": "l", "passes": [{"name": "p", "type": "raytracing"}]}]}"#).err().unwrap().contains("pass 'p' in layer 'l' has unknown type 'raytracing'"));
+        assert_eq!(parse(r#"{"buffers": [{"name": "b", "size": 4, "usage": ["NOT_A_FLAG"]}]}"#).err().unwrap(), "buffer 'b' has an unknown 'usage' flag 'NOT_A_FLAG'");
+        assert_eq!(parse(r#"{"images": [{"name": "i", "width": 1, "height": 1, "format": "R9_UNORM", "usage": []}]}"#).err().unwrap(), "image 'i' has an unknown 'format' value 'R9_UNORM'");
+    }
+
+    #[test]
+    fn only_loads_json_files() {
+        assert!(matches!(GraphDescription::load("graph.yaml"), Err(Error::Unsupported(_))));
+    }
+}
@@ -1,15 +1,19 @@
+use std::collections::HashMap;
+
 use ash::vk;
 
 use crate::descriptors::{Descriptors, DescriptorsBuilder};
-use crate::{math::vec::Vec4, layer::Pass};
+use crate::{math::vec::Vec4, layer::{Pass, ResourceAccess, ResourceUsage}};
 use crate::{core::Core, descriptors::CreationReference, renderer_data::RendererData};
 use crate::device::Device;
+use crate::error::{Error, Result};
 use crate::vertex_buffer::{VertexBuffer, VertexAttributes};
 use crate::push_constant::PushConstantBuilder;
 use crate::graphics_pipeline::GraphicsPipeline;
 use crate::framebuffer::Framebuffer;
 use crate::push_constant::PushConstant;
 use crate::image::Image;
+use crate::buffer::Buffer;
 
 #[derive(Copy, Clone)]
 pub struct GraphicsPassDrawInfo {
@@ -39,6 +43,8 @@ pub struct GraphicsPassBuilder<'a, T: VertexAttributes, U> {
     fragment_descriptors_builder: Option<DescriptorsBuilder>,
     with_depth_buffer: bool,
     clear_col: Option<Vec4>,
+    accesses: Vec<ResourceAccess>,
+    error: Option<Error>,
 }
 
 pub struct GraphicsPass {
@@ -56,9 +62,17 @@ pub struct GraphicsPass {
 
     pub clear_values: Vec<vk::ClearValue>,
     pub target_rect: vk::Rect2D,
+    pub targets: Vec<Image>,
+    pub extent: Option<vk::Extent2D>,
+
+    pub accesses: Vec<ResourceAccess>,
 }
 
-impl Pass for GraphicsPass {}
+impl Pass for GraphicsPass {
+    fn accesses(&self) -> &Vec<ResourceAccess> {
+        &self.accesses
+    }
+}
 
 impl GraphicsPassDrawInfo {
     pub fn simple_vertex(vertex_count: usize) -> GraphicsPassDrawInfo {
@@ -138,6 +152,8 @@ impl <'a, T: VertexAttributes, U> GraphicsPassBuilder<'a, T, U> {
             fragment_descriptors_builder: None,
             with_depth_buffer: false,
             clear_col: None,
+            accesses: Vec::new(),
+            error: None,
         }
     }
 
@@ -215,14 +231,22 @@ impl <'a, T: VertexAttributes, U> GraphicsPassBuilder<'a, T, U> {
         self
     }
 
-    pub fn vertex_push_constant<V>(mut self) -> GraphicsPassBuilder<'a, T, U> {
-        self.vertex_push_constant_builder = Some(PushConstantBuilder::new().stage(vk::ShaderStageFlags::VERTEX).size(std::mem::size_of::<V>()));
+    pub fn vertex_push_constant<V>(self) -> GraphicsPassBuilder<'a, T, U> {
+        self.vertex_push_constant_size(std::mem::size_of::<V>())
+    }
+
+    pub fn fragment_push_constant<V>(self) -> GraphicsPassBuilder<'a, T, U> {
+        self.fragment_push_constant_size(std::mem::size_of::<V>())
+    }
+
+    pub fn vertex_push_constant_size(mut self, size: usize) -> GraphicsPassBuilder<'a, T, U> {
+        self.vertex_push_constant_builder = Some(PushConstantBuilder::new().stage(vk::ShaderStageFlags::VERTEX).size(size));
 
         self
     }
 
-    pub fn fragment_push_constant<V>(mut self) -> GraphicsPassBuilder<'a, T, U> {
-        self.fragment_push_constant_builder = Some(PushConstantBuilder::new().stage(vk::ShaderStageFlags::FRAGMENT).size(std::mem::size_of::<V>()));
+    pub fn fragment_push_constant_size(mut self, size: usize) -> GraphicsPassBuilder<'a, T, U> {
+        self.fragment_push_constant_builder = Some(PushConstantBuilder::new().stage(vk::ShaderStageFlags::FRAGMENT).size(size));
 
         self
     }
@@ -239,41 +263,22 @@ impl <'a, T: VertexAttributes, U> GraphicsPassBuilder<'a, T, U> {
         self
     }
 
+    // A missing resource is held onto and returned from build, so the builder can still be chained
     pub fn vertex_descriptors(mut self, create_refs: Vec<CreationReference>, data: &RendererData) -> GraphicsPassBuilder<'a, T, U> {
-        let mut descriptors_builder = DescriptorsBuilder::new()
-            .stage(vk::ShaderStageFlags::VERTEX)
-            .count(data.count);
-
-        for create_ref in create_refs {
-            match create_ref {
-                CreationReference::Uniform(name) => { descriptors_builder = descriptors_builder.add_uniform_simple(data.get_buffers(&name)); },
-                CreationReference::Storage(name) => { descriptors_builder = descriptors_builder.add_storage_simple(data.get_buffers(&name)); },
-                CreationReference::Image(name) => { descriptors_builder = descriptors_builder.add_image_simple(data.get_images(&name)); },
-                CreationReference::Sampler(name) => { descriptors_builder = descriptors_builder.add_sampler_simple(data.get_images(&name)); },
-            }
+        match DescriptorsBuilder::from_references(create_refs, data, vk::ShaderStageFlags::VERTEX) {
+            Ok(descriptors_builder) => self.vertex_descriptors_builder = Some(descriptors_builder),
+            Err(e) => self.error = Some(e),
         }
 
-        self.vertex_descriptors_builder = Some(descriptors_builder);
-        
         self
     }
 
     pub fn fragment_descriptors(mut self, create_refs: Vec<CreationReference>, data: &RendererData) -> GraphicsPassBuilder<'a, T, U> {
-        let mut descriptors_builder = DescriptorsBuilder::new()
-            .stage(vk::ShaderStageFlags::FRAGMENT)
-            .count(data.count);
-
-        for create_ref in create_refs {
-            match create_ref {
-                CreationReference::Uniform(name) => { descriptors_builder = descriptors_builder.add_uniform_simple(data.get_buffers(&name)); },
-                CreationReference::Storage(name) => { descriptors_builder = descriptors_builder.add_storage_simple(data.get_buffers(&name)); },
-                CreationReference::Image(name) => { descriptors_builder = descriptors_builder.add_image_simple(data.get_images(&name)); },
-                CreationReference::Sampler(name) => { descriptors_builder = descriptors_builder.add_sampler_simple(data.get_images(&name)); },
-            }
+        match DescriptorsBuilder::from_references(create_refs, data, vk::ShaderStageFlags::FRAGMENT) {
+            Ok(descriptors_builder) => self.fragment_descriptors_builder = Some(descriptors_builder),
+            Err(e) => self.error = Some(e),
         }
 
-        self.fragment_descriptors_builder = Some(descriptors_builder);
-        
         self
     }
 
@@ -289,20 +294,40 @@ impl <'a, T: VertexAttributes, U> GraphicsPassBuilder<'a, T, U> {
         self
     }
 
-    pub unsafe fn build(self, c: &Core, d: &Device) -> GraphicsPass {
-        GraphicsPass::new(c, d, self.targets.expect("Error: Graphics pass builder has no targets"), self.extent, self.offset, self.verts, self.vertex_indices, self.has_verts, self.indexed, self.resizable_vertex_buffer, self.vertex_descriptors_builder, self.fragment_descriptors_builder, self.vertex_push_constant_builder, self.fragment_push_constant_builder, self.vs.expect("Error: Graphics pass builder has no vertex shader"), self.fs.expect("Error: Graphics pass builder has no fragment shader"), self.with_depth_buffer, self.clear_col, self.draw_infos.unwrap_or(vec![]))
+    pub fn reads(mut self, name: &str, usage: ResourceUsage) -> GraphicsPassBuilder<'a, T, U> {
+        self.accesses.push(ResourceAccess::new(name, usage, false));
+
+        self
+    }
+
+    pub fn writes(mut self, name: &str, usage: ResourceUsage) -> GraphicsPassBuilder<'a, T, U> {
+        self.accesses.push(ResourceAccess::new(name, usage, true));
+
+        self
+    }
+
+    pub unsafe fn build(self, c: &Core, d: &Device) -> Result<GraphicsPass> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        let targets = self.targets.ok_or(Error::IncompleteBuilder("Graphics pass builder has no targets".to_string()))?;
+        let vs = self.vs.ok_or(Error::IncompleteBuilder("Graphics pass builder has no vertex shader".to_string()))?;
+        let fs = self.fs.ok_or(Error::IncompleteBuilder("Graphics pass builder has no fragment shader".to_string()))?;
+
+        GraphicsPass::new(c, d, targets, self.extent, self.offset, self.verts, self.vertex_indices, self.has_verts, self.indexed, self.resizable_vertex_buffer, self.vertex_descriptors_builder, self.fragment_descriptors_builder, self.vertex_push_constant_builder, self.fragment_push_constant_builder, vs, fs, self.with_depth_buffer, self.clear_col, self.draw_infos.unwrap_or(vec![]), self.accesses)
     }
 }
 
 impl GraphicsPass {
-    pub unsafe fn new<T: VertexAttributes, U>(c: &Core, d: &Device, targets: Vec<Image>, extent: Option<vk::Extent2D>, offset: Option<vk::Offset2D>, verts: Option<&Vec<T>>, indices: Option<&Vec<U>>, has_verts: bool, indexed: bool, resizable_vertex_buffer: bool, vertex_descriptors_builder: Option<DescriptorsBuilder>, fragment_descriptors_builder: Option<DescriptorsBuilder>, vertex_push_constant_builder: Option<PushConstantBuilder>, fragment_push_constant_builder: Option<PushConstantBuilder>, vs: &str, fs: &str, with_depth_buffer: bool, clear_col: Option<Vec4>, draw_infos: Vec<GraphicsPassDrawInfo>) -> GraphicsPass {
+    pub unsafe fn new<T: VertexAttributes, U>(c: &Core, d: &Device, targets: Vec<Image>, extent: Option<vk::Extent2D>, offset: Option<vk::Offset2D>, verts: Option<&Vec<T>>, indices: Option<&Vec<U>>, has_verts: bool, indexed: bool, resizable_vertex_buffer: bool, vertex_descriptors_builder: Option<DescriptorsBuilder>, fragment_descriptors_builder: Option<DescriptorsBuilder>, vertex_push_constant_builder: Option<PushConstantBuilder>, fragment_push_constant_builder: Option<PushConstantBuilder>, vs: &str, fs: &str, with_depth_buffer: bool, clear_col: Option<Vec4>, draw_infos: Vec<GraphicsPassDrawInfo>, accesses: Vec<ResourceAccess>) -> Result<GraphicsPass> {
         let vertex_descriptors = match vertex_descriptors_builder {
-            Some(de_b) => Some(de_b.build(c, d)),
+            Some(de_b) => Some(de_b.build(c, d)?),
             None => None
         };
         
         let fragment_descriptors = match fragment_descriptors_builder {
-            Some(de_b) => Some(de_b.build(c, d)),
+            Some(de_b) => Some(de_b.build(c, d)?),
             None => None
         };
 
@@ -317,23 +342,24 @@ impl GraphicsPass {
         };
 
         let vertex_push_constant = match vertex_push_constant_builder {
-            Some(builder) => Some(builder.build()),
+            Some(builder) => Some(builder.build()?),
             None => None
         };
 
         let fragment_push_constant = match fragment_push_constant_builder {
-            Some(builder) => Some(builder.build()),
+            Some(builder) => Some(builder.build()?),
             None => None
         };
 
         let vertex_buffer = match has_verts {
-            true => Some(VertexBuffer::new(c, d, verts, indices, resizable_vertex_buffer)),
+            true => Some(VertexBuffer::new(c, d, verts, indices, resizable_vertex_buffer)?),
             false => None
         };
 
-        let target_extent = match extent {
-            Some(e) => e,
-            None => vk::Extent2D { width: targets[0].width, height: targets[0].height },
+        let target_extent = match (extent, targets.first()) {
+            (Some(e), _) => e,
+            (None, Some(target)) => vk::Extent2D { width: target.width, height: target.height },
+            (None, None) => return Err(Error::IncompleteBuilder("Graphics pass builder has no targets".to_string())),
         };
 
         let offset = match offset {
@@ -343,9 +369,9 @@ impl GraphicsPass {
 
         let target_rect = vk::Rect2D { extent: target_extent, offset };
         
-        let pipeline = GraphicsPipeline::new(c, d, target_rect, vertex_buffer.as_ref(), vertex_descriptor_set_layout, fragment_descriptor_set_layout, vertex_push_constant.as_ref(), fragment_push_constant.as_ref(), vs, fs, targets[0].layout, with_depth_buffer, clear_col.is_some());
+        let pipeline = GraphicsPipeline::new(c, d, target_rect, vertex_buffer.as_ref(), vertex_descriptor_set_layout, fragment_descriptor_set_layout, vertex_push_constant.as_ref(), fragment_push_constant.as_ref(), vs, fs, with_depth_buffer, clear_col.is_some())?;
 
-        let framebuffers = Framebuffer::new_many(d, &pipeline, &targets, extent);
+        let framebuffers = Framebuffer::new_many(d, &pipeline, &targets, extent)?;
 
         let mut clear_values = Vec::<vk::ClearValue>::new();
         
@@ -357,7 +383,7 @@ impl GraphicsPass {
             clear_values.push(vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } });
         }
 
-        GraphicsPass {
+        Ok(GraphicsPass {
             vertex_push_constant,
             fragment_push_constant,
             vertex_descriptors,
@@ -369,14 +395,78 @@ impl GraphicsPass {
             indexed,
             clear_values,
             target_rect,
+            targets,
+            extent,
+            accesses,
+        })
+    }
+
+    // Rebinds the pass to images that were recreated, rebuilding the framebuffers if one of its targets changed
+    pub unsafe fn replace_images(&mut self, c: &Core, d: &Device, data: &RendererData, replaced: &HashMap<vk::Image, Image>) -> Result<()> {
+        if let Some(descriptors) = &mut self.vertex_descriptors {
+            descriptors.replace_images(d, replaced);
         }
+
+        if let Some(descriptors) = &mut self.fragment_descriptors {
+            descriptors.replace_images(d, replaced);
+        }
+
+        if !self.targets.iter().any(|target| replaced.contains_key(&target.image)) {
+            return Ok(());
+        }
+
+        for target in &mut self.targets {
+            if let Some(image) = replaced.get(&target.image) {
+                *target = *image;
+            }
+        }
+
+        // The swapchain can come back with a different number of images, in which case the pass takes on all of them
+        if let Ok(swapchain_images) = data.get_images("swapchain_image") {
+            if swapchain_images.len() != self.targets.len() && swapchain_images.iter().any(|image| image.image == self.targets[0].image) {
+                self.targets = swapchain_images.clone();
+            }
+        }
+
+        // Passes with an explicit extent keep it, otherwise they follow the size of their targets
+        if self.extent.is_none() {
+            self.target_rect.extent = vk::Extent2D { width: self.targets[0].width, height: self.targets[0].height };
+        }
+
+        self.pipeline.resize(c, d, self.target_rect)?;
+
+        for framebuffer in &self.framebuffers {
+            framebuffer.destroy(d);
+        }
+
+        self.framebuffers = Framebuffer::new_many(d, &self.pipeline, &self.targets, self.extent)?;
+
+        Ok(())
+    }
+
+    pub unsafe fn update_vertex_buffer<T: VertexAttributes, U>(&mut self, c: &Core, d: &Device, verts: Option<&Vec<T>>, indices: Option<&Vec<U>>) -> Result<Vec<Buffer>> {
+        let vb = self.vertex_buffer.as_mut().ok_or(Error::Unsupported("No vertex buffer present to be updated".to_string()))?;
+
+        vb.update(c, d, verts, indices)
     }
 
-    pub unsafe fn update_vertex_buffer<T: VertexAttributes, U>(&mut self, c: &Core, d: &Device, verts: Option<&Vec<T>>, indices: Option<&Vec<U>>) {
-        assert!(self.vertex_buffer.is_some(), "Error: No vertex buffer present to be updated");
+    pub unsafe fn destroy(&self, d: &Device) {
+        if let Some(descriptors) = &self.vertex_descriptors {
+            descriptors.destroy(d);
+        }
+
+        if let Some(descriptors) = &self.fragment_descriptors {
+            descriptors.destroy(d);
+        }
 
-        if let Some(vb) = &mut self.vertex_buffer {
-            vb.update(c, d, verts, indices);
+        if let Some(vertex_buffer) = &self.vertex_buffer {
+            vertex_buffer.destroy(d);
         }
+
+        for framebuffer in &self.framebuffers {
+            framebuffer.destroy(d);
+        }
+
+        self.pipeline.destroy(d);
     }
 }
\ No newline at end of file
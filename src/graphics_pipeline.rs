@@ -4,6 +4,7 @@ use ash::vk::{self, RenderPass};
 
 use crate::{core::Core, image::ImageBuilder};
 use crate::device::Device;
+use crate::error::Result;
 use crate::shader::Shader;
 use crate::image::Image;
 use crate::push_constant::PushConstant;
@@ -21,9 +22,9 @@ pub struct GraphicsPipeline {
 }
 
 impl GraphicsPipeline {
-    pub unsafe fn new(c: &Core, d: &Device, target_rect: vk::Rect2D, vertex_buffer: Option<&VertexBuffer>, vertex_descriptor_set_layout: Option<vk::DescriptorSetLayout>, fragment_descriptor_set_layout: Option<vk::DescriptorSetLayout>, vertex_push_constant: Option<&PushConstant>, fragment_push_constant: Option<&PushConstant>, vs: &str, fs: &str, target_layout: vk::ImageLayout, with_depth_buffer: bool, clear_prev: bool) -> GraphicsPipeline {
-        let vert_shader = Shader::new(d, vs, vk::ShaderStageFlags::VERTEX);
-        let frag_shader = Shader::new(d, fs, vk::ShaderStageFlags::FRAGMENT);
+    pub unsafe fn new(c: &Core, d: &Device, target_rect: vk::Rect2D, vertex_buffer: Option<&VertexBuffer>, vertex_descriptor_set_layout: Option<vk::DescriptorSetLayout>, fragment_descriptor_set_layout: Option<vk::DescriptorSetLayout>, vertex_push_constant: Option<&PushConstant>, fragment_push_constant: Option<&PushConstant>, vs: &str, fs: &str, with_depth_buffer: bool, clear_prev: bool) -> Result<GraphicsPipeline> {
+        let vert_shader = Shader::new(d, vs, vk::ShaderStageFlags::VERTEX)?;
+        let frag_shader = Shader::new(d, fs, vk::ShaderStageFlags::FRAGMENT)?;
 
         let shaders = vec![vert_shader, frag_shader];
 
@@ -43,32 +44,31 @@ impl GraphicsPipeline {
         }
 
         let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-        let dynamic_state_ci = vk::PipelineDynamicStateCreateInfo::builder()
+        let dynamic_state_ci = vk::PipelineDynamicStateCreateInfo::default()
             .dynamic_states(&dynamic_states);
 
-        let input_assembly_state_ci = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        let input_assembly_state_ci = vk::PipelineInputAssemblyStateCreateInfo::default()
             .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
             .primitive_restart_enable(false);
 
-        let viewport = vk::Viewport::builder()
+        let viewport = vk::Viewport::default()
             .x(target_rect.offset.x as f32)
             .y(target_rect.offset.y as f32)
             .width(target_rect.extent.width as f32)
             .height(target_rect.extent.height as f32)
             .min_depth(0.0)
-            .max_depth(1.0)
-            .build();
+            .max_depth(1.0);
 
-        let scissor = target_rect;
+        let viewports = [viewport];
+        let scissors = [target_rect];
 
-        let viewport_state_ci = vk::PipelineViewportStateCreateInfo::builder()
+        let viewport_state_ci = vk::PipelineViewportStateCreateInfo::default()
             .viewport_count(1)
             .scissor_count(1)
-            .viewports(&[viewport])
-            .scissors(&[scissor])
-            .build();
+            .viewports(&viewports)
+            .scissors(&scissors);
 
-        let rasterization_state_ci = vk::PipelineRasterizationStateCreateInfo::builder()
+        let rasterization_state_ci = vk::PipelineRasterizationStateCreateInfo::default()
             .depth_clamp_enable(false)
             .rasterizer_discard_enable(false)
             .polygon_mode(vk::PolygonMode::FILL)
@@ -78,17 +78,16 @@ impl GraphicsPipeline {
             //.front_face(vk::FrontFace::CLOCKWISE)
             .depth_bias_enable(false);
 
-        let multisample_state_ci = vk::PipelineMultisampleStateCreateInfo::builder()
+        let multisample_state_ci = vk::PipelineMultisampleStateCreateInfo::default()
             .sample_shading_enable(false)
             .rasterization_samples(vk::SampleCountFlags::TYPE_1);
 
-        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState::builder()
+        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState::default()
             .color_write_mask(
                 vk::ColorComponentFlags::R
               | vk::ColorComponentFlags::G
               | vk::ColorComponentFlags::B
-              | vk::ColorComponentFlags::A
-            )
+              | vk::ColorComponentFlags::A)
             .blend_enable(true)
             .alpha_blend_op(vk::BlendOp::ADD)
             .color_blend_op(vk::BlendOp::ADD)
@@ -96,30 +95,27 @@ impl GraphicsPipeline {
             .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
             .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
             .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-            .build()
+            
         ];
 
-        let color_blend_state_ci = vk::PipelineColorBlendStateCreateInfo::builder()
+        let color_blend_state_ci = vk::PipelineColorBlendStateCreateInfo::default()
             .logic_op_enable(false)
-            .attachments(&color_blend_attachment_states)
-            .build();
+            .attachments(&color_blend_attachment_states);
 
         let mut push_constant_ranges = Vec::<vk::PushConstantRange>::new();
 
         if let Some(push_constant) = vertex_push_constant {
-            push_constant_ranges.push(vk::PushConstantRange::builder()
+            push_constant_ranges.push(vk::PushConstantRange::default()
             .size(push_constant.size as u32)
             .offset(0)
-            .stage_flags(push_constant.stage)
-            .build());
+            .stage_flags(push_constant.stage));
         }
 
         if let Some(push_constant) = fragment_push_constant {
-            push_constant_ranges.push(vk::PushConstantRange::builder()
+            push_constant_ranges.push(vk::PushConstantRange::default()
             .size(push_constant.size as u32)
             .offset(0)
-            .stage_flags(push_constant.stage)
-            .build());
+            .stage_flags(push_constant.stage));
         }
 
         let mut descriptor_set_layouts = Vec::<vk::DescriptorSetLayout>::new();
@@ -139,20 +135,20 @@ impl GraphicsPipeline {
             None => (vec![], vec![])
         };
 
-        let vertex_input_state_ci = vk::PipelineVertexInputStateCreateInfo::builder()
+        let vertex_input_state_ci = vk::PipelineVertexInputStateCreateInfo::default()
             .vertex_attribute_descriptions(&vertex_attribute_descs)
             .vertex_binding_descriptions(&vertex_binding_descs);
 
-        let pipeline_layout_ci = vk::PipelineLayoutCreateInfo::builder()
+        let pipeline_layout_ci = vk::PipelineLayoutCreateInfo::default()
             .set_layouts(&descriptor_set_layouts)
-            .push_constant_ranges(&push_constant_ranges)
-            .build();
+            .push_constant_ranges(&push_constant_ranges);
         
-        let pipeline_layout = d.device.create_pipeline_layout(&pipeline_layout_ci, None).unwrap();
+        let pipeline_layout = d.device.create_pipeline_layout(&pipeline_layout_ci, None)?;
 
-        let load_op = match clear_prev {
-            true => vk::AttachmentLoadOp::CLEAR,
-            false => vk::AttachmentLoadOp::LOAD,
+        // The layer transitions the target into COLOR_ATTACHMENT_OPTIMAL before the pass and out of it afterwards
+        let (load_op, initial_layout) = match clear_prev {
+            true => (vk::AttachmentLoadOp::CLEAR, vk::ImageLayout::UNDEFINED),
+            false => (vk::AttachmentLoadOp::LOAD, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
         };
 
         let mut attachment_descs = vec![vk::AttachmentDescription {
@@ -160,8 +156,8 @@ impl GraphicsPipeline {
             samples: vk::SampleCountFlags::TYPE_1,
             load_op,
             store_op: vk::AttachmentStoreOp::STORE,
-            initial_layout: vk::ImageLayout::UNDEFINED,
-            final_layout: target_layout,
+            initial_layout,
+            final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
             ..Default::default()
         }];
 
@@ -170,7 +166,7 @@ impl GraphicsPipeline {
             layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
         }];
 
-        let mut depth_stencil_state_ci_builder = vk::PipelineDepthStencilStateCreateInfo::builder();
+        let mut depth_stencil_state_ci_builder = vk::PipelineDepthStencilStateCreateInfo::default();
 
         let mut depth_attachment_ref = None;
         let mut depth_image = None;
@@ -183,7 +179,7 @@ impl GraphicsPipeline {
                 .height(target_rect.extent.height)
                 .format(depth_format)
                 .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
-                .build(c, d));
+                .build(c, d)?);
 
             attachment_descs.push(vk::AttachmentDescription {
                 format: depth_format,
@@ -208,16 +204,15 @@ impl GraphicsPipeline {
                 .depth_compare_op(vk::CompareOp::LESS)
         }
 
-        let depth_stencil_state_ci = depth_stencil_state_ci_builder
-            .build();
+        let depth_stencil_state_ci = depth_stencil_state_ci_builder;
 
-        let subpass_description_builder = vk::SubpassDescription::builder()
+        let subpass_description_builder = vk::SubpassDescription::default()
             .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
             .color_attachments(&color_attachment_refs);
 
         let subpass_description = match depth_attachment_ref.as_ref() {
-            Some(depth_ref) => subpass_description_builder.depth_stencil_attachment(depth_ref).build(),
-            None => subpass_description_builder.build(),
+            Some(depth_ref) => subpass_description_builder.depth_stencil_attachment(depth_ref),
+            None => subpass_description_builder,
         };
 
         let mut subpass_dep_stage_mask = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
@@ -228,23 +223,24 @@ impl GraphicsPipeline {
             subpass_dep_access_mask |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
         }
         
-        let subpass_dependency = vk::SubpassDependency::builder()
+        let subpass_dependency = vk::SubpassDependency::default()
             .src_subpass(vk::SUBPASS_EXTERNAL)
             .dst_subpass(0)
             .src_stage_mask(subpass_dep_stage_mask)
             .dst_stage_mask(subpass_dep_stage_mask)
-            .dst_access_mask(subpass_dep_access_mask)
-            .build();
+            .dst_access_mask(subpass_dep_access_mask);
 
-        let render_pass_ci = vk::RenderPassCreateInfo::builder()
+        let subpass_descriptions = [subpass_description];
+        let subpass_dependencies = [subpass_dependency];
+
+        let render_pass_ci = vk::RenderPassCreateInfo::default()
             .attachments(&attachment_descs)
-            .subpasses(&[subpass_description])
-            .dependencies(&[subpass_dependency])
-            .build();
+            .subpasses(&subpass_descriptions)
+            .dependencies(&subpass_dependencies);
 
-        let render_pass = d.device.create_render_pass(&render_pass_ci, None).unwrap();
+        let render_pass = d.device.create_render_pass(&render_pass_ci, None)?;
 
-        let mut pipeline_ci_builder = vk::GraphicsPipelineCreateInfo::builder()
+        let mut pipeline_ci_builder = vk::GraphicsPipelineCreateInfo::default()
             .stages(&shader_stage_cis)
             .input_assembly_state(&input_assembly_state_ci)
             .vertex_input_state(&vertex_input_state_ci)
@@ -262,20 +258,59 @@ impl GraphicsPipeline {
                 .depth_stencil_state(&depth_stencil_state_ci);
         }
     
-        let pipeline_ci = pipeline_ci_builder
-            .build();
+        let pipeline_ci = pipeline_ci_builder;
+
+        let pipeline = d.device.create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_ci], None).map_err(|(_, e)| e)?[0];
 
-        let pipeline = d.device.create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_ci], None).expect("Error creating graphics pipeline")[0];
+        for s in shaders.iter() {
+            s.destroy(d);
+        }
 
-        GraphicsPipeline {
+        Ok(GraphicsPipeline {
             pipeline,
             pipeline_layout,
             render_pass,
 
             viewport,
-            scissor,
+            scissor: target_rect,
 
             depth_image,
+        })
+    }
+
+    // The viewport and scissor are dynamic state, so only the depth image has to be rebuilt for a new target size
+    pub unsafe fn resize(&mut self, c: &Core, d: &Device, target_rect: vk::Rect2D) -> Result<()> {
+        self.viewport = vk::Viewport::default()
+            .x(target_rect.offset.x as f32)
+            .y(target_rect.offset.y as f32)
+            .width(target_rect.extent.width as f32)
+            .height(target_rect.extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0);
+
+        self.scissor = target_rect;
+
+        if let Some(depth_image) = self.depth_image {
+            depth_image.destroy(d);
+
+            self.depth_image = Some(ImageBuilder::new()
+                .width(target_rect.extent.width)
+                .height(target_rect.extent.height)
+                .format(vk::Format::D32_SFLOAT)
+                .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+                .build(c, d)?);
+        }
+
+        Ok(())
+    }
+
+    pub unsafe fn destroy(&self, d: &Device) {
+        d.device.destroy_pipeline(self.pipeline, None);
+        d.device.destroy_pipeline_layout(self.pipeline_layout, None);
+        d.device.destroy_render_pass(self.render_pass, None);
+
+        if let Some(depth_image) = self.depth_image {
+            depth_image.destroy(d);
         }
     }
 }
\ No newline at end of file
@@ -1,10 +1,12 @@
+use std::os::raw::c_void;
+
 use ash::vk;
 
 use crate::buffer::Buffer;
-use crate::commands::Commands;
 use crate::core::Core;
 use crate::device::Device;
-use crate::layer::LayerExecution;
+use crate::allocator::MemoryUsage;
+use crate::error::{Error, Result};
 use crate::sampler::Sampler;
 
 #[derive(Copy, Clone)]
@@ -24,6 +26,12 @@ pub struct ImageBuilder {
     pub layout: Option<vk::ImageLayout>,
     pub pre_allocated_images: Option<Vec<vk::Image>>,
     pub data: Option<Buffer>,
+    pub data_ptr: Option<(*const c_void, usize)>,
+    pub relative_size: Option<(f32, f32)>,
+    pub memory_usage: Option<MemoryUsage>,
+    pub transient: bool,
+    pub concurrent: bool,
+    pub mip_levels: Option<u32>,
 }
 
 
@@ -35,7 +43,10 @@ pub struct Image {
     pub width: u32,
     pub height: u32,
     pub extent: vk::Extent3D,
+    pub format: vk::Format,
+    pub usage: vk::ImageUsageFlags,
     pub layout: vk::ImageLayout,
+    pub mip_levels: u32,
 }
 
 impl ImageData {
@@ -59,6 +70,12 @@ impl ImageBuilder {
             layout: None,
             pre_allocated_images: None,
             data: None,
+            data_ptr: None,
+            relative_size: None,
+            memory_usage: None,
+            transient: false,
+            concurrent: false,
+            mip_levels: None,
         }
     }
 
@@ -109,31 +126,78 @@ impl ImageBuilder {
 
         self
     }
+
+    // Copied in through the staging uploader, so the image can stay device local
+    pub fn data_from_ptr(mut self, data: *const c_void, size: usize) -> ImageBuilder {
+        self.data_ptr = Some((data, size));
+
+        self
+    }
+
+    pub fn memory_usage(mut self, memory_usage: MemoryUsage) -> ImageBuilder {
+        self.memory_usage = Some(memory_usage);
+
+        self
+    }
+
+    // Transient images only hold data within a frame, so once the graph is compiled they can share memory with
+    // other transient images that are never in use at the same time
+    pub fn transient(mut self) -> ImageBuilder {
+        self.transient = true;
+
+        self
+    }
+
+    // Shares the image between the queue families layers run on, so layers on any of them can use it without the graph
+    // transferring ownership between them, at the cost of some access performance on some hardware
+    pub fn concurrent(mut self) -> ImageBuilder {
+        self.concurrent = true;
+
+        self
+    }
+
+    // Only level 0 is written by uploads and render passes, the rest are filled by a transfer pass generating mips.
+    // The count is clamped to the full chain for the image's size
+    pub fn mip_levels(mut self, mip_levels: u32) -> ImageBuilder {
+        self.mip_levels = Some(mip_levels);
+
+        self
+    }
+
+    // Sizes the image as a fraction of the render target, so it can be rebuilt when the target is resized
+    pub fn relative_size(mut self, width_scale: f32, height_scale: f32) -> ImageBuilder {
+        self.relative_size = Some((width_scale, height_scale));
+
+        self
+    }
+
+    pub fn resolve_size(mut self, target_extent: vk::Extent2D) -> ImageBuilder {
+        if let Some((width_scale, height_scale)) = self.relative_size {
+            self.width = Some(((target_extent.width as f32 * width_scale) as u32).max(1));
+            self.height = Some(((target_extent.height as f32 * height_scale) as u32).max(1));
+        }
+
+        self
+    }
     
-    pub unsafe fn build(&self, c: &Core, d: &Device) -> Image {
+    pub unsafe fn build(&self, c: &Core, d: &Device) -> Result<Image> {
         let mut pre_allocated_image: Option<vk::Image> = None;
         if let Some(is) = self.pre_allocated_images.as_ref() {
-            assert!(self.pre_allocated_images.as_ref().unwrap().len() == 1, "Error: Number of image handles given is not 1");
+            if is.len() != 1 {
+                return Err(Error::IncompleteBuilder("Number of image handles given is not 1".to_string()));
+            }
 
             pre_allocated_image = Some(is[0]);
         }
 
-        Image::new(
-            c, d,
-            self.width.expect("Error: Image builder has no specified width"),
-            self.height.expect("Error: Image builder has no specified height"),
-            self.depth,
-            self.usage.expect("Error: Image builder has no specified usage"),
-            self.format.expect("Error: Image builder has no specified format"),
-            self.layout,
-            pre_allocated_image,
-            self.data
-        )
+        self.build_one(c, d, pre_allocated_image)
     }
 
-    pub unsafe fn build_many(&self, c: &Core, d: &Device, count: usize) -> Vec<Image> {
-        if self.pre_allocated_images.is_some() {
-            assert!(self.pre_allocated_images.as_ref().unwrap().len() == count, "Error: Number of image handles given is not equal to count");
+    pub unsafe fn build_many(&self, c: &Core, d: &Device, count: usize) -> Result<Vec<Image>> {
+        if let Some(is) = self.pre_allocated_images.as_ref() {
+            if is.len() != count {
+                return Err(Error::IncompleteBuilder("Number of image handles given is not equal to count".to_string()));
+            }
         }
 
         let mut images = Vec::<Image>::new();
@@ -143,72 +207,183 @@ impl ImageBuilder {
                 pre_allocated_image = Some(is[i]);
             }
 
-            images.push(Image::new(
-                c, d,
-                self.width.expect("Error: Image builder has no specified width"),
-                self.height.expect("Error: Image builder has no specified height"),
-                self.depth,
-                self.usage.expect("Error: Image builder has no specified usage"),
-                self.format.expect("Error: Image builder has no specified format"),
-                self.layout,
-                pre_allocated_image,
-                self.data
-            ));
+            images.push(self.build_one(c, d, pre_allocated_image)?);
+        }
+
+        Ok(images)
+    }
+
+    // Builds one image per builder with all of them bound to the same memory, so writing any one of them leaves the
+    // contents of the others undefined. Nothing is uploaded or transitioned, the images start out UNDEFINED
+    pub unsafe fn build_aliased(_c: &Core, d: &Device, builders: &Vec<ImageBuilder>) -> Result<Vec<Image>> {
+        let mut handles = Vec::<(vk::Image, vk::Extent3D, u32)>::new();
+
+        for builder in builders {
+            let (w, h, u, format) = builder.required()?;
+            let (image, extent, mip_levels) = match Image::create_handle(d, w, h, builder.depth, u, format, builder.concurrent, builder.mip_levels.unwrap_or(1)) {
+                Ok(handle) => handle,
+                Err(e) => {
+                    handles.iter().for_each(|(image, _, _)| d.device.destroy_image(*image, None));
+                    return Err(e);
+                },
+            };
+
+            handles.push((image, extent, mip_levels));
+        }
+
+        let image_handles = handles.iter().map(|(image, _, _)| *image).collect::<Vec<vk::Image>>();
+        let memory_usage = builders.first().and_then(|builder| builder.memory_usage).unwrap_or(MemoryUsage::GpuOnly);
+
+        let allocation = match d.allocator.allocate_aliased_images(&d.device, &image_handles, memory_usage) {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                image_handles.iter().for_each(|&image| d.device.destroy_image(image, None));
+                return Err(e);
+            },
+        };
+
+        let mut images = Vec::<Image>::new();
+        for (builder, (image, extent, mip_levels)) in builders.iter().zip(handles) {
+            let (w, h, u, format) = builder.required()?;
+
+            images.push(Image {
+                image,
+                view: Image::create_view(d, image, format, u, mip_levels)?,
+                memory: Some(allocation.memory),
+                width: w,
+                height: h,
+                extent,
+                format,
+                usage: u,
+                layout: builder.layout.unwrap_or(vk::ImageLayout::UNDEFINED),
+                mip_levels,
+            });
         }
 
-        images
+        Ok(images)
+    }
+
+    fn required(&self) -> Result<(u32, u32, vk::ImageUsageFlags, vk::Format)> {
+        Ok((
+            self.width.ok_or(Error::IncompleteBuilder("Image builder has no specified width".to_string()))?,
+            self.height.ok_or(Error::IncompleteBuilder("Image builder has no specified height".to_string()))?,
+            self.usage.ok_or(Error::IncompleteBuilder("Image builder has no specified usage".to_string()))?,
+            self.format.ok_or(Error::IncompleteBuilder("Image builder has no specified format".to_string()))?,))
+    }
+
+    unsafe fn build_one(&self, c: &Core, d: &Device, pre_allocated_image: Option<vk::Image>) -> Result<Image> {
+        let (w, h, u, format) = self.required()?;
+
+        Image::new(
+            c, d,
+            w,
+            h,
+            self.depth,
+            u,
+            format,
+            self.layout,
+            pre_allocated_image,
+            self.data,
+            self.data_ptr,
+            self.memory_usage.unwrap_or(MemoryUsage::GpuOnly),
+            self.concurrent,
+            self.mip_levels.unwrap_or(1))
     }
 }
 
 impl Image {
-    pub unsafe fn new(c: &Core, d: &Device, w: u32, h: u32, de: Option<u32>, u: vk::ImageUsageFlags, format: vk::Format, layout: Option<vk::ImageLayout>, pre_allocated_image: Option<vk::Image>, data: Option<Buffer>) -> Image {
+    pub unsafe fn new(_c: &Core, d: &Device, w: u32, h: u32, de: Option<u32>, u: vk::ImageUsageFlags, format: vk::Format, layout: Option<vk::ImageLayout>, pre_allocated_image: Option<vk::Image>, data: Option<Buffer>, data_ptr: Option<(*const c_void, usize)>, memory_usage: MemoryUsage, concurrent: bool, mip_levels: u32) -> Result<Image> {
+        let image: vk::Image;
+        let extent: vk::Extent3D;
+        let mut mip_levels = mip_levels;
+        let mut memory: Option<vk::DeviceMemory> = None;
+
+        if let Some(alloced_image) = pre_allocated_image {
+            image = alloced_image;
+            extent = vk::Extent3D { width: w, height: h, depth: de.filter(|&dep| dep > 1).unwrap_or(1) };
+        } else {
+            (image, extent, mip_levels) = Image::create_handle(d, w, h, de, u, format, concurrent, mip_levels)?;
+
+            memory = Some(d.allocator.allocate_image(&d.device, image, memory_usage)?.memory);
+        }
+
+        let image_aspect = Image::usage_aspect(u);
+
+        let view = Image::create_view(d, image, format, u, mip_levels)?;
+
+        // Owned images are moved into their initial layout, and filled, through the uploader so creation can be batched
+        let image_layout = match (data.is_some() || data_ptr.is_some(), layout) {
+            (_, Some(image_layout)) => image_layout,
+            (true, None) => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            (false, None) => vk::ImageLayout::UNDEFINED,
+        };
+
+        if pre_allocated_image.is_none() {
+            let mut uploader = d.uploader.lock().unwrap();
+
+            if let Some(image_data) = data {
+                uploader.copy_buffer_to_image(d, image_data.buffer, 0, image, extent, image_aspect, image_layout)?;
+            } else if let Some((p, size)) = data_ptr {
+                uploader.upload_image(d, image, extent, image_aspect, p, size, image_layout)?;
+            } else if layout.is_some() {
+                uploader.transition_image(d, image, image_aspect, image_layout)?;
+            }
+        }
+
+        Ok(Image {
+            image,
+            view,
+            memory,
+            width: w,
+            height: h,
+            extent,
+            format,
+            usage: u,
+            layout: image_layout,
+            mip_levels,
+        })
+    }
+
+    // Returns the number of mip levels actually created alongside the handle
+    unsafe fn create_handle(d: &Device, w: u32, h: u32, de: Option<u32>, u: vk::ImageUsageFlags, format: vk::Format, concurrent: bool, mip_levels: u32) -> Result<(vk::Image, vk::Extent3D, u32)> {
         let (image_type, depth) = match de {
             Some(dep) if dep > 1 => (vk::ImageType::TYPE_3D, dep),
             _ => (vk::ImageType::TYPE_2D, 1),
         };
         
-        let extent = vk::Extent3D::builder()
+        let extent = vk::Extent3D::default()
             .width(w)
             .height(h)
-            .depth(depth)
-            .build();
+            .depth(depth);
 
-        let mut image: vk::Image;
-        let mut memory: Option<vk::DeviceMemory> = None;
+        // Levels are generated by blitting each from the one above
+        let mip_levels = mip_levels.clamp(1, 32 - w.max(h).max(depth).leading_zeros());
+        let u = if mip_levels > 1 { u | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST } else { u };
 
-        if let Some(alloced_image) = pre_allocated_image {
-            image = alloced_image;
-        } else {
-            let image_ci = vk::ImageCreateInfo::builder()
-                .image_type(image_type)
-                .extent(extent)
-                .mip_levels(1)
-                .array_layers(1)
-                .format(format)
-                .tiling(vk::ImageTiling::OPTIMAL)
-                .initial_layout(vk::ImageLayout::UNDEFINED)
-                .usage(u)
-                .samples(vk::SampleCountFlags::TYPE_1);
-
-            image = d.device.create_image(&image_ci, None).unwrap();
-
-            let memory_requirements = d.device.get_image_memory_requirements(image);
-            let memory_type_index = d.get_memory_type(c, vk::MemoryPropertyFlags::DEVICE_LOCAL, memory_requirements);
-    
-            let memory_alloc_i = vk::MemoryAllocateInfo::builder()
-                .allocation_size(memory_requirements.size)
-                .memory_type_index(memory_type_index as u32);
-    
-            memory = Some(d.device.allocate_memory(&memory_alloc_i, None).unwrap());
-            d.device.bind_image_memory(image, memory.unwrap(), 0).unwrap();
+        let queue_families = d.queue_families();
+
+        let mut image_ci = vk::ImageCreateInfo::default()
+            .image_type(image_type)
+            .extent(extent)
+            .mip_levels(mip_levels)
+            .array_layers(1)
+            .format(format)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(u)
+            .samples(vk::SampleCountFlags::TYPE_1);
+
+        if concurrent && queue_families.len() > 1 {
+            image_ci = image_ci
+                .sharing_mode(vk::SharingMode::CONCURRENT)
+                .queue_family_indices(&queue_families);
         }
 
-        let image_aspect = match u {
-            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT => vk::ImageAspectFlags::DEPTH,
-            _ => vk::ImageAspectFlags::COLOR,
-        };
+        Ok((d.device.create_image(&image_ci, None)?, extent, mip_levels))
+    }
 
-        let view_ci = vk::ImageViewCreateInfo::builder()
+    unsafe fn create_view(d: &Device, image: vk::Image, format: vk::Format, u: vk::ImageUsageFlags, mip_levels: u32) -> Result<vk::ImageView> {
+        let view_ci = vk::ImageViewCreateInfo::default()
             .image(image)
             .view_type(vk::ImageViewType::TYPE_2D)
             .format(format)
@@ -219,103 +394,67 @@ impl Image {
                 a: vk::ComponentSwizzle::A,
            })
             .subresource_range(vk::ImageSubresourceRange {
-                aspect_mask: image_aspect,
+                aspect_mask: Image::usage_aspect(u),
                 base_mip_level: 0,
-                level_count: 1,
+                level_count: mip_levels,
                 base_array_layer: 0,
                 layer_count: 1,
             });
 
-        let view = d.device.create_image_view(&view_ci, None).unwrap();
+        Ok(d.device.create_image_view(&view_ci, None)?)
+    }
 
-        let image_creation_commands;
-        if layout.is_some() || data.is_some() {
-            image_creation_commands = Commands::new(d, d.get_queue(LayerExecution::Main).1, 1, false);
+    fn usage_aspect(u: vk::ImageUsageFlags) -> vk::ImageAspectFlags {
+        match u {
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT => vk::ImageAspectFlags::DEPTH,
+            _ => vk::ImageAspectFlags::COLOR,
+        }
+    }
 
-            // TODO: Customise subresource range
-            image_creation_commands.record_all(d, |i, b| {
-                let mut old_layout = vk::ImageLayout::UNDEFINED;
+    pub fn aspect(&self) -> vk::ImageAspectFlags {
+        match self.format {
+            vk::Format::D16_UNORM | vk::Format::D32_SFLOAT | vk::Format::X8_D24_UNORM_PACK32 => vk::ImageAspectFlags::DEPTH,
+            _ => vk::ImageAspectFlags::COLOR,
+        }
+    }
 
-                if let Some(image_data) = data {
-                    old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-    
-                    let subresource_range = vk::ImageSubresourceRange::builder()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .layer_count(1)
-                        .level_count(1)
-                        .build();
-    
-                    let subresource_layers = vk::ImageSubresourceLayers::builder()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .layer_count(1)
-                        .build();
-    
-                    let barrier = vk::ImageMemoryBarrier::builder()
-                        .image(image)
-                        .old_layout(vk::ImageLayout::UNDEFINED)
-                        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                        .subresource_range(subresource_range)
-                        .build();
-    
-                    let copy_region = vk::BufferImageCopy::builder()
-                        .image_subresource(subresource_layers)
-                        .image_extent(extent)
-                        .build();
-
-                    d.device.cmd_pipeline_barrier(b, vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::TRANSFER, vk::DependencyFlags::empty(), &[], &[], &[barrier]);
-                    d.device.cmd_copy_buffer_to_image(b, image_data.buffer, image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[copy_region]);
-                }
-    
-                if let Some(image_layout) = layout {
-                    if pre_allocated_image.is_none() {
-                        let subresource_range = vk::ImageSubresourceRange::builder()
-                            .aspect_mask(vk::ImageAspectFlags::COLOR)
-                            .layer_count(1)
-                            .level_count(1)
-                            .build();
-        
-                        let barrier = vk::ImageMemoryBarrier::builder()
-                            .image(image)
-                            .old_layout(old_layout)
-                            .new_layout(image_layout)
-                            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                            .subresource_range(subresource_range)
-                            .build();
-
-                        d.device.cmd_pipeline_barrier(b, vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::TRANSFER, vk::DependencyFlags::empty(), &[], &[], &[barrier]);
-                    }
-                }
-            });
-        
-            let submit_is = [vk::SubmitInfo::builder()
-                .command_buffers(&image_creation_commands.buffers)
-                .build()];
+    // Size in bytes of one texel, as laid out when the image is copied to a buffer
+    pub fn texel_size(&self) -> Result<usize> {
+        let size = match self.format {
+            vk::Format::R8_UNORM | vk::Format::R8_SNORM | vk::Format::R8_UINT | vk::Format::R8_SINT | vk::Format::R8_SRGB => 1,
+            vk::Format::R8G8_UNORM | vk::Format::R8G8_SNORM | vk::Format::R8G8_UINT | vk::Format::R8G8_SINT | vk::Format::R8G8_SRGB => 2,
+            vk::Format::R16_UNORM | vk::Format::R16_SNORM | vk::Format::R16_UINT | vk::Format::R16_SINT | vk::Format::R16_SFLOAT | vk::Format::D16_UNORM => 2,
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SNORM | vk::Format::R8G8B8A8_UINT | vk::Format::R8G8B8A8_SINT | vk::Format::R8G8B8A8_SRGB => 4,
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SNORM | vk::Format::B8G8R8A8_UINT | vk::Format::B8G8R8A8_SINT | vk::Format::B8G8R8A8_SRGB => 4,
+            vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::A2R10G10B10_UNORM_PACK32 | vk::Format::B10G11R11_UFLOAT_PACK32 => 4,
+            vk::Format::R16G16_UNORM | vk::Format::R16G16_SFLOAT | vk::Format::R32_UINT | vk::Format::R32_SINT | vk::Format::R32_SFLOAT => 4,
+            vk::Format::D32_SFLOAT | vk::Format::X8_D24_UNORM_PACK32 => 4,
+            vk::Format::R16G16B16A16_UNORM | vk::Format::R16G16B16A16_SFLOAT | vk::Format::R16G16B16A16_UINT | vk::Format::R16G16B16A16_SINT => 8,
+            vk::Format::R32G32_UINT | vk::Format::R32G32_SINT | vk::Format::R32G32_SFLOAT => 8,
+            vk::Format::R32G32B32_UINT | vk::Format::R32G32B32_SINT | vk::Format::R32G32B32_SFLOAT => 12,
+            vk::Format::R32G32B32A32_UINT | vk::Format::R32G32B32A32_SINT | vk::Format::R32G32B32A32_SFLOAT => 16,
+            _ => return Err(Error::UnsupportedFormat(self.format)),
+        };
 
-            d.device.queue_submit(d.get_queue(LayerExecution::Main).0, &submit_is, vk::Fence::null()).unwrap();
-            d.device.queue_wait_idle(d.get_queue(LayerExecution::Main).0).unwrap();
-        }
+        Ok(size)
+    }
 
-        Image {
-            image,
-            view,
-            memory,
-            width: w,
-            height: h,
-            extent,
-            layout: layout.unwrap_or(vk::ImageLayout::UNDEFINED),
+    // Images backed by pre-allocated handles (e.g. the swapchain) only own their view
+    pub unsafe fn destroy(&self, d: &Device) {
+        d.device.destroy_image_view(self.view, None);
+
+        if self.memory.is_some() {
+            d.device.destroy_image(self.image, None);
+            d.allocator.free_image(self.image);
         }
     }
 
-    pub unsafe fn generate_samplers(c: &Core, d: &Device, images: &Vec<Image>) -> Vec<Sampler> {
+    pub unsafe fn generate_samplers(c: &Core, d: &Device, images: &Vec<Image>) -> Result<Vec<Sampler>> {
         let mut samplers = Vec::<Sampler>::new();
         for image in images {
-            samplers.push(Sampler::new(c, d, &ImageData::from_image(image)))
+            samplers.push(Sampler::new(c, d, &ImageData::from_image(image))?)
         }
 
-        samplers
+        Ok(samplers)
     }
 }
\ No newline at end of file
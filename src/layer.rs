@@ -1,20 +1,29 @@
-use ash::vk;
+use std::collections::{HashMap, HashSet};
 
-use crate::{compute_pass::ComputePass, core::Core, renderer_data::{RendererData, ResourceReference}, semaphore::Semaphore, shader::ShaderType, util::graph::Graph, vertex_buffer::{self, VertexAttributes}};
+use ash::vk::{self, Handle};
+
+use crate::{compute_pass::ComputePass, core::Core, renderer_data::{RendererData, ResourceReference}, semaphore::Semaphore, shader::ShaderType, util::graph::{Graph, GraphLabel}, vertex_buffer::{self, VertexAttributes}};
 use crate::device::Device;
+use crate::error::{Error, Result};
 use crate::commands::Commands;
 use crate::graphics_pass::GraphicsPass;
+use crate::descriptors::Descriptors;
+use crate::transfer_pass::{ResolvedTransfer, TransferPass};
+use crate::image::Image;
+use crate::buffer::Buffer;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum LayerExecution {
     Main,
     Async,
+    Transfer,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub enum PassType {
     Compute,
     Graphics,
+    Transfer,
 }
 
 #[derive(Copy, Clone)]
@@ -41,16 +50,90 @@ pub struct LayerDependencyInfo {
     pub stage: vk::PipelineStageFlags,
 }
 
-pub trait Pass {}
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ResourceUsage {
+    Sampled,
+    Storage,
+    Uniform,
+    ColorAttachment,
+    DepthAttachment,
+    Transfer,
+    Vertex,
+    Index,
+}
+
+// A named RendererData resource that a pass reads or writes, used to infer barriers between passes
+#[derive(Clone)]
+pub struct ResourceAccess {
+    pub name: String,
+    pub usage: ResourceUsage,
+    pub write: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum ResourceKey {
+    Buffer(vk::Buffer),
+    Image(vk::Image),
+}
+
+// A pass access resolved to the resource it touches this frame
+type ResolvedAccess = (ResourceKey, Option<Image>, ResourceUsage, bool);
+
+#[derive(Copy, Clone)]
+struct AccessState {
+    access: vk::AccessFlags,
+    stage: vk::PipelineStageFlags,
+    layout: vk::ImageLayout,
+    aspect: vk::ImageAspectFlags,
+    write: bool,
+}
+
+// One pipeline barrier command, planned before anything is recorded
+pub struct Barriers {
+    pub src_stage: vk::PipelineStageFlags,
+    pub dst_stage: vk::PipelineStageFlags,
+    pub memory: Vec<vk::MemoryBarrier<'static>>,
+    pub buffers: Vec<vk::BufferMemoryBarrier<'static>>,
+    pub images: Vec<vk::ImageMemoryBarrier<'static>>,
+}
+
+// A pass along with the barriers around it, which is everything needed to record it independently of the other passes
+pub struct PassRecording {
+    pub pass_ref: PassRef,
+    // Which of a graphics pass's targets and framebuffers it renders to
+    pub target: usize,
+    pub before: Vec<Barriers>,
+    pub after: Vec<Barriers>,
+    pub transfers: Vec<ResolvedTransfer>,
+}
+
+// Everything a layer records in one frame. The barriers at the end hand resources to other queues and the swapchain image
+// to the presentation engine
+pub struct LayerRecording {
+    pub passes: Vec<PassRecording>,
+    pub end: Vec<Barriers>,
+}
+
+// A primary command buffer recorded for one frame in flight and swapchain image. The signature is of the barriers it was
+// recorded with, and is cleared when anything else it recorded changes
+pub struct RecordedCommands {
+    pub buffer: usize,
+    pub signature: Option<Vec<u64>>,
+}
+
+pub trait Pass {
+    fn accesses(&self) -> &Vec<ResourceAccess>;
+}
 
 pub struct LayerSubmitInfo {
     pub wait_semaphores: Vec<vk::Semaphore>,
+    pub wait_values: Vec<u64>,
     pub wait_stages: Vec<vk::PipelineStageFlags>,
     pub signal_semaphores: Vec<vk::Semaphore>,
+    pub signal_values: Vec<u64>,
     pub command_buffers: Vec<vk::CommandBuffer>,
     pub queue: vk::Queue,
     pub fence: vk::Fence,
-    pub submit_i: vk::SubmitInfo,
 }
 
 pub struct Layer {
@@ -61,6 +144,7 @@ pub struct Layer {
 
     pub graphics_passes: Vec<GraphicsPass>,
     pub compute_passes: Vec<ComputePass>,
+    pub transfer_passes: Vec<TransferPass>,
 
     pub pass_graph: Graph<PassRef, Option<PassDependency>>,
 
@@ -68,204 +152,1036 @@ pub struct Layer {
 
     pub semaphore: Semaphore,
 
+    // The value the layer's timeline semaphore is signalled with by its latest submission, unused with binary semaphores
+    pub timeline_value: u64,
+
     pub present: bool,
+
+    // Set when the graph is compiled, culled passes aren't recorded and a layer with every pass culled isn't submitted
+    pub kept_passes: HashSet<String>,
+    pub culled_passes: HashSet<String>,
+    pub culled: bool,
+
+    // Resources this layer hands to another queue family once it is done with them, as planned when the graph is compiled
+    pub queue_releases: Vec<(String, u32)>,
+
+    // Command buffers are only recorded again once the layer changes or the barriers it needs do
+    pub recorded: HashMap<(usize, usize), RecordedCommands>,
+
+    // Set when passes or the dependencies between them change, so the graph is compiled again before the next draw
+    pub changed: bool,
+}
+
+impl ResourceUsage {
+    pub fn access_flags(&self, write: bool) -> vk::AccessFlags {
+        match (self, write) {
+            (ResourceUsage::Sampled, _) => vk::AccessFlags::SHADER_READ,
+            (ResourceUsage::Storage, false) => vk::AccessFlags::SHADER_READ,
+            (ResourceUsage::Storage, true) => vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            (ResourceUsage::Uniform, _) => vk::AccessFlags::UNIFORM_READ,
+            (ResourceUsage::ColorAttachment, false) => vk::AccessFlags::COLOR_ATTACHMENT_READ,
+            (ResourceUsage::ColorAttachment, true) => vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            (ResourceUsage::DepthAttachment, false) => vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
+            (ResourceUsage::DepthAttachment, true) => vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            (ResourceUsage::Transfer, false) => vk::AccessFlags::TRANSFER_READ,
+            (ResourceUsage::Transfer, true) => vk::AccessFlags::TRANSFER_WRITE,
+            (ResourceUsage::Vertex, _) => vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+            (ResourceUsage::Index, _) => vk::AccessFlags::INDEX_READ,
+        }
+    }
+
+    pub fn stage_flags(&self, pass_type: PassType) -> vk::PipelineStageFlags {
+        match (self, pass_type) {
+            (ResourceUsage::Sampled | ResourceUsage::Storage | ResourceUsage::Uniform, PassType::Compute) => vk::PipelineStageFlags::COMPUTE_SHADER,
+            (ResourceUsage::Sampled | ResourceUsage::Storage | ResourceUsage::Uniform, PassType::Graphics) => vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            (ResourceUsage::Sampled | ResourceUsage::Storage | ResourceUsage::Uniform, PassType::Transfer) => vk::PipelineStageFlags::TRANSFER,
+            (ResourceUsage::ColorAttachment, _) => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            (ResourceUsage::DepthAttachment, _) => vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            (ResourceUsage::Transfer, _) => vk::PipelineStageFlags::TRANSFER,
+            (ResourceUsage::Vertex | ResourceUsage::Index, _) => vk::PipelineStageFlags::VERTEX_INPUT,
+        }
+    }
+
+    // Images created in GENERAL are sampled in GENERAL, so descriptors written before any transition stay valid
+    pub fn image_layout(&self, write: bool, created: vk::ImageLayout) -> vk::ImageLayout {
+        match (self, write) {
+            (ResourceUsage::Sampled, _) if created == vk::ImageLayout::GENERAL => vk::ImageLayout::GENERAL,
+            (ResourceUsage::Sampled, _) => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            (ResourceUsage::Storage, _) => vk::ImageLayout::GENERAL,
+            (ResourceUsage::ColorAttachment, _) => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            (ResourceUsage::DepthAttachment, _) => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            (ResourceUsage::Transfer, false) => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            (ResourceUsage::Transfer, true) => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            (ResourceUsage::Uniform | ResourceUsage::Vertex | ResourceUsage::Index, _) => vk::ImageLayout::GENERAL,
+        }
+    }
+
+    pub fn aspect_mask(&self) -> vk::ImageAspectFlags {
+        match self {
+            ResourceUsage::DepthAttachment => vk::ImageAspectFlags::DEPTH,
+            _ => vk::ImageAspectFlags::COLOR,
+        }
+    }
+}
+
+impl GraphLabel for PassRef {
+    fn label(&self) -> String {
+        match self.pass_type {
+            PassType::Compute => "compute".to_string(),
+            PassType::Graphics => "graphics".to_string(),
+            PassType::Transfer => "transfer".to_string(),
+        }
+    }
+}
+
+impl GraphLabel for PassDependency {
+    fn label(&self) -> String {
+        let resource = match self.resource {
+            ResourceReference::Buffer(index) => format!("buffer {}", index),
+            ResourceReference::Image(index) => format!("image {}", index),
+        };
+
+        format!("{}\n{}", resource, self.masks_label())
+    }
+}
+
+impl PassDependency {
+    pub fn masks_label(&self) -> String {
+        format!("{:?} -> {:?}\n{:?} -> {:?}", self.src_access, self.dst_access, self.src_stage, self.dst_stage)
+    }
+}
+
+impl GraphLabel for LayerDependencyInfo {
+    fn label(&self) -> String {
+        format!("{:?}", self.stage)
+    }
+}
+
+impl Barriers {
+    pub fn new(src_stage: vk::PipelineStageFlags, dst_stage: vk::PipelineStageFlags) -> Barriers {
+        Barriers {
+            src_stage,
+            dst_stage,
+            memory: Vec::new(),
+            buffers: Vec::new(),
+            images: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_empty() && self.buffers.is_empty() && self.images.is_empty()
+    }
+
+    pub unsafe fn record(&self, d: &Device, b: vk::CommandBuffer) {
+        d.device.cmd_pipeline_barrier(b, self.src_stage, self.dst_stage, vk::DependencyFlags::empty(), &self.memory, &self.buffers, &self.images);
+    }
+
+    fn signature(&self, signature: &mut Vec<u64>) {
+        signature.extend([self.src_stage.as_raw() as u64, self.dst_stage.as_raw() as u64]);
+
+        for barrier in &self.memory {
+            signature.extend([barrier.src_access_mask.as_raw() as u64, barrier.dst_access_mask.as_raw() as u64]);
+        }
+
+        for barrier in &self.buffers {
+            signature.extend([barrier.buffer.as_raw(), barrier.src_access_mask.as_raw() as u64, barrier.dst_access_mask.as_raw() as u64, barrier.src_queue_family_index as u64, barrier.dst_queue_family_index as u64]);
+        }
+
+        for barrier in &self.images {
+            signature.extend([barrier.image.as_raw(), barrier.src_access_mask.as_raw() as u64, barrier.dst_access_mask.as_raw() as u64, barrier.src_queue_family_index as u64, barrier.dst_queue_family_index as u64]);
+            signature.extend([barrier.old_layout.as_raw() as u64, barrier.new_layout.as_raw() as u64, barrier.subresource_range.aspect_mask.as_raw() as u64]);
+        }
+    }
+}
+
+impl LayerRecording {
+    // Everything that can differ between two plans of an unchanged layer, which is the barriers that depend on where the
+    // layers before it left each resource
+    pub fn signature(&self) -> Vec<u64> {
+        let mut signature = Vec::<u64>::new();
+
+        for pass in &self.passes {
+            pass.before.iter().for_each(|barriers| barriers.signature(&mut signature));
+            signature.push(u64::MAX);
+            pass.after.iter().for_each(|barriers| barriers.signature(&mut signature));
+            signature.push(u64::MAX);
+        }
+
+        self.end.iter().for_each(|barriers| barriers.signature(&mut signature));
+
+        signature
+    }
+}
+
+impl ResourceAccess {
+    pub fn new(name: &str, usage: ResourceUsage, write: bool) -> ResourceAccess {
+        ResourceAccess {
+            name: name.to_string(),
+            usage,
+            write,
+        }
+    }
 }
 
 impl Layer {
-    pub unsafe fn new(c: &Core, d: &Device, count: usize, present: bool, exec: LayerExecution) -> Layer {
-        let commands = Commands::new(d, d.get_queue(exec).1, count, false);
-        let semaphore = Semaphore::new(d);
+    pub unsafe fn new(c: &Core, d: &Device, count: usize, present: bool, exec: LayerExecution) -> Result<Layer> {
+        let commands = Commands::new(d, d.get_queue(exec).1, count, false)?;
+        let semaphore = match d.timeline_semaphores {
+            true => Semaphore::new_timeline(d, 0)?,
+            false => Semaphore::new(d)?,
+        };
 
-        Layer {
+        Ok(Layer {
             count,
             commands,
             exec,
             graphics_passes: Vec::new(),
             compute_passes: Vec::new(),
+            transfer_passes: Vec::new(),
             pass_graph: Graph::new(),
             root_pass: None,
             semaphore,
+            timeline_value: 0,
             present,
-        }
+            kept_passes: HashSet::new(),
+            culled_passes: HashSet::new(),
+            culled: false,
+            queue_releases: Vec::new(),
+            recorded: HashMap::new(),
+            changed: false,
+        })
     }
 
-    pub unsafe fn add_compute_pass(&mut self, name: &str, pass: ComputePass) {
+    // Passes are only kept once their name has been added to the pass graph, so a duplicate name leaves the layer as it was
+    pub unsafe fn add_compute_pass(&mut self, name: &str, pass: ComputePass) -> Result<()> {
+        self.pass_graph.add_node(name, PassRef { pass_type: PassType::Compute, index: self.compute_passes.len() })?;
         self.compute_passes.push(pass);
-        self.pass_graph.add_node(name, PassRef { pass_type: PassType::Compute, index: self.compute_passes.len() - 1 });
+        self.changed = true;
+
+        Ok(())
     }
 
-    pub unsafe fn add_graphics_pass(&mut self, name: &str, pass: GraphicsPass) {
+    pub unsafe fn add_graphics_pass(&mut self, name: &str, pass: GraphicsPass) -> Result<()> {
+        self.pass_graph.add_node(name, PassRef { pass_type: PassType::Graphics, index: self.graphics_passes.len() })?;
         self.graphics_passes.push(pass);
-        self.pass_graph.add_node(name, PassRef { pass_type: PassType::Graphics, index: self.graphics_passes.len() - 1 });
+        self.changed = true;
+
+        Ok(())
     }
 
-    pub fn add_pass_dependency(&mut self, src_name: &str, dst_name: &str, dep: Option<PassDependency>) {
-        self.pass_graph.add_edge(src_name, dst_name, dep);
+    pub fn add_transfer_pass(&mut self, name: &str, pass: TransferPass) -> Result<()> {
+        self.pass_graph.add_node(name, PassRef { pass_type: PassType::Transfer, index: self.transfer_passes.len() })?;
+        self.transfer_passes.push(pass);
+        self.changed = true;
+
+        Ok(())
+    }
+
+    pub fn add_pass_dependency(&mut self, src_name: &str, dst_name: &str, dep: Option<PassDependency>) -> Result<()> {
+        self.pass_graph.add_edge(src_name, dst_name, dep).map_err(Layer::missing_pass)?;
+        self.changed = true;
+
+        Ok(())
     }
 
     pub fn set_root_pass(&mut self, name: &str) {
         self.pass_graph.set_root(name.to_string());
+        self.changed = true;
+    }
+
+    // Kept passes are recorded even when nothing reads what they write, for passes with side effects the graph can't see
+    pub fn keep_pass(&mut self, name: &str) -> Result<()> {
+        self.get_pass_ref(name)?;
+        self.kept_passes.insert(name.to_string());
+        self.changed = true;
+
+        Ok(())
+    }
+
+    // The pass graph only knows about nodes, so lookups into it are reported as missing passes
+    fn missing_pass(e: Error) -> Error {
+        match e {
+            Error::MissingNode(name) => Error::MissingPass(name),
+            e => e,
+        }
+    }
+
+    fn get_pass_ref(&self, name: &str) -> Result<PassRef> {
+        Ok(self.pass_graph.get_node(name).map_err(Layer::missing_pass)?.data)
+    }
+
+    pub fn get_compute_pass(&self, name: &str) -> Result<&ComputePass> {
+        Ok(&self.compute_passes[self.get_pass_ref(name)?.index])
+    }
+
+    pub fn get_graphics_pass(&self, name: &str) -> Result<&GraphicsPass> {
+        Ok(&self.graphics_passes[self.get_pass_ref(name)?.index])
+    }
+
+    pub fn get_transfer_pass(&self, name: &str) -> Result<&TransferPass> {
+        Ok(&self.transfer_passes[self.get_pass_ref(name)?.index])
     }
 
-    pub fn get_compute_pass(&self, name: &str) -> &ComputePass {
-        &self.compute_passes[self.pass_graph.get_node(name).data.index]
+    // Anything reached through a mutable pass may change what it records
+    pub fn get_compute_pass_mut(&mut self, name: &str) -> Result<&mut ComputePass> {
+        self.invalidate();
+        let index = self.get_pass_ref(name)?.index;
+        Ok(&mut self.compute_passes[index])
     }
 
-    pub fn get_graphics_pass(&self, name: &str) -> &GraphicsPass {
-        &self.graphics_passes[self.pass_graph.get_node(name).data.index]
+    pub fn get_graphics_pass_mut(&mut self, name: &str) -> Result<&mut GraphicsPass> {
+        self.invalidate();
+        let index = self.get_pass_ref(name)?.index;
+        Ok(&mut self.graphics_passes[index])
     }
 
-    pub fn get_compute_pass_mut(&mut self, name: &str) -> &mut ComputePass {
-        &mut self.compute_passes[self.pass_graph.get_node(name).data.index]
+    pub unsafe fn fill_compute_push_constant<T>(&mut self, name: &str, data: &T) -> Result<()> {
+        self.get_compute_pass_mut(name)?.push_constant.as_mut().ok_or(Error::Unsupported(format!("Compute pass '{}' has no push constant to fill", name)))?.set_data(data);
+
+        Ok(())
+    }
+
+    pub unsafe fn fill_vertex_push_constant<T>(&mut self, name: &str, data: &T) -> Result<()> {
+        self.get_graphics_pass_mut(name)?.vertex_push_constant.as_mut().ok_or(Error::Unsupported(format!("Graphics pass '{}' has no vertex push constant to fill", name)))?.set_data(data);
+
+        Ok(())
     }
 
-    pub fn get_graphics_pass_mut(&mut self, name: &str) -> &mut GraphicsPass {
-        &mut self.graphics_passes[self.pass_graph.get_node(name).data.index]
+    pub unsafe fn fill_fragment_push_constant<T>(&mut self, name: &str, data: &T) -> Result<()> {
+        self.get_graphics_pass_mut(name)?.fragment_push_constant.as_mut().ok_or(Error::Unsupported(format!("Graphics pass '{}' has no fragment push constant to fill", name)))?.set_data(data);
+
+        Ok(())
     }
 
-    pub unsafe fn fill_compute_push_constant<T>(&mut self, name: &str, data: &T) {
-        self.get_compute_pass_mut(name).push_constant.as_mut().expect("Error: Graphics pass has no vertex push constant to fill").set_data(data);
+    pub unsafe fn update_vertex_buffer<T: VertexAttributes, U>(&mut self, name: &str, c: &Core, d: &Device, verts: Option<&Vec<T>>, indices: Option<&Vec<U>>) -> Result<Vec<Buffer>> {
+        self.get_graphics_pass_mut(name)?.update_vertex_buffer(c, d, verts, indices)
     }
 
-    pub unsafe fn fill_vertex_push_constant<T>(&mut self, name: &str, data: &T) {
-        self.get_graphics_pass_mut(name).vertex_push_constant.as_mut().expect("Error: Graphics pass has no vertex push constant to fill").set_data(data);
+    pub unsafe fn record_one(&mut self, d: &Device, resources: &mut RendererData, i: usize, present_index: usize) -> Result<()> {
+        let recording = self.plan(d, resources, i, present_index)?;
+
+        if !self.is_recorded(&recording, i, present_index) {
+            self.record_planned(d, &recording, None, i, present_index)?;
+        }
+
+        Ok(())
+    }
+
+    // Marks every recorded command buffer as out of date, so each is recorded again the next time it's used
+    pub fn invalidate(&mut self) {
+        for recorded in self.recorded.values_mut() {
+            recorded.signature = None;
+        }
     }
 
-    pub unsafe fn fill_fragment_push_constant<T>(&mut self, name: &str, data: &T) {
-        self.get_graphics_pass_mut(name).fragment_push_constant.as_mut().expect("Error: Graphics pass has no fragment push constant to fill").set_data(data);
+    // Whether the command buffer for this frame in flight and swapchain image was recorded with the same barriers and
+    // hasn't been invalidated since
+    pub fn is_recorded(&self, recording: &LayerRecording, i: usize, present_index: usize) -> bool {
+        self.recorded.get(&(i, present_index)).and_then(|recorded| recorded.signature.as_ref()).is_some_and(|signature| *signature == recording.signature())
     }
 
-    pub unsafe fn update_vertex_buffer<T: VertexAttributes, U>(&mut self, name: &str, c: &Core, d: &Device, verts: Option<&Vec<T>>, indices: Option<&Vec<U>>) {
-        self.get_graphics_pass_mut(name).update_vertex_buffer(c, d, verts, indices)
+    pub fn command_buffer(&self, i: usize, present_index: usize) -> Option<vk::CommandBuffer> {
+        self.recorded.get(&(i, present_index)).map(|recorded| self.commands.buffers[recorded.buffer])
     }
 
-    pub unsafe fn record_one(&self, d: &Device, resources: &RendererData, i: usize, present_index: usize) {
-        let mut dependencies = self.pass_graph.breadth_first_backwards(None);
-        dependencies.reverse();
+    // Works out every barrier the layer needs this frame, updating the tracked image layouts as it goes. This has to
+    // happen in submission order, while the passes themselves can then be recorded in any order
+    pub fn plan(&self, d: &Device, resources: &mut RendererData, i: usize, present_index: usize) -> Result<LayerRecording> {
+        let dependencies = self.pass_graph.topological_order(None)?.into_iter().filter(|node| !self.culled_passes.contains(&node.name)).collect::<Vec<_>>();
+
+        // Names are resolved up front so a missing resource is reported before anything is recorded
+        let pass_accesses = dependencies.iter().map(|dependency| {
+            self.resolve_accesses(resources, dependency.data, i, present_index)
+        }).collect::<Result<Vec<_>>>()?;
+
+        let transfers = dependencies.iter().map(|dependency| match dependency.data.pass_type {
+            PassType::Transfer => self.transfer_passes[dependency.data.index].resolve(resources, i, present_index),
+            _ => Ok(Vec::new()),
+        }).collect::<Result<Vec<_>>>()?;
+
+        let releases = self.queue_releases.iter().map(|(name, family)| {
+            let (key, image) = Layer::resolve_resource(resources, name, i, present_index)?;
+            Ok((key, image, *family))
+        }).collect::<Result<Vec<_>>>()?;
+
+        let present_image = match self.present {
+            true => Some(resources.get_images("swapchain_image")?[present_index]),
+            false => None,
+        };
+
+        let mut access_states = HashMap::<ResourceKey, AccessState>::new();
+        let mut passes = Vec::<PassRecording>::new();
+
+        for ((dependency, accesses), transfers) in dependencies.iter().zip(pass_accesses).zip(transfers) {
+            let pass_ref = dependency.data;
+
+            let mut accesses = accesses;
+            let mut target_index = 0;
+
+            // Graphics passes always write their target, whether or not it was declared
+            if pass_ref.pass_type == PassType::Graphics {
+                let targets = &self.graphics_passes[pass_ref.index].targets;
+                target_index = Layer::target_index(resources, targets, i, present_index);
+
+                let target = targets[target_index];
+                accesses.push((ResourceKey::Image(target.image), Some(target), ResourceUsage::ColorAttachment, true));
+            }
 
-        self.commands.record_one(d, i, |b| {
-            for dependency in &dependencies {
-                let pass_ref = dependency.data;
+            passes.push(PassRecording {
+                pass_ref,
+                target: target_index,
+                before: self.plan_access_barriers(d, resources, &accesses, pass_ref.pass_type, &mut access_states),
+                after: self.plan_dependency_barriers(resources, &dependency.name, i),
+                transfers,
+            });
+        }
+
+        let mut end = self.plan_queue_releases(d, resources, &access_states, &releases);
+
+        if let Some(image) = present_image {
+            end.extend(self.plan_present_barrier(resources, &access_states, image));
+        }
+
+        Ok(LayerRecording {
+            passes,
+            end,
+        })
+    }
+
+    // Records the layer's primary command buffer for this frame in flight and swapchain image. Passes are recorded inline,
+    // or executed from the secondary command buffer recorded for each of them when those are given
+    pub unsafe fn record_planned(&mut self, d: &Device, recording: &LayerRecording, secondaries: Option<&Vec<vk::CommandBuffer>>, i: usize, present_index: usize) -> Result<()> {
+        let contents = match secondaries {
+            Some(_) => vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+            None => vk::SubpassContents::INLINE,
+        };
+
+        // The first frames in flight use the buffers the layer was created with
+        let buffer = match self.recorded.get(&(i, present_index)) {
+            Some(recorded) => recorded.buffer,
+            None if self.recorded.len() < self.commands.buffers.len() => self.recorded.len(),
+            None => self.commands.add_buffer(d)?,
+        };
+
+        self.commands.record_one(d, buffer, |b| {
+            for (k, pass) in recording.passes.iter().enumerate() {
+                pass.before.iter().for_each(|barriers| barriers.record(d, b));
+
+                let graphics_pass = match pass.pass_ref.pass_type {
+                    PassType::Graphics => Some(&self.graphics_passes[pass.pass_ref.index]),
+                    _ => None,
+                };
+
+                if let Some(graphics_pass) = graphics_pass {
+                    let render_pass_bi = vk::RenderPassBeginInfo::default()
+                        .render_pass(graphics_pass.pipeline.render_pass)
+                        .framebuffer(graphics_pass.framebuffers[pass.target].framebuffer)
+                        .render_area(graphics_pass.target_rect)
+                        .clear_values(&graphics_pass.clear_values);
+
+                    d.device.cmd_begin_render_pass(b, &render_pass_bi, contents);
+                }
+
+                match secondaries {
+                    Some(secondaries) => d.device.cmd_execute_commands(b, &[secondaries[k]]),
+                    None => self.record_pass(d, b, pass, i),
+                }
+
+                if graphics_pass.is_some() {
+                    d.device.cmd_end_render_pass(b);
+                }
+
+                pass.after.iter().for_each(|barriers| barriers.record(d, b));
+            }
+
+            recording.end.iter().for_each(|barriers| barriers.record(d, b));
+        })?;
+
+        self.recorded.insert((i, present_index), RecordedCommands {
+            buffer,
+            signature: Some(recording.signature()),
+        });
+
+        Ok(())
+    }
+
+    // Records one pass into a secondary command buffer, which may happen on any thread. Graphics passes continue the
+    // render pass begun in the primary command buffer. Secondaries are kept for as long as the primary executing them
+    pub unsafe fn record_secondary(&self, d: &Device, b: vk::CommandBuffer, pass: &PassRecording, i: usize) -> Result<()> {
+        let mut inheritance_i = vk::CommandBufferInheritanceInfo::default();
+        let mut flags = vk::CommandBufferUsageFlags::empty();
+
+        if pass.pass_ref.pass_type == PassType::Graphics {
+            let graphics_pass = &self.graphics_passes[pass.pass_ref.index];
+
+            inheritance_i = inheritance_i
+                .render_pass(graphics_pass.pipeline.render_pass)
+                .subpass(0)
+                .framebuffer(graphics_pass.framebuffers[pass.target].framebuffer);
+            flags |= vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE;
+        }
+
+        let buffer_bi = vk::CommandBufferBeginInfo::default()
+            .flags(flags)
+            .inheritance_info(&inheritance_i);
+
+        d.device.begin_command_buffer(b, &buffer_bi)?;
+
+        self.record_pass(d, b, pass, i);
+
+        d.device.end_command_buffer(b)?;
+
+        Ok(())
+    }
+
+    // The commands of the pass itself, with graphics passes recorded inside their already begun render pass
+    unsafe fn record_pass(&self, d: &Device, b: vk::CommandBuffer, pass: &PassRecording, i: usize) {
+        let pass_ref = pass.pass_ref;
+
+        match pass_ref.pass_type {
+            PassType::Compute => {
+                let pass = &self.compute_passes[pass_ref.index];
+
+                d.device.cmd_bind_pipeline(b, vk::PipelineBindPoint::COMPUTE, pass.pipeline.pipeline);
+
+                if let Some(push_constant) = &pass.push_constant {
+                    d.device.cmd_push_constants(b, pass.pipeline.pipeline_layout, push_constant.stage, 0, &push_constant.data);
+                }
+
+                if let Some(descriptors) = &pass.descriptors {
+                    descriptors.bind(d, &b, vk::PipelineBindPoint::COMPUTE, &pass.pipeline.pipeline_layout, i);
+                }
 
-                match pass_ref.pass_type {
-                    PassType::Compute => {
-                        let pass = &self.compute_passes[pass_ref.index];
-                        
-                        d.device.cmd_bind_pipeline(b, vk::PipelineBindPoint::COMPUTE, pass.pipeline.pipeline);
-        
-                        if pass.push_constant.is_some() {
-                            d.device.cmd_push_constants(b, pass.pipeline.pipeline_layout, pass.push_constant.as_ref().unwrap().stage, 0, &pass.push_constant.as_ref().unwrap().data);
-                        }
-        
-                        if pass.descriptors.is_some() {
-                            let descriptors = pass.descriptors.as_ref().unwrap();
-                            descriptors.bind(d, &b, vk::PipelineBindPoint::COMPUTE, &pass.pipeline.pipeline_layout, i);
-                        }
-        
-                        d.device.cmd_dispatch(b, pass.dispatch_info.x, pass.dispatch_info.y, pass.dispatch_info.z);
+                d.device.cmd_dispatch(b, pass.dispatch_info.x, pass.dispatch_info.y, pass.dispatch_info.z);
+            },
+            PassType::Graphics => {
+                let pass = &self.graphics_passes[pass_ref.index];
+
+                d.device.cmd_bind_pipeline(b, vk::PipelineBindPoint::GRAPHICS, pass.pipeline.pipeline);
+
+                if let Some(push_constant) = &pass.vertex_push_constant {
+                    d.device.cmd_push_constants(b, pass.pipeline.pipeline_layout, push_constant.stage, 0, &push_constant.data);
+                }
+
+                if let Some(push_constant) = &pass.fragment_push_constant {
+                    d.device.cmd_push_constants(b, pass.pipeline.pipeline_layout, push_constant.stage, 0, &push_constant.data);
+                }
+
+                if let Some(descriptors) = &pass.vertex_descriptors {
+                    descriptors.bind(d, &b, vk::PipelineBindPoint::GRAPHICS, &pass.pipeline.pipeline_layout, i);
+                }
+
+                if let Some(descriptors) = &pass.fragment_descriptors {
+                    descriptors.bind(d, &b, vk::PipelineBindPoint::GRAPHICS, &pass.pipeline.pipeline_layout, i);
+                }
+
+                d.device.cmd_set_viewport(b, 0, &[pass.pipeline.viewport]);
+                d.device.cmd_set_scissor(b, 0, &[pass.pipeline.scissor]);
+
+                if let Some(vbw) = pass.vertex_buffer.as_ref() {
+                    if let Some(vb) = vbw.vertex_buffer {
+                        d.device.cmd_bind_vertex_buffers(b, 0, &[vb.buffer], &[0]);
+                    }
+
+                    if let (true, Some(index_buffer), Some(index_size)) = (pass.indexed, vbw.index_buffer, vbw.index_size) {
+                        d.device.cmd_bind_index_buffer(b, index_buffer.buffer, 0, index_size);
+                    }
+                }
+
+                if pass.indexed {
+                    pass.draw_infos.iter().for_each(|draw_info| {
+                        d.device.cmd_draw_indexed(b, draw_info.index_count, draw_info.instance_count, draw_info.first_vertex, draw_info.vertex_offset, draw_info.first_instance);
+                    });
+
+                } else {
+                    pass.draw_infos.iter().for_each(|draw_info| {
+                        d.device.cmd_draw(b, draw_info.vertex_count, draw_info.instance_count, draw_info.first_vertex, draw_info.first_instance);
+                    });
+                }
+            },
+            PassType::Transfer => TransferPass::record(d, b, &pass.transfers),
+        }
+    }
+
+    // Explicit dependencies out of a pass are placed straight after it
+    fn plan_dependency_barriers(&self, resources: &RendererData, name: &str, i: usize) -> Vec<Barriers> {
+        let mut planned = Vec::<Barriers>::new();
+
+        for dependant_edge in self.pass_graph.get_next_edges(name) {
+            if let Some(dependant_info) = dependant_edge.info {
+                let mut barriers = Barriers::new(dependant_info.src_stage, dependant_info.dst_stage);
+
+                match dependant_info.resource {
+                    ResourceReference::Buffer(_) => {
+                        let memory_barrier = vk::MemoryBarrier::default()
+                            .src_access_mask(dependant_info.src_access)
+                            .dst_access_mask(dependant_info.dst_access);
+
+                        barriers.memory.push(memory_barrier);
                     },
-                    PassType::Graphics => {
-                        let pass = &self.graphics_passes[pass_ref.index];
-
-                        let render_pass_bi = vk::RenderPassBeginInfo::builder()
-                            .render_pass(pass.pipeline.render_pass)
-                            .framebuffer(pass.framebuffers[present_index].framebuffer)
-                            .render_area(pass.target_rect)
-                            .clear_values(&pass.clear_values);
-
-                        if let Some(push_constant) = &pass.vertex_push_constant {
-                            d.device.cmd_push_constants(b, pass.pipeline.pipeline_layout, push_constant.stage, 0, &push_constant.data);
-                        }
-
-                        if let Some(push_constant) = &pass.fragment_push_constant {
-                            d.device.cmd_push_constants(b, pass.pipeline.pipeline_layout, push_constant.stage, 0, &push_constant.data);
-                        }
-
-                        if let Some(descriptors) = &pass.vertex_descriptors {
-                            descriptors.bind(d, &b, vk::PipelineBindPoint::GRAPHICS, &pass.pipeline.pipeline_layout, i);
-                        }
-
-                        if let Some(descriptors) = &pass.fragment_descriptors {
-                            descriptors.bind(d, &b, vk::PipelineBindPoint::GRAPHICS, &pass.pipeline.pipeline_layout, i);
-                        }
-                        
-                        d.device.cmd_begin_render_pass(b, &render_pass_bi, vk::SubpassContents::INLINE);
-
-                        d.device.cmd_bind_pipeline(b, vk::PipelineBindPoint::GRAPHICS, pass.pipeline.pipeline);
-                        
-                        d.device.cmd_set_viewport(b, 0, &[pass.pipeline.viewport]);
-                        d.device.cmd_set_scissor(b, 0, &[pass.pipeline.scissor]);
-
-                        if let Some(vbw) = pass.vertex_buffer.as_ref() {
-                            if let Some(vb) = vbw.vertex_buffer {
-                                d.device.cmd_bind_vertex_buffers(b, 0, &[vb.buffer], &[0]);
-                            }
-
-                            if pass.indexed && vbw.index_buffer.is_some() && vbw.index_size.is_some() {
-                                d.device.cmd_bind_index_buffer(b, vbw.index_buffer.unwrap().buffer, 0, vbw.index_size.unwrap());
-                            }
-                        }
-                        
-                        if pass.indexed {
-                            pass.draw_infos.iter().for_each(|draw_info| {
-                                d.device.cmd_draw_indexed(b, draw_info.index_count, draw_info.instance_count, draw_info.first_vertex, draw_info.vertex_offset, draw_info.first_instance);
-                            });
-
-                        } else {
-                            pass.draw_infos.iter().for_each(|draw_info| {
-                                d.device.cmd_draw(b, draw_info.vertex_count, draw_info.instance_count, draw_info.first_vertex, draw_info.first_instance);
-                            });
-                        }
-
-                        d.device.cmd_end_render_pass(b);
+                    ResourceReference::Image(index) => {
+                        let subresource_range = vk::ImageSubresourceRange::default()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .layer_count(1)
+                            .level_count(vk::REMAINING_MIP_LEVELS);
+
+                        let image = resources.get_images_from_ref(index)[i];
+                        let layout = resources.get_image_layout(&image);
+
+                        let image_memory_barrier = vk::ImageMemoryBarrier::default()
+                            .src_access_mask(dependant_info.src_access)
+                            .dst_access_mask(dependant_info.dst_access)
+                            .old_layout(layout)
+                            .new_layout(layout)
+                            .image(image.image)
+                            .subresource_range(subresource_range)
+                            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED);
+
+                        barriers.images.push(image_memory_barrier);
                     }
                 }
 
-                let dependant_edges = self.pass_graph.get_next_edges(&dependency.name);
-
-                for dependant_edge in dependant_edges {
-                    if let Some(dependant_info) = dependant_edge.info {
-                        let mut memory_barriers = Vec::<vk::MemoryBarrier>::new();
-                        let mut buffer_memory_barriers = Vec::<vk::BufferMemoryBarrier>::new();
-                        let mut image_memory_barriers = Vec::<vk::ImageMemoryBarrier>::new();
-
-                        match dependant_info.resource {
-                            ResourceReference::Buffer(index) => {
-                                let memory_barrier = vk::MemoryBarrier::builder()
-                                    .src_access_mask(dependant_info.src_access)
-                                    .dst_access_mask(dependant_info.dst_access)
-                                    .build();
-
-                                memory_barriers.push(memory_barrier);
-                            },
-                            ResourceReference::Image(index) => {
-                                let subresource_range = vk::ImageSubresourceRange::builder()
-                                    .aspect_mask(vk::ImageAspectFlags::COLOR)
-                                    .layer_count(1)
-                                    .level_count(1)
-                                    .build();
-
-                                let image = resources.get_images_from_ref(index)[i];
-
-                                let image_memory_barrier = vk::ImageMemoryBarrier::builder()
-                                    .src_access_mask(dependant_info.src_access)
-                                    .dst_access_mask(dependant_info.dst_access)
-                                    .old_layout(image.layout)
-                                    .new_layout(image.layout)
-                                    .image(image.image)
-                                    .subresource_range(subresource_range)
-                                    .src_queue_family_index(d.get_queue(self.exec).1)
-                                    .dst_queue_family_index(d.get_queue(self.exec).1)
-                                    .build();
-
-                                image_memory_barriers.push(image_memory_barrier);
-                            }
-                        }
-
-                        d.device.cmd_pipeline_barrier(b, dependant_info.src_stage, dependant_info.dst_stage, vk::DependencyFlags::empty(), &memory_barriers, &buffer_memory_barriers, &image_memory_barriers);
+                planned.push(barriers);
+            }
+        }
+
+        planned
+    }
+
+    fn resolve_accesses(&self, resources: &RendererData, pass_ref: PassRef, i: usize, present_index: usize) -> Result<Vec<ResolvedAccess>> {
+        self.pass_accesses(pass_ref).iter().map(|access| {
+            let (key, image) = Layer::resolve_resource(resources, &access.name, i, present_index)?;
+            Ok((key, image, access.usage, access.write))
+        }).collect()
+    }
+
+    fn resolve_resource(resources: &RendererData, name: &str, i: usize, present_index: usize) -> Result<(ResourceKey, Option<Image>)> {
+        if resources.image_refs.contains_key(name) {
+            let image = resources.get_images(name)?[Layer::resource_index(name, i, present_index)];
+            Ok((ResourceKey::Image(image.image), Some(image)))
+        } else {
+            let buffer = resources.get_buffers(name)?[i];
+            Ok((ResourceKey::Buffer(buffer.buffer), None))
+        }
+    }
+
+    // The barriers needed between the previous access of each resource and the accesses of the next pass, transitioning
+    // images from their tracked layout to the layout the pass needs
+    fn plan_access_barriers(&self, d: &Device, resources: &mut RendererData, accesses: &Vec<ResolvedAccess>, pass_type: PassType, access_states: &mut HashMap<ResourceKey, AccessState>) -> Vec<Barriers> {
+        let mut pass_states = Vec::<(ResourceKey, Option<Image>, AccessState)>::new();
+
+        for &(key, image, usage, write) in accesses {
+            let state = AccessState {
+                access: usage.access_flags(write),
+                stage: usage.stage_flags(pass_type),
+                layout: image.map_or(vk::ImageLayout::UNDEFINED, |image| usage.image_layout(write, image.layout)),
+                aspect: usage.aspect_mask(),
+                write,
+            };
+
+            match pass_states.iter_mut().find(|(k, _, _)| *k == key) {
+                Some((_, _, pass_state)) => {
+                    pass_state.access |= state.access;
+                    pass_state.stage |= state.stage;
+                    pass_state.write |= state.write;
+
+                    if state.write {
+                        pass_state.layout = state.layout;
                     }
+                },
+                None => pass_states.push((key, image, state)),
+            }
+        }
+
+        let mut barriers = Barriers::new(vk::PipelineStageFlags::empty(), vk::PipelineStageFlags::empty());
+
+        let family = d.get_queue(self.exec).1;
+        let mut acquires = Barriers::new(vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::empty());
+
+        for (key, image, state) in pass_states {
+            // A resource released by a layer on another queue family is acquired before its first access here, naming the
+            // same families and layout as the release did
+            if !access_states.contains_key(&key) {
+                match key {
+                    ResourceKey::Image(handle) => if let Some((src_family, dst_family)) = resources.image_transfers.remove(&handle).filter(|&(_, dst_family)| dst_family == family) {
+                        let layout = resources.get_image_layout(&image.unwrap());
+
+                        let subresource_range = vk::ImageSubresourceRange::default()
+                            .aspect_mask(state.aspect)
+                            .layer_count(1)
+                            .level_count(vk::REMAINING_MIP_LEVELS);
+
+                        acquires.images.push(vk::ImageMemoryBarrier::default()
+                            .src_access_mask(vk::AccessFlags::empty())
+                            .dst_access_mask(state.access)
+                            .old_layout(layout)
+                            .new_layout(layout)
+                            .image(handle)
+                            .subresource_range(subresource_range)
+                            .src_queue_family_index(src_family)
+                            .dst_queue_family_index(dst_family));
+
+                        acquires.dst_stage |= state.stage;
+                    },
+                    ResourceKey::Buffer(handle) => if let Some((src_family, dst_family)) = resources.buffer_transfers.remove(&handle).filter(|&(_, dst_family)| dst_family == family) {
+                        acquires.buffers.push(vk::BufferMemoryBarrier::default()
+                            .src_access_mask(vk::AccessFlags::empty())
+                            .dst_access_mask(state.access)
+                            .buffer(handle)
+                            .size(vk::WHOLE_SIZE)
+                            .src_queue_family_index(src_family)
+                            .dst_queue_family_index(dst_family));
+
+                        acquires.dst_stage |= state.stage;
+                    },
                 }
             }
-        })
+
+            // Work from previous layers is ordered by semaphores, so the first access in a layer only has to chain onto those.
+            // Transient images may share memory with an image written earlier, so those writes are waited on as well
+            let prev_state = match (access_states.get(&key), image) {
+                (Some(prev_state), _) => *prev_state,
+                (None, Some(image)) => AccessState {
+                    access: if resources.is_transient(&image) { vk::AccessFlags::MEMORY_WRITE } else { vk::AccessFlags::empty() },
+                    stage: vk::PipelineStageFlags::ALL_COMMANDS,
+                    layout: resources.get_image_layout(&image),
+                    aspect: state.aspect,
+                    write: false,
+                },
+                (None, None) => {
+                    access_states.insert(key, state);
+                    continue;
+                },
+            };
+
+            if prev_state.write || state.write || prev_state.layout != state.layout {
+                barriers.src_stage |= prev_state.stage;
+                barriers.dst_stage |= state.stage;
+
+                match key {
+                    ResourceKey::Image(handle) => {
+                        let subresource_range = vk::ImageSubresourceRange::default()
+                            .aspect_mask(state.aspect)
+                            .layer_count(1)
+                            .level_count(vk::REMAINING_MIP_LEVELS);
+
+                        barriers.images.push(vk::ImageMemoryBarrier::default()
+                            .src_access_mask(prev_state.access)
+                            .dst_access_mask(state.access)
+                            .old_layout(prev_state.layout)
+                            .new_layout(state.layout)
+                            .image(handle)
+                            .subresource_range(subresource_range)
+                            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED));
+                    },
+                    ResourceKey::Buffer(handle) => {
+                        barriers.buffers.push(vk::BufferMemoryBarrier::default()
+                            .src_access_mask(prev_state.access)
+                            .dst_access_mask(state.access)
+                            .buffer(handle)
+                            .size(vk::WHOLE_SIZE)
+                            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED));
+                    },
+                }
+            }
+
+            if let Some(image) = image {
+                resources.set_image_layout(&image, state.layout);
+            }
+
+            access_states.insert(key, state);
+        }
+
+        [acquires, barriers].into_iter().filter(|barriers| !barriers.is_empty()).collect()
+    }
+
+    // Hands resources over to the queue family of the next layer using them. Layouts are left alone, the acquiring layer
+    // transitions the image itself once it owns it
+    fn plan_queue_releases(&self, d: &Device, resources: &mut RendererData, access_states: &HashMap<ResourceKey, AccessState>, releases: &Vec<(ResourceKey, Option<Image>, u32)>) -> Vec<Barriers> {
+        let family = d.get_queue(self.exec).1;
+
+        let mut barriers = Barriers::new(vk::PipelineStageFlags::empty(), vk::PipelineStageFlags::BOTTOM_OF_PIPE);
+
+        for &(key, image, dst_family) in releases {
+            let state = match access_states.get(&key) {
+                Some(state) => *state,
+                None => continue,
+            };
+
+            barriers.src_stage |= state.stage;
+
+            match key {
+                ResourceKey::Image(handle) => {
+                    let layout = resources.get_image_layout(&image.unwrap());
+
+                    let subresource_range = vk::ImageSubresourceRange::default()
+                        .aspect_mask(state.aspect)
+                        .layer_count(1)
+                        .level_count(vk::REMAINING_MIP_LEVELS);
+
+                    barriers.images.push(vk::ImageMemoryBarrier::default()
+                        .src_access_mask(state.access)
+                        .dst_access_mask(vk::AccessFlags::empty())
+                        .old_layout(layout)
+                        .new_layout(layout)
+                        .image(handle)
+                        .subresource_range(subresource_range)
+                        .src_queue_family_index(family)
+                        .dst_queue_family_index(dst_family));
+
+                    resources.image_transfers.insert(handle, (family, dst_family));
+                },
+                ResourceKey::Buffer(handle) => {
+                    barriers.buffers.push(vk::BufferMemoryBarrier::default()
+                        .src_access_mask(state.access)
+                        .dst_access_mask(vk::AccessFlags::empty())
+                        .buffer(handle)
+                        .size(vk::WHOLE_SIZE)
+                        .src_queue_family_index(family)
+                        .dst_queue_family_index(dst_family));
+
+                    resources.buffer_transfers.insert(handle, (family, dst_family));
+                },
+            }
+        }
+
+        [barriers].into_iter().filter(|barriers| !barriers.is_empty()).collect()
+    }
+
+    // Leaves the image that will be presented in the layout expected by the presentation engine
+    fn plan_present_barrier(&self, resources: &mut RendererData, access_states: &HashMap<ResourceKey, AccessState>, image: Image) -> Option<Barriers> {
+        let layout = resources.get_image_layout(&image);
+
+        if layout == resources.present_layout {
+            return None;
+        }
+
+        let (src_access, src_stage) = match access_states.get(&ResourceKey::Image(image.image)) {
+            Some(state) => (state.access, state.stage),
+            None => (vk::AccessFlags::empty(), vk::PipelineStageFlags::ALL_COMMANDS),
+        };
+
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1)
+            .level_count(vk::REMAINING_MIP_LEVELS);
+
+        let barrier = vk::ImageMemoryBarrier::default()
+            .src_access_mask(src_access)
+            .dst_access_mask(vk::AccessFlags::empty())
+            .old_layout(layout)
+            .new_layout(resources.present_layout)
+            .image(image.image)
+            .subresource_range(subresource_range)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED);
+
+        resources.set_image_layout(&image, resources.present_layout);
+
+        let mut barriers = Barriers::new(src_stage, vk::PipelineStageFlags::BOTTOM_OF_PIPE);
+        barriers.images.push(barrier);
+
+        Some(barriers)
+    }
+
+    // Checks the pass graph and every resource the passes name, returning one message per problem found
+    pub fn validate(&self, name: &str, resources: &RendererData) -> Vec<String> {
+        let mut problems = self.pass_graph.validate().into_iter().map(|problem| {
+            format!("Pass graph of layer '{}' {}", name, problem)
+        }).collect::<Vec<String>>();
+
+        let passes = self.compute_passes.iter().map(|pass| pass.accesses()).chain(self.graphics_passes.iter().map(|pass| pass.accesses())).chain(self.transfer_passes.iter().map(|pass| pass.accesses()));
+        for accesses in passes {
+            for access in accesses {
+                if !resources.image_refs.contains_key(&access.name) && !resources.buffer_refs.contains_key(&access.name) {
+                    problems.push(format!("Layer '{}' has a pass accessing '{}', which is not a resource", name, access.name));
+                }
+            }
+        }
+
+        for edge in self.pass_graph.get_edges() {
+            let (kind, exists) = match edge.info.map(|info| info.resource) {
+                Some(ResourceReference::Buffer(index)) => ("buffer", index < resources.buffers.len()),
+                Some(ResourceReference::Image(index)) => ("image", index < resources.images.len()),
+                None => continue,
+            };
+
+            if !exists {
+                problems.push(format!("Layer '{}' has a dependency from '{}' to '{}' on a {} that does not exist", name, self.pass_graph.get_src_node(edge).name, self.pass_graph.get_dst_node(edge).name, kind));
+            }
+        }
+
+        problems
+    }
+
+    // Whether any pass in the layer writes the named resource, either through a declared access or as a render target
+    pub fn writes(&self, resources: &RendererData, name: &str) -> bool {
+        let declared = self.compute_passes.iter().map(|pass| pass.accesses()).chain(self.graphics_passes.iter().map(|pass| pass.accesses())).chain(self.transfer_passes.iter().map(|pass| pass.accesses())).any(|accesses| {
+            accesses.iter().any(|access| access.name == name && access.write)
+        });
+
+        let targeted = match resources.get_images(name) {
+            Ok(images) => self.graphics_passes.iter().any(|pass| {
+                pass.targets.iter().any(|target| images.iter().any(|image| image.image == target.image))
+            }),
+            Err(_) => false,
+        };
+
+        declared || targeted
+    }
+
+    // The images each pass uses, through a declared access, as a render target or bound to a descriptor, with passes in
+    // recording order
+    pub fn image_uses(&self, resources: &RendererData) -> Result<Vec<(String, Vec<usize>)>> {
+        let order = self.pass_graph.topological_order(None)?;
+
+        Ok(order.iter().map(|node| {
+            let pass_ref = node.data;
+
+            let mut uses = self.pass_accesses(pass_ref).iter().filter_map(|access| resources.image_refs.get(&access.name).copied()).collect::<Vec<usize>>();
+
+            if pass_ref.pass_type == PassType::Graphics {
+                uses.extend(self.graphics_passes[pass_ref.index].targets.iter().filter_map(|target| resources.find_image_ref(target)));
+            }
+
+            // Images bound through descriptors are used by the pass whether or not it declared an access for them
+            for descriptors in self.pass_descriptors(pass_ref) {
+                uses.extend(descriptors.bound_images().into_iter().filter_map(|image| resources.find_image_handle_ref(image)));
+            }
+
+            (node.name.clone(), uses)
+        }).collect())
     }
-}
\ No newline at end of file
+
+    // Culls the passes whose writes nothing live reads, walking back from the last pass recorded. Kept passes stay, as do
+    // passes that declare no writes, since whatever they do can't be seen. Everything a remaining pass touches, or
+    // guards with a dependency into it, becomes live in turn. Returns whether any resource became live
+    pub fn cull_passes(&mut self, resources: &RendererData, live: &mut HashSet<String>) -> Result<bool> {
+        let live_count = live.len();
+        let mut culled = HashSet::<String>::new();
+
+        for node in self.pass_graph.topological_order(None)?.into_iter().rev() {
+            let (writes, touches) = self.pass_resources(resources, node.data);
+
+            if !self.kept_passes.contains(&node.name) && !writes.is_empty() && !writes.iter().any(|name| live.contains(name)) {
+                culled.insert(node.name.clone());
+                continue;
+            }
+
+            live.extend(touches);
+
+            for edge in self.pass_graph.get_prev_edges(&node.name) {
+                if let Some(name) = edge.info.and_then(|info| resources.get_reference_name(info.resource)) {
+                    live.insert(name.clone());
+                }
+            }
+        }
+
+        self.culled_passes = culled;
+
+        Ok(live.len() != live_count)
+    }
+
+    // The names of the resources a pass writes and of every resource it touches, render targets included
+    fn pass_resources(&self, resources: &RendererData, pass_ref: PassRef) -> (Vec<String>, Vec<String>) {
+        let mut writes = self.pass_accesses(pass_ref).iter().filter(|access| access.write).map(|access| access.name.clone()).collect::<Vec<String>>();
+        let mut touches = self.pass_accesses(pass_ref).iter().map(|access| access.name.clone()).collect::<Vec<String>>();
+
+        if pass_ref.pass_type == PassType::Graphics {
+            let targets = self.graphics_passes[pass_ref.index].targets.iter().filter_map(|target| {
+                resources.find_image_ref(target).and_then(|index| resources.get_reference_name(ResourceReference::Image(index))).cloned()
+            }).collect::<Vec<String>>();
+
+            writes.extend(targets.iter().cloned());
+            touches.extend(targets);
+        }
+
+        (writes, touches)
+    }
+
+    // Every resource the passes that survived culling touch, without repeats
+    pub fn resource_names(&self, resources: &RendererData) -> Result<Vec<String>> {
+        let mut names = Vec::<String>::new();
+
+        for node in self.pass_graph.topological_order(None)? {
+            if self.culled_passes.contains(&node.name) {
+                continue;
+            }
+
+            for name in self.pass_resources(resources, node.data).1 {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+
+        Ok(names)
+    }
+
+    fn pass_accesses(&self, pass_ref: PassRef) -> &Vec<ResourceAccess> {
+        match pass_ref.pass_type {
+            PassType::Compute => self.compute_passes[pass_ref.index].accesses(),
+            PassType::Graphics => self.graphics_passes[pass_ref.index].accesses(),
+            PassType::Transfer => self.transfer_passes[pass_ref.index].accesses(),
+        }
+    }
+
+    fn pass_descriptors(&self, pass_ref: PassRef) -> Vec<&Descriptors> {
+        match pass_ref.pass_type {
+            PassType::Compute => self.compute_passes[pass_ref.index].descriptors.iter().collect(),
+            PassType::Graphics => {
+                let pass = &self.graphics_passes[pass_ref.index];
+                pass.vertex_descriptors.iter().chain(pass.fragment_descriptors.iter()).collect()
+            },
+            PassType::Transfer => Vec::new(),
+        }
+    }
+
+    pub unsafe fn replace_images(&mut self, c: &Core, d: &Device, data: &RendererData, replaced: &HashMap<vk::Image, Image>) -> Result<()> {
+        self.invalidate();
+
+        for pass in &mut self.compute_passes {
+            pass.replace_images(d, data, replaced)?;
+        }
+
+        for pass in &mut self.graphics_passes {
+            pass.replace_images(c, d, data, replaced)?;
+        }
+
+        Ok(())
+    }
+
+    pub unsafe fn destroy(&self, d: &Device) {
+        for pass in &self.compute_passes {
+            pass.destroy(d);
+        }
+
+        for pass in &self.graphics_passes {
+            pass.destroy(d);
+        }
+
+        self.commands.destroy(d);
+        self.semaphore.destroy(d);
+    }
+
+    // Targets are either the swapchain images or images with one per frame in flight, and the two counts can differ
+    fn target_index(resources: &RendererData, targets: &Vec<Image>, i: usize, present_index: usize) -> usize {
+        let name = resources.find_image_ref(&targets[0]).and_then(|index| resources.get_reference_name(ResourceReference::Image(index)));
+
+        match name {
+            Some(name) => Layer::resource_index(name, i, present_index),
+            None => i,
+        }
+    }
+
+    // The swapchain image is chosen by the presentation engine rather than the frame in flight
+    pub fn resource_index(name: &str, i: usize, present_index: usize) -> usize {
+        match name {
+            "swapchain_image" => present_index,
+            _ => i,
+        }
+    }
+}
@@ -2,7 +2,15 @@ pub mod math;
 pub mod util;
 
 pub mod core;
+pub mod adapter;
 pub mod device;
+pub mod surface;
+pub mod allocator;
+pub mod upload;
+pub mod readback;
+pub mod capture;
+pub mod export;
+pub mod graph_description;
 pub mod swapchain;
 pub mod buffer;
 pub mod image;
@@ -16,27 +24,38 @@ pub mod compute_pipeline;
 pub mod graphics_pipeline;
 pub mod compute_pass;
 pub mod graphics_pass;
+pub mod transfer_pass;
 pub mod fence;
 pub mod semaphore;
 pub mod frame;
+pub mod pacing;
 pub mod mesh;
 pub mod push_constant;
 pub mod renderer_data;
+pub mod aliasing;
 pub mod layer;
+pub mod recording;
+pub mod deletion_queue;
+pub mod error;
+
+pub use error::{Error, Result};
+
+use std::collections::HashMap;
 
 use ash::vk;
 use raw_window_handle::{RawWindowHandle, RawDisplayHandle};
 
 use crate::{buffer::Buffer, image::Image, layer::{LayerDependencyInfo, LayerSubmitInfo, PassDependency}, util::graph::Graph, vertex_buffer::VertexAttributes};
 
-const FRAMES_IN_FLIGHT: u32 = 2;
+const FRAMES_IN_FLIGHT: usize = 2;
 
 pub struct Renderer {
     pub core: core::Core,
     pub device: device::Device,
-    pub swapchain: swapchain::Swapchain,
+    pub swapchain: Option<swapchain::Swapchain>,
 
     pub data: renderer_data::RendererData,
+    pub deletion_queue: deletion_queue::DeletionQueue,
  
     pub layers: Vec<layer::Layer>,
     pub layer_graph: Graph<usize, LayerDependencyInfo>,
@@ -46,211 +65,1057 @@ pub struct Renderer {
     pub frames_in_flight: usize,
     pub current_frame: usize,
     pub present_index: usize,
+
+    pub swapchain_out_of_date: bool,
+    pub frame_skipped: bool,
+
+    pub pacing: pacing::FramePacing,
+
+    // Cleared whenever the graph changes, so the next draw validates it again
+    pub compiled: bool,
+
+    // Everything loaded through load_graph, which is what save_graph writes back out
+    pub description: graph_description::GraphDescription,
+
+    pub capture_sequence: Option<capture::CaptureSequence>,
+
+    // Only created when recording on more than one thread
+    pub recording: Option<recording::RecordingPools>,
+}
+
+// Options that are fixed once the renderer has been created
+pub struct RendererBuilder {
+    debug: bool,
+    timeline_semaphores: bool,
+    recording_threads: usize,
+    frames_in_flight: usize,
+    swapchain_config: swapchain::SwapchainConfig,
+    adapter_policy: adapter::AdapterPolicy,
+}
+
+impl RendererBuilder {
+    pub fn new() -> RendererBuilder {
+        RendererBuilder {
+            debug: false,
+            timeline_semaphores: false,
+            recording_threads: 1,
+            frames_in_flight: FRAMES_IN_FLIGHT,
+            swapchain_config: swapchain::SwapchainConfig::new(),
+            adapter_policy: adapter::AdapterPolicy::new(),
+        }
+    }
+
+    // Enables the validation layers
+    pub fn debug(mut self, debug: bool) -> RendererBuilder {
+        self.debug = debug;
+
+        self
+    }
+
+    // Layers signal a timeline semaphore with a value that goes up every submission instead of a binary semaphore,
+    // so a layer can have any number of dependents and the CPU can wait on a layer's work. Needs Vulkan 1.2
+    pub fn timeline_semaphores(mut self) -> RendererBuilder {
+        self.timeline_semaphores = true;
+
+        self
+    }
+
+    // Passes are recorded into secondary command buffers across this many threads, then executed from each layer's
+    // primary command buffer. One thread records everything inline on the calling thread
+    pub fn recording_threads(mut self, threads: usize) -> RendererBuilder {
+        self.recording_threads = threads.max(1);
+
+        self
+    }
+
+    // Every per-frame resource is created this many times. More frames let the CPU run further ahead of the GPU at the
+    // cost of memory and latency
+    pub fn frames_in_flight(mut self, frames: usize) -> RendererBuilder {
+        self.frames_in_flight = frames.max(1);
+
+        self
+    }
+
+    // How the window is presented to, which can be changed later with Renderer::set_swapchain_config
+    pub fn swapchain_config(mut self, config: swapchain::SwapchainConfig) -> RendererBuilder {
+        self.swapchain_config = config;
+
+        self
+    }
+
+    // Which physical device to run on. Discrete GPUs are preferred unless told otherwise, and the VRG_DEVICE environment
+    // variable overrides the choice unless the policy ignores it
+    pub fn adapter_policy(mut self, policy: adapter::AdapterPolicy) -> RendererBuilder {
+        self.adapter_policy = policy;
+
+        self
+    }
+
+    fn api_version(&self) -> u32 {
+        match self.timeline_semaphores {
+            true => vk::API_VERSION_1_2,
+            false => vk::API_VERSION_1_0,
+        }
+    }
+
+    pub unsafe fn build(self, window: RawWindowHandle, display: RawDisplayHandle) -> Result<Renderer> {
+        let core = core::Core::new(self.debug, display, self.api_version())?;
+        let device = device::Device::new(&core, window, display, self.timeline_semaphores, &self.swapchain_config, &self.adapter_policy)?;
+        let (swapchain, images) = swapchain::Swapchain::new(&core, &device, self.swapchain_config.clone())?;
+
+        let mut data = renderer_data::RendererData::new(self.frames_in_flight);
+        data.add_images_raw(&core, &device, "swapchain_image", images)?;
+
+        Renderer::from_parts(&self, core, device, Some(swapchain), data)
+    }
+
+    pub unsafe fn build_headless(self, extent: vk::Extent2D, format: vk::Format) -> Result<Renderer> {
+        let core = core::Core::new_headless(self.debug, self.api_version())?;
+        let device = device::Device::new_headless(&core, extent, format, self.timeline_semaphores, &self.adapter_policy)?;
+
+        let mut data = renderer_data::RendererData::new(self.frames_in_flight);
+        data.add_images(&core, &device, "swapchain_image", image::ImageBuilder::new()
+            .width(extent.width)
+            .height(extent.height)
+            .format(format)
+            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST)
+            .layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .relative_size(1.0, 1.0))?;
+        data.present_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+
+        Renderer::from_parts(&self, core, device, None, data)
+    }
 }
 
 impl Renderer {
-    pub unsafe fn new(window: RawWindowHandle, display: RawDisplayHandle, debug: bool) -> Renderer {
-        let core = core::Core::new(debug, display);
-        let device = device::Device::new(&core, window, display);
-        let (swapchain, images) = swapchain::Swapchain::new(&core, &device);
+    pub unsafe fn new(window: RawWindowHandle, display: RawDisplayHandle, debug: bool) -> Result<Renderer> {
+        RendererBuilder::new().debug(debug).build(window, display)
+    }
+
+    pub unsafe fn new_headless(extent: vk::Extent2D, format: vk::Format, debug: bool) -> Result<Renderer> {
+        RendererBuilder::new().debug(debug).build_headless(extent, format)
+    }
 
+    unsafe fn from_parts(builder: &RendererBuilder, core: core::Core, device: device::Device, swapchain: Option<swapchain::Swapchain>, data: renderer_data::RendererData) -> Result<Renderer> {
         let layers = Vec::<layer::Layer>::new();
         let layer_graph = Graph::new();
 
-        let mut data = renderer_data::RendererData::new(FRAMES_IN_FLIGHT as usize);
-        data.add_images_raw(&core, &device, "swapchain_image", images);
-
         let mut frames = Vec::<frame::Frame>::new();
-        for _ in 0..FRAMES_IN_FLIGHT {
-            frames.push(frame::Frame::new(&device));
+        for _ in 0..builder.frames_in_flight {
+            frames.push(frame::Frame::new(&device)?);
         }
 
-        Renderer {
+        let recording = match builder.recording_threads {
+            1 => None,
+            threads => Some(recording::RecordingPools::new(threads)?),
+        };
+
+        Ok(Renderer {
             core,
             device,
             swapchain,
 
             data,
+            deletion_queue: deletion_queue::DeletionQueue::new(builder.frames_in_flight),
 
             layers,
             layer_graph,
 
             frames,
 
-            frames_in_flight: FRAMES_IN_FLIGHT as usize,
+            frames_in_flight: builder.frames_in_flight,
             current_frame: 0,
             present_index: 0,
-        }
+
+            swapchain_out_of_date: false,
+            frame_skipped: false,
+
+            pacing: pacing::FramePacing::new(),
+
+            compiled: false,
+
+            description: graph_description::GraphDescription::new(),
+
+            capture_sequence: None,
+
+            recording,
+        })
+    }
+
+    // Every physical device the instance can see, whether or not it could run the renderer
+    pub unsafe fn adapters(&self) -> Result<Vec<adapter::Adapter>> {
+        adapter::Adapter::enumerate(&self.core)
+    }
+
+    // The physical device the renderer was created on
+    pub fn adapter(&self) -> &adapter::Adapter {
+        &self.device.adapter
     }
 
-    pub unsafe fn pre_draw(&mut self) {
+    pub fn headless(&self) -> bool {
+        self.swapchain.is_none()
+    }
+
+    pub unsafe fn pre_draw(&mut self) -> Result<()> {
+        self.pacing.limit();
+
         self.current_frame = (self.current_frame + 1) % self.frames_in_flight;
 
         let active_frame = self.frames[self.current_frame];
-        
-        self.device.device.wait_for_fences(&[active_frame.in_flight_fence.fence], true, u64::MAX).unwrap();
-        self.device.device.reset_fences(&[active_frame.in_flight_fence.fence]).unwrap();
-        
-        self.present_index = self.swapchain.swapchain_init.acquire_next_image(self.swapchain.swapchain, u64::MAX, active_frame.image_available_semaphore.semaphore, vk::Fence::null()).unwrap().0 as usize;
+
+        // Waiting on every frame's fence is the same as waiting on the previous frame, as frames finish in order
+        let fences = match self.pacing.latency_mode {
+            pacing::LatencyMode::Throughput => vec![active_frame.in_flight_fence.fence],
+            pacing::LatencyMode::Low => self.frames.iter().map(|frame| frame.in_flight_fence.fence).collect(),
+        };
+
+        let wait_start = std::time::Instant::now();
+        self.device.device.wait_for_fences(&fences, true, u64::MAX)?;
+        self.pacing.fence_wait = wait_start.elapsed();
+
+        self.deletion_queue.flush(&self.device, self.current_frame);
+
+        if self.swapchain_out_of_date {
+            self.recreate_targets(self.device.surface_extent)?;
+        }
+
+        // A minimised window has no extent to create a swapchain with, so frames are skipped until it comes back
+        self.frame_skipped = self.swapchain_out_of_date;
+        if self.frame_skipped {
+            return Ok(());
+        }
+
+        // Headless renderers present into a ring of offscreen images, one per frame in flight
+        let acquire_result = match &self.swapchain {
+            Some(swapchain) => swapchain.swapchain_init.acquire_next_image(swapchain.swapchain, u64::MAX, active_frame.image_available_semaphore.semaphore, vk::Fence::null()),
+            None => Ok((self.current_frame as u32, false)),
+        };
+
+        match acquire_result {
+            Ok((present_index, suboptimal)) => {
+                self.present_index = present_index as usize;
+                self.swapchain_out_of_date = suboptimal;
+            },
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.swapchain_out_of_date = true;
+                self.frame_skipped = true;
+                return Ok(());
+            },
+            Err(e) => return Err(Error::Vulkan(e)),
+        }
+
+        // The fence is only reset once a frame is guaranteed to be submitted, otherwise the next wait on it would never return
+        self.device.device.reset_fences(&[active_frame.in_flight_fence.fence])?;
+
+        Ok(())
+    }
+
+    // The present modes, formats and image counts the window's surface supports
+    pub unsafe fn surface_support(&self) -> Result<swapchain::SurfaceSupport> {
+        swapchain::Swapchain::supported(&self.device)
     }
 
-    pub unsafe fn draw(&mut self) {
+    // Rebuilds the swapchain with the new configuration, along with every framebuffer and command buffer for its images.
+    // Graphics passes are created for the surface format, so it can't change once a graphics pass has been added
+    pub unsafe fn set_swapchain_config(&mut self, config: swapchain::SwapchainConfig) -> Result<()> {
+        let surface = self.device.surface.ok_or(Error::Unsupported("Headless renderers have no swapchain to configure".to_string()))?;
+
+        let surface_format = config.choose_format(&self.device.surface_init.get_physical_device_surface_formats(self.device.physical_device, surface)?)?;
+
+        let has_graphics_passes = self.layers.iter().any(|layer| !layer.graphics_passes.is_empty());
+
+        if surface_format != self.device.surface_format && has_graphics_passes {
+            return Err(Error::Unsupported(format!("Surface format can't change to {:?} once graphics passes have been added", surface_format)));
+        }
+
+        self.swapchain.as_mut().unwrap().config = config;
+        self.device.surface_format = surface_format;
+
+        self.recreate_targets(self.device.surface_extent)
+    }
+
+    pub unsafe fn resize(&mut self, width: u32, height: u32) -> Result<()> {
+        self.recreate_targets(vk::Extent2D { width, height })
+    }
+
+    // Recreates the swapchain and every size-relative image, then rebinds anything that referenced the old images
+    unsafe fn recreate_targets(&mut self, preferred_extent: vk::Extent2D) -> Result<()> {
+        self.device.device.device_wait_idle()?;
+        self.device.update_surface_extent(preferred_extent)?;
+
+        if self.device.surface_extent.width == 0 || self.device.surface_extent.height == 0 {
+            self.swapchain_out_of_date = true;
+            return Ok(());
+        }
+
+        self.swapchain_out_of_date = false;
+
+        let mut rebuilt = Vec::<(image::Image, image::Image)>::new();
+        let mut retired = Vec::<image::Image>::new();
+
+        if let Some(swapchain) = &mut self.swapchain {
+            let new_images = swapchain.recreate(&self.core, &self.device)?;
+            let swapchain_images = self.data.replace_images("swapchain_image", new_images.clone())?;
+
+            // When the swapchain comes back with fewer images, the ones left over have nothing to be replaced with
+            retired.extend(swapchain_images.iter().skip(new_images.len()).copied());
+            rebuilt.extend(swapchain_images.into_iter().zip(new_images));
+        }
+
+        rebuilt.extend(self.data.rebuild_relative_images(&self.core, &self.device)?);
+        rebuilt.extend(self.data.alias_transient_images(&self.core, &self.device)?);
+
+        self.rebind_images(rebuilt)?;
+
+        for image in retired {
+            image.destroy(&self.device);
+        }
+
+        Ok(())
+    }
+
+    // Points every pass at the new images, then destroys the old ones. Nothing may still be using the old images
+    unsafe fn rebind_images(&mut self, rebuilt: Vec<(image::Image, image::Image)>) -> Result<()> {
+        let replaced = rebuilt.iter().map(|(old_image, new_image)| (old_image.image, *new_image)).collect::<HashMap<vk::Image, image::Image>>();
+
+        for layer in &mut self.layers {
+            layer.replace_images(&self.core, &self.device, &self.data, &replaced)?;
+        }
+
+        for (old_image, _) in rebuilt {
+            old_image.destroy(&self.device);
+        }
+
+        Ok(())
+    }
+
+    pub unsafe fn draw(&mut self) -> Result<()> {
+        if self.frame_skipped {
+            return Ok(());
+        }
+
+        if self.needs_compile() {
+            self.compile()?;
+        }
+
+        // Anything still sitting in an open upload batch has to land before the frame reads it
+        self.device.uploader.lock().unwrap().flush(&self.device)?;
+
         let active_frame = self.frames[self.current_frame];
 
         let present_indices = [self.present_index as u32];
 
-        for layer in &self.layers {
-            layer.record_one(&self.device, &self.data, self.current_frame, self.present_index);
+        let nodes = self.layer_graph.topological_order(None)?;
+
+        self.data.discard_transient_images(self.current_frame);
+
+        // Barriers are planned in submission order so each layer sees the image layouts left by the layers before it,
+        // after which the passes themselves can be recorded in any order
+        let nodes = nodes.into_iter().filter(|node| !self.layers[node.data].culled).collect::<Vec<_>>();
+        let recordings = nodes.iter().map(|node| {
+            Ok((node.data, self.layers[node.data].plan(&self.device, &mut self.data, self.current_frame, self.present_index)?))
+        }).collect::<Result<Vec<_>>>()?;
+
+        // Layers that haven't changed since they were last recorded for this frame and image are submitted as they are
+        let recordings = recordings.into_iter().filter(|(layer_ref, planned)| {
+            !self.layers[*layer_ref].is_recorded(planned, self.current_frame, self.present_index)
+        }).collect::<Vec<_>>();
+
+        match &mut self.recording {
+            Some(recording) => {
+                let secondaries = recording.record(&self.device, &self.layers, &recordings, self.current_frame, self.present_index)?;
+
+                for ((layer_ref, planned), secondaries) in recordings.iter().zip(secondaries) {
+                    self.layers[*layer_ref].record_planned(&self.device, planned, Some(&secondaries), self.current_frame, self.present_index)?;
+                }
+            },
+            None => {
+                for (layer_ref, planned) in &recordings {
+                    self.layers[*layer_ref].record_planned(&self.device, planned, None, self.current_frame, self.present_index)?;
+                }
+            },
         }
 
         let mut present_wait_semaphores = Vec::<vk::Semaphore>::new();
 
         let mut layer_submit_infos = Vec::<LayerSubmitInfo>::with_capacity(self.layer_graph.node_count());
 
-        let mut nodes = self.layer_graph.breadth_first_backwards(None);
-        nodes.reverse();
+        let timeline = self.device.timeline_semaphores;
 
         let mut present_info_set = false;
         for node in nodes {
             let mut wait_semaphores = Vec::<vk::Semaphore>::new();
+            let mut wait_values = Vec::<u64>::new();
             let mut wait_stages = Vec::<vk::PipelineStageFlags>::new();
             let mut signal_semaphores = Vec::<vk::Semaphore>::new();
+            let mut signal_values = Vec::<u64>::new();
 
-            let dependencies = self.layer_graph.get_prev_edges(&node.name);
+            // Layers are submitted in dependency order, so the value each dependency was given this frame is already known.
+            // Binary semaphores ignore the values
+            for (layer_ref, stage) in self.live_dependencies(&node.name) {
+                wait_semaphores.push(self.layers[layer_ref].semaphore.semaphore);
+                wait_values.push(self.layers[layer_ref].timeline_value);
+                wait_stages.push(stage);
+            }
 
-            for dependency in dependencies {
-                wait_semaphores.push(self.get_layer(&self.layer_graph.get_src_node(dependency).name).semaphore.semaphore);
-                wait_stages.push(dependency.info.stage);
+            if timeline {
+                self.layers[node.data].timeline_value += 1;
             }
 
-            signal_semaphores.push(self.get_layer(&node.name).semaphore.semaphore);
+            let layer = &self.layers[node.data];
+
+            signal_semaphores.push(layer.semaphore.semaphore);
+            signal_values.push(layer.timeline_value);
 
             let mut fence = vk::Fence::null();
 
-            let layer = self.get_layer(&node.name);
             if layer.present {
-                assert!(!present_info_set, "Error: Multiple layers marked as present");
+                if present_info_set {
+                    return Err(Error::InvalidGraph("Multiple layers marked as present".to_string()));
+                }
                 present_info_set = true;
 
-                wait_semaphores.push(active_frame.image_available_semaphore.semaphore);
-                wait_stages.push(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT);
+                if !self.headless() {
+                    wait_semaphores.push(active_frame.image_available_semaphore.semaphore);
+                    wait_values.push(0);
+                    wait_stages.push(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT);
+                }
+
                 fence = active_frame.in_flight_fence.fence;
-                present_wait_semaphores = signal_semaphores.clone();
+
+                if timeline && !self.headless() {
+                    signal_semaphores.push(active_frame.render_finished_semaphore.semaphore);
+                    signal_values.push(0);
+                    present_wait_semaphores = vec![active_frame.render_finished_semaphore.semaphore];
+                } else {
+                    present_wait_semaphores = signal_semaphores.clone();
+                }
             }
 
-            let command_buffers = vec![layer.commands.buffers[self.current_frame]];
+            let command_buffers = vec![layer.command_buffer(self.current_frame, self.present_index).unwrap()];
             let queue = self.device.get_queue(layer.exec).0;
 
-            let mut layer_submit_info = LayerSubmitInfo {
+            layer_submit_infos.push(LayerSubmitInfo {
                 wait_semaphores,
+                wait_values,
                 wait_stages,
                 signal_semaphores,
+                signal_values,
                 command_buffers,
                 queue,
                 fence,
-                submit_i: vk::SubmitInfo::builder().build(),
-            };
+            });
+        };
+        
+        for layer_submit_info in layer_submit_infos {
+            let mut timeline_i = vk::TimelineSemaphoreSubmitInfo::default()
+                .wait_semaphore_values(&layer_submit_info.wait_values)
+                .signal_semaphore_values(&layer_submit_info.signal_values);
 
-            layer_submit_info.submit_i = vk::SubmitInfo::builder()
+            let mut submit_i = vk::SubmitInfo::default()
                 .wait_semaphores(&layer_submit_info.wait_semaphores)
                 .signal_semaphores(&layer_submit_info.signal_semaphores)
                 .wait_dst_stage_mask(&layer_submit_info.wait_stages)
-                .command_buffers(&layer_submit_info.command_buffers)
-                .build();
+                .command_buffers(&layer_submit_info.command_buffers);
 
-            layer_submit_infos.push(layer_submit_info);
-        };
-        
-        for layer_submit_info in layer_submit_infos {
-            self.device.device.queue_submit(layer_submit_info.queue, &[layer_submit_info.submit_i], layer_submit_info.fence).unwrap();
+            if timeline {
+                submit_i = submit_i.push_next(&mut timeline_i);
+            }
+
+            self.device.device.queue_submit(layer_submit_info.queue, &[submit_i], layer_submit_info.fence)?;
+        }
+
+        if let Some(swapchain) = &self.swapchain {
+            let swapchains = [swapchain.swapchain];
+
+            let present_i = vk::PresentInfoKHR::default()
+                .wait_semaphores(&present_wait_semaphores)
+                .swapchains(&swapchains)
+                .image_indices(&present_indices);
+
+            match swapchain.swapchain_init.queue_present(self.device.queue_present.0, &present_i) {
+                Ok(suboptimal) => self.swapchain_out_of_date |= suboptimal,
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.swapchain_out_of_date = true,
+                Err(e) => return Err(Error::Vulkan(e)),
+            }
+        }
+
+        if let Some(mut sequence) = self.capture_sequence.take() {
+            let path = sequence.next_path();
+            if sequence.remaining > 0 {
+                self.capture_sequence = Some(sequence.clone());
+            }
+
+            self.capture(&sequence.name, &path.to_string_lossy())?;
+        }
+
+        Ok(())
+    }
+
+    // The layers a layer waits on, looking through culled layers to the layers they would have waited on themselves
+    fn live_dependencies(&self, name: &str) -> Vec<(usize, vk::PipelineStageFlags)> {
+        let mut dependencies = Vec::<(usize, vk::PipelineStageFlags)>::new();
+
+        for edge in self.layer_graph.get_prev_edges(name) {
+            let src = self.layer_graph.get_src_node(edge);
+
+            let srcs = match self.layers[src.data].culled {
+                true => self.live_dependencies(&src.name).into_iter().map(|(layer_ref, _)| layer_ref).collect(),
+                false => vec![src.data],
+            };
+
+            for layer_ref in srcs {
+                match dependencies.iter_mut().find(|(r, _)| *r == layer_ref) {
+                    Some((_, stage)) => *stage |= edge.info.stage,
+                    None => dependencies.push((layer_ref, edge.info.stage)),
+                }
+            }
         }
 
-        let swapchains = [self.swapchain.swapchain];
+        dependencies
+    }
 
-        let present_i = vk::PresentInfoKHR::builder()
-            .wait_semaphores(&present_wait_semaphores)
-            .swapchains(&swapchains)
-            .image_indices(&present_indices);
+    pub fn get_target_size(&self) -> Result<(u32, u32)> {
+        let image = self.get_images("swapchain_image")?[0];
+        Ok((image.width, image.height))
+    }
 
-        self.swapchain.swapchain_init.queue_present(self.device.queue_present.0, &present_i).unwrap();
+    // Uploads made between these calls are recorded into a single submission
+    pub fn begin_upload_batch(&mut self) {
+        self.device.uploader.lock().unwrap().begin_batch();
     }
 
-    pub fn get_target_size(&self) -> (u32, u32) {
-        let image = self.get_images("swapchain_image")[0];
-        (image.width, image.height)
+    pub unsafe fn end_upload_batch(&mut self) -> Result<()> {
+        self.device.uploader.lock().unwrap().end_batch(&self.device)
     }
 
-    pub unsafe fn add_buffers<T>(&mut self, name: &str, builder: buffer::BufferBuilder, data: Option<*const T>) {
-        self.data.add_buffers(&self.core, &self.device, name, builder, data);
+    pub fn get_allocation_stats(&self) -> Result<allocator::AllocationStats> {
+        self.device.allocation_stats()
     }
 
-    pub unsafe fn add_images(&mut self, name: &str, builder: image::ImageBuilder) {
-        self.data.add_images(&self.core, &self.device, name, builder);
+    // Memory saved by aliasing transient images, as of the last compile or resize
+    pub fn get_aliasing_report(&self) -> aliasing::AliasingReport {
+        self.data.aliasing_report
     }
 
-    pub fn get_buffers(&self, name: &str) -> &Vec<Buffer> {
+    pub unsafe fn add_buffers<T>(&mut self, name: &str, builder: buffer::BufferBuilder, data: Option<*const T>) -> Result<()> {
+        self.compiled = false;
+        self.data.add_buffers(&self.core, &self.device, name, builder, data)
+    }
+
+    pub unsafe fn add_images(&mut self, name: &str, builder: image::ImageBuilder) -> Result<()> {
+        self.compiled = false;
+        self.data.add_images(&self.core, &self.device, name, builder)
+    }
+
+    pub fn get_buffers(&self, name: &str) -> Result<&Vec<Buffer>> {
         self.data.get_buffers(name)
     }
 
-    pub fn get_images(&self, name: &str) -> &Vec<Image> {
+    pub fn get_images(&self, name: &str) -> Result<&Vec<Image>> {
         self.data.get_images(name)
     }
 
-    pub unsafe fn add_layer(&mut self, name: &str, present: bool, exec: layer::LayerExecution) {
-        self.layers.push(layer::Layer::new(&self.core, &self.device, self.frames_in_flight, present, exec));
-        self.layer_graph.add_node(name, self.layers.len() - 1);
+    pub unsafe fn add_layer(&mut self, name: &str, present: bool, exec: layer::LayerExecution) -> Result<()> {
+        if self.layer_graph.get_node(name).is_ok() {
+            return Err(Error::InvalidGraph(format!("Layer '{}' was added more than once", name)));
+        }
+
+        self.layers.push(layer::Layer::new(&self.core, &self.device, self.frames_in_flight, present, exec)?);
+        self.layer_graph.add_node(name, self.layers.len() - 1)?;
+        self.compiled = false;
+
+        Ok(())
     }
 
     pub fn set_root_layer(&mut self, name: &str) {
         self.layer_graph.set_root(name.to_string());
+        self.compiled = false;
     }
 
-    pub unsafe fn add_layer_dependency(&mut self, src: &str, dst: &str, stage: vk::PipelineStageFlags) {
-        self.layer_graph.add_edge(src, dst, LayerDependencyInfo { stage });
+    pub unsafe fn add_layer_dependency(&mut self, src: &str, dst: &str, stage: vk::PipelineStageFlags) -> Result<()> {
+        self.compiled = false;
+        self.layer_graph.add_edge(src, dst, LayerDependencyInfo { stage }).map_err(Renderer::missing_layer)
     }
 
-    pub unsafe fn add_compute_pass(&mut self, layer_name: &str, pass_name: &str, builder: compute_pass::ComputePassBuilder) {
-        let pass = builder.build(&self.core, &self.device);
-        self.get_layer_mut(layer_name).add_compute_pass(pass_name, pass);
+    pub unsafe fn add_compute_pass(&mut self, layer_name: &str, pass_name: &str, builder: compute_pass::ComputePassBuilder) -> Result<()> {
+        self.check_pass_name(layer_name, pass_name)?;
+
+        let pass = builder.build(&self.core, &self.device)?;
+        self.get_layer_mut(layer_name)?.add_compute_pass(pass_name, pass)
     }
 
-    pub unsafe fn add_graphics_pass<T: VertexAttributes, U>(&mut self, layer_name: &str, pass_name: &str, builder: graphics_pass::GraphicsPassBuilder<T, U>) {
-        let pass = builder.build(&self.core, &self.device);
-        self.get_layer_mut(layer_name).add_graphics_pass(pass_name, pass);
+    pub unsafe fn add_graphics_pass<T: VertexAttributes, U>(&mut self, layer_name: &str, pass_name: &str, builder: graphics_pass::GraphicsPassBuilder<T, U>) -> Result<()> {
+        self.check_pass_name(layer_name, pass_name)?;
+
+        let pass = builder.build(&self.core, &self.device)?;
+        self.get_layer_mut(layer_name)?.add_graphics_pass(pass_name, pass)
     }
 
-    pub fn add_pass_dependency(&mut self, layer_name: &str, src_name: &str, dst_name: &str, dep: Option<PassDependency>) {
-        self.get_layer_mut(layer_name).add_pass_dependency(src_name, dst_name, dep);
+    // Blits and mip generation are checked against the layer's queue here, as a transfer-only queue can't run them
+    pub unsafe fn add_transfer_pass(&mut self, layer_name: &str, pass_name: &str, builder: transfer_pass::TransferPassBuilder) -> Result<()> {
+        let pass = builder.build()?;
+        let exec = self.get_layer(layer_name)?.exec;
+
+        if pass.needs_graphics() && !self.device.queue_flags(&self.core, exec).contains(vk::QueueFlags::GRAPHICS) {
+            return Err(Error::Unsupported(format!("Transfer pass '{}' blits images, which the {:?} queue of layer '{}' can't do", pass_name, exec, layer_name)));
+        }
+
+        for operation in &pass.operations {
+            if let transfer_pass::TransferOperation::GenerateMips { image } = operation {
+                self.check_mip_generation(pass_name, image)?;
+            }
+        }
+
+        self.get_layer_mut(layer_name)?.add_transfer_pass(pass_name, pass)
     }
 
-    pub fn get_layer(&self, name: &str) -> &layer::Layer {
-        let layer_ref = self.layer_graph.get_node(name).data;
-        &self.layers[layer_ref]
+    // Mips are generated by blitting each level into the next with a linear filter, from and to the same image
+    unsafe fn check_mip_generation(&self, pass_name: &str, name: &str) -> Result<()> {
+        let image = self.data.get_images(name)?[0];
+
+        if !image.usage.contains(vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST) {
+            return Err(Error::Unsupported(format!("Transfer pass '{}' generates mips for '{}', which needs TRANSFER_SRC and TRANSFER_DST usage", pass_name, name)));
+        }
+
+        if image.mip_levels <= 1 {
+            return Err(Error::Unsupported(format!("Transfer pass '{}' generates mips for '{}', which only has one mip level", pass_name, name)));
+        }
+
+        let required = vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+        let features = self.core.instance.get_physical_device_format_properties(self.device.physical_device, image.format).optimal_tiling_features;
+
+        if !features.contains(required) {
+            return Err(Error::Unsupported(format!("Transfer pass '{}' generates mips for '{}', but {:?} images can't be blitted with linear filtering", pass_name, name, image.format)));
+        }
+
+        Ok(())
+    }
+
+    // Checked before a pass is built, so a duplicate name doesn't leave the pass's pipeline behind
+    fn check_pass_name(&self, layer_name: &str, pass_name: &str) -> Result<()> {
+        match self.get_layer(layer_name)?.pass_graph.get_node(pass_name) {
+            Ok(_) => Err(Error::InvalidGraph(format!("Layer '{}' already has a pass named '{}'", layer_name, pass_name))),
+            Err(_) => Ok(()),
+        }
     }
 
-    pub fn get_layer_mut(&mut self, name: &str) -> &mut layer::Layer {
-        let layer_ref = self.layer_graph.get_node(name).data;
-        &mut self.layers[layer_ref]
+    pub fn add_pass_dependency(&mut self, layer_name: &str, src_name: &str, dst_name: &str, dep: Option<PassDependency>) -> Result<()> {
+        self.get_layer_mut(layer_name)?.add_pass_dependency(src_name, dst_name, dep)
+    }
+
+    pub fn keep_pass(&mut self, layer_name: &str, pass_name: &str) -> Result<()> {
+        self.get_layer_mut(layer_name)?.keep_pass(pass_name)
+    }
+
+    // Exported resources are read outside the graph, e.g. by read_image, so the passes writing them are never culled
+    pub fn mark_exported(&mut self, name: &str) -> Result<()> {
+        if !self.data.image_refs.contains_key(name) && !self.data.buffer_refs.contains_key(name) {
+            return Err(Error::MissingResource(name.to_string()));
+        }
+
+        self.data.exported.insert(name.to_string());
+        self.compiled = false;
+
+        Ok(())
+    }
+
+    // (layer, pass) names of every pass left out of the frame by the last compile
+    pub fn get_culled_passes(&self) -> Vec<(String, String)> {
+        let mut culled = Vec::<(String, String)>::new();
+
+        for node in self.layer_graph.get_nodes() {
+            for pass in self.layers[node.data].pass_graph.get_nodes() {
+                if self.layers[node.data].culled_passes.contains(&pass.name) {
+                    culled.push((node.name.clone(), pass.name.clone()));
+                }
+            }
+        }
+
+        culled
+    }
+
+    // The layer graph only knows about nodes, so lookups into it are reported as missing layers
+    fn missing_layer(e: Error) -> Error {
+        match e {
+            Error::MissingNode(name) => Error::MissingLayer(name),
+            e => e,
+        }
+    }
+
+    pub fn get_layer(&self, name: &str) -> Result<&layer::Layer> {
+        let layer_ref = self.layer_graph.get_node(name).map_err(Renderer::missing_layer)?.data;
+        Ok(&self.layers[layer_ref])
+    }
+
+    // Anything reached through a mutable layer may change what it records, so only its own command buffers are recorded
+    // again. Changes to its passes or their dependencies mark the layer changed, which compiles the graph again
+    pub fn get_layer_mut(&mut self, name: &str) -> Result<&mut layer::Layer> {
+        let layer_ref = self.layer_graph.get_node(name).map_err(Renderer::missing_layer)?.data;
+        self.layers[layer_ref].invalidate();
+        Ok(&mut self.layers[layer_ref])
+    }
+
+    pub fn needs_compile(&self) -> bool {
+        !self.compiled || self.layers.iter().any(|layer| layer.changed)
+    }
+
+    // Checks the layer graph, every pass graph and the resources they name, reporting every problem at once
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = self.layer_graph.validate().into_iter().map(|problem| {
+            format!("Layer graph {}", problem)
+        }).collect::<Vec<String>>();
+
+        let present_layers = self.layer_graph.get_nodes().iter().filter(|node| self.layers[node.data].present).map(|node| format!("'{}'", node.name)).collect::<Vec<String>>();
+        match present_layers.len() {
+            0 => problems.push("No layer is marked as present".to_string()),
+            1 => {},
+            _ => problems.push(format!("Multiple layers are marked as present: {}", present_layers.join(", "))),
+        }
+
+        for node in self.layer_graph.get_nodes() {
+            problems.extend(self.layers[node.data].validate(&node.name, &self.data));
+        }
+
+        // A transient image's lifetime comes from the passes using it, so one no pass uses has nothing to alias with
+        for &index in self.data.transient_image_builders.keys() {
+            let used = self.layers.iter().any(|layer| layer.image_uses(&self.data).is_ok_and(|uses| uses.iter().any(|(_, pass_uses)| pass_uses.contains(&index))));
+
+            if !used {
+                let name = self.data.get_reference_name(renderer_data::ResourceReference::Image(index)).cloned().unwrap_or_default();
+                problems.push(format!("Transient image '{}' is not accessed by any pass", name));
+            }
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Validation(problems)),
+        }
+    }
+
+    // Adds the resources, layers and passes described in a .json file, see graph_description for the format
+    pub unsafe fn load_graph(&mut self, path: &str) -> Result<()> {
+        let description = graph_description::GraphDescription::load(path)?;
+        description.build(self)?;
+
+        self.description.extend(description);
+
+        Ok(())
+    }
+
+    // Parts of the graph built in code rather than loaded aren't included
+    pub fn save_graph(&self, path: &str) -> Result<()> {
+        self.description.save(path)
+    }
+
+    // The layer graph with each layer's passes as a cluster, for rendering with Graphviz
+    pub fn export_dot(&self) -> String {
+        export::render_graph_dot(&self.layer_graph, &self.layers, &self.data)
+    }
+
+    pub fn export_json(&self) -> String {
+        export::render_graph_json(&self.layer_graph, &self.layers, &self.data)
+    }
+
+    // Validates the graph, culls passes whose results go unused, then works out which transient images can share memory
+    // from the order the remaining passes are recorded in. draw calls this itself whenever the graph has changed since the last compile
+    pub unsafe fn compile(&mut self) -> Result<()> {
+        self.validate()?;
+        self.cull()?;
+        self.plan_queue_transfers()?;
+
+        // Images used on different queues only have their lifetimes ordered if a chain of layer dependencies joins them
+        let lifetimes = aliasing::transient_lifetimes(&self.device, &self.layer_graph, &self.layers, &self.data)?;
+        let alias_groups = aliasing::alias_groups(&lifetimes, |prev, next| {
+            prev.last_layer == next.first_layer || self.layer_graph.reaches(&prev.last_layer, &next.first_layer).unwrap_or(false)
+        });
+
+        if alias_groups != self.data.alias_groups {
+            self.device.device.device_wait_idle()?;
+
+            self.data.alias_groups = alias_groups;
+            let rebuilt = self.data.alias_transient_images(&self.core, &self.device)?;
+            self.rebind_images(rebuilt)?;
+        }
+
+        for layer in &mut self.layers {
+            layer.invalidate();
+            layer.changed = false;
+        }
+
+        self.compiled = true;
+
+        Ok(())
+    }
+
+    // Whatever the present layer draws to and every exported resource is live to begin with. Layers are swept back to
+    // front until nothing new becomes live, as a pass may read what a later pass wrote in the previous frame
+    fn cull(&mut self) -> Result<()> {
+        let mut live = self.data.exported.clone();
+        live.insert("swapchain_image".to_string());
+
+        let order = self.layer_graph.topological_order(None)?.iter().map(|node| node.data).collect::<Vec<usize>>();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for &layer_ref in order.iter().rev() {
+                changed |= self.layers[layer_ref].cull_passes(&self.data, &mut live)?;
+            }
+        }
+
+        // The present layer is always submitted, as it signals the frame's fence
+        for layer_ref in order {
+            let layer = &self.layers[layer_ref];
+            let culled = !layer.present && layer.pass_graph.topological_order(None)?.iter().all(|pass| layer.culled_passes.contains(&pass.name));
+
+            self.layers[layer_ref].culled = culled;
+        }
+
+        Ok(())
+    }
+
+    // Resources that aren't shared concurrently belong to one queue family at a time. Each live layer using one releases it
+    // to the family of the next layer using it, wrapping around to the first user in the following frame. Transient
+    // images start every frame with nothing worth keeping, so they aren't handed back across frames
+    fn plan_queue_transfers(&mut self) -> Result<()> {
+        let order = self.layer_graph.topological_order(None)?.iter().map(|node| node.data).filter(|&layer_ref| !self.layers[layer_ref].culled).collect::<Vec<usize>>();
+
+        let mut uses = Vec::<(usize, Vec<String>)>::new();
+        let mut names = Vec::<String>::new();
+        for &layer_ref in &order {
+            let layer_names = self.layers[layer_ref].resource_names(&self.data)?;
+            names.extend(layer_names.iter().filter(|name| !self.data.concurrent.contains(*name) && *name != "swapchain_image").cloned());
+            uses.push((layer_ref, layer_names));
+        }
+
+        names.sort();
+        names.dedup();
+
+        for layer in &mut self.layers {
+            layer.queue_releases.clear();
+        }
+
+        for name in names {
+            let transient = self.data.image_refs.get(&name).is_some_and(|index| self.data.transient_image_builders.contains_key(index));
+            let users = uses.iter().filter(|(_, layer_names)| layer_names.contains(&name)).map(|(layer_ref, _)| *layer_ref).collect::<Vec<usize>>();
+
+            for (k, &user) in users.iter().enumerate() {
+                if transient && k + 1 == users.len() {
+                    continue;
+                }
+
+                let next = users[(k + 1) % users.len()];
+                let dst_family = self.device.get_queue(self.layers[next].exec).1;
+
+                if self.device.get_queue(self.layers[user].exec).1 != dst_family {
+                    self.layers[user].queue_releases.push((name.clone(), dst_family));
+                }
+            }
+        }
+
+        Ok(())
     }
 
-    pub unsafe fn fill_buffer<T>(&mut self, name: &str, data: &Vec<T>, i: usize) {
-        self.data.get_buffers(name)[i].fill(&self.device, &data);
+    pub unsafe fn fill_buffer<T>(&mut self, name: &str, data: &Vec<T>, i: usize) -> Result<()> {
+        let buffers = self.data.get_buffers(name)?;
+        let buffer = buffers.get(i).ok_or_else(|| Error::Unsupported(format!("Buffer '{}' has {} copies, so there's no copy {} to fill", name, buffers.len(), i)))?;
+
+        buffer.fill(&self.device, data)
     }
 
-    pub unsafe fn fill_current_buffer<T>(&mut self, name: &str, data: &Vec<T>) {
+    pub unsafe fn fill_current_buffer<T>(&mut self, name: &str, data: &Vec<T>) -> Result<()> {
         self.fill_buffer(name, data, self.current_frame)
     }
 
-    pub unsafe fn update_vertex_buffer<T: VertexAttributes, U>(&mut self, layer_name: &str, pass_name: &str, verts: Option<&Vec<T>>, indices: Option<&Vec<U>>) {
-        // this is disgusting
-        // because of borrow checker rules, no references can be saved, so everything must either be clonable or done on a single line
-        let idontevenknow = self.layers[self.layer_graph.get_node(layer_name).data].pass_graph.get_node(pass_name).data.index;
-        self.layers[self.layer_graph.get_node(layer_name).data].graphics_passes[idontevenknow].update_vertex_buffer(&self.core, &self.device, verts, indices);
+    // Results are only read once every layer writing the resource has finished. With timeline semaphores that is a wait
+    // on each writer's latest value, otherwise every queue with a writer has to go idle
+    unsafe fn wait_for_writers(&self, name: &str) -> Result<()> {
+        self.device.uploader.lock().unwrap().flush(&self.device)?;
+
+        if self.device.timeline_semaphores {
+            let writers = self.layers.iter().filter(|layer| layer.writes(&self.data, name)).collect::<Vec<&layer::Layer>>();
+
+            if writers.is_empty() {
+                self.device.device.device_wait_idle()?;
+            }
+
+            for layer in writers {
+                layer.semaphore.wait(&self.device, layer.timeline_value)?;
+            }
+
+            return Ok(());
+        }
+
+        let mut queues = Vec::<vk::Queue>::new();
+        for layer in &self.layers {
+            let queue = self.device.get_queue(layer.exec).0;
+
+            if layer.writes(&self.data, name) && !queues.contains(&queue) {
+                queues.push(queue);
+            }
+        }
+
+        if queues.is_empty() {
+            self.device.device.device_wait_idle()?;
+        }
+
+        for queue in queues {
+            self.device.device.queue_wait_idle(queue)?;
+        }
+
+        Ok(())
+    }
+
+    // Blocks until everything submitted for the layer so far has finished
+    pub unsafe fn wait_for_layer(&self, name: &str) -> Result<()> {
+        let layer = self.get_layer(name)?;
+
+        match self.device.timeline_semaphores {
+            true => layer.semaphore.wait(&self.device, layer.timeline_value),
+            false => Ok(self.device.device.queue_wait_idle(self.device.get_queue(layer.exec).0)?),
+        }
     }
 
-    pub unsafe fn fill_all_buffers<T>(&mut self, name: &str, data: &Vec<T>) {
-        for i in 0..FRAMES_IN_FLIGHT as usize {
-            self.fill_buffer(name, data, i);
+    pub unsafe fn read_buffer<T: Copy>(&self, name: &str, frame: usize) -> Result<Vec<T>> {
+        self.wait_for_writers(name)?;
+
+        let size = std::mem::size_of::<T>();
+        if size == 0 {
+            return Err(Error::Unsupported(format!("Can't read buffer '{}' as zero sized {}", name, std::any::type_name::<T>())));
+        }
+
+        let buffers = self.data.get_buffers(name)?;
+        let buffer = buffers.get(frame).ok_or_else(|| Error::Unsupported(format!("Buffer '{}' has {} copies, so there's no copy {} to read", name, buffers.len(), frame)))?;
+
+        let bytes = readback::read_buffer(&self.core, &self.device, buffer)?;
+
+        if bytes.len() % size != 0 {
+            return Err(Error::Unsupported(format!("Buffer '{}' is {} bytes, which isn't a whole number of {} ({} bytes each)", name, bytes.len(), std::any::type_name::<T>(), size)));
+        }
+
+        // The bytes are only aligned for u8, so every element is read unaligned
+        Ok(bytes.chunks_exact(size).map(|chunk| std::ptr::read_unaligned(chunk.as_ptr() as *const T)).collect())
+    }
+
+    // Texels are tightly packed rows in the image's own format, see Image::texel_size
+    pub unsafe fn read_image(&mut self, name: &str, frame: usize) -> Result<Vec<u8>> {
+        // Once presented, swapchain images belong to the presentation engine until they're acquired again, so only
+        // headless renderers can read back what they drew. Windowed renderers can render to an offscreen image instead
+        if name == "swapchain_image" && !self.headless() {
+            return Err(Error::Unsupported("Swapchain images can only be read back from headless renderers".to_string()));
+        }
+
+        self.wait_for_writers(name)?;
+
+        let images = self.data.get_images(name)?;
+        let image = *images.get(frame).ok_or_else(|| Error::Unsupported(format!("Image '{}' has {} copies, so there's no copy {} to read", name, images.len(), frame)))?;
+
+        let (data, layout) = readback::read_image(&self.core, &self.device, &image, self.data.get_image_layout(&image))?;
+
+        self.data.set_image_layout(&image, layout);
+
+        Ok(data)
+    }
+
+    // Render targets are indexed by the acquired image, everything else by the frame in flight
+    pub fn latest_image_index(&self, name: &str) -> usize {
+        if name == "swapchain_image" {
+            self.present_index
+        } else {
+            self.current_frame
+        }
+    }
+
+    // Writes the most recently rendered copy of an image to disk, see capture::write_image for supported formats
+    pub unsafe fn capture(&mut self, name: &str, path: &str) -> Result<()> {
+        let frame = self.latest_image_index(name);
+
+        let image = self.data.get_images(name)?[frame];
+        let data = self.read_image(name, frame)?;
+
+        capture::write_image(path, image.width, image.height, image.format, &data)
+    }
+
+    // Captures the image after each of the next `count` drawn frames, to numbered files next to `path`
+    pub fn capture_frames(&mut self, name: &str, path: &str, count: usize) {
+        self.capture_sequence = match count {
+            0 => None,
+            _ => Some(capture::CaptureSequence::new(name, path, count)),
+        };
+    }
+
+    pub unsafe fn update_vertex_buffer<T: VertexAttributes, U>(&mut self, layer_name: &str, pass_name: &str, verts: Option<&Vec<T>>, indices: Option<&Vec<U>>) -> Result<()> {
+        // the layer is looked up by index so the core and device can still be borrowed alongside it
+        let layer_ref = self.layer_graph.get_node(layer_name).map_err(Renderer::missing_layer)?.data;
+        let replaced = self.layers[layer_ref].update_vertex_buffer(pass_name, &self.core, &self.device, verts, indices)?;
+
+        for buffer in replaced {
+            self.deletion_queue.push(self.current_frame, deletion_queue::DeferredResource::Buffer(buffer));
+        }
+
+        Ok(())
+    }
+
+    // Destroys a resource once every frame that might be using it has finished
+    pub fn destroy_deferred(&mut self, resource: deletion_queue::DeferredResource) {
+        self.deletion_queue.push(self.current_frame, resource);
+    }
+
+    // Limits how often pre_draw lets a frame begin, or None to begin frames as soon as the GPU allows
+    pub fn set_target_fps(&mut self, fps: Option<f64>) {
+        self.pacing.target_fps = fps;
+    }
+
+    pub fn set_latency_mode(&mut self, mode: pacing::LatencyMode) {
+        self.pacing.latency_mode = mode;
+    }
+
+    // How long the latest pre_draw blocked on the GPU finishing earlier frames, which grows when rendering is GPU bound
+    pub fn fence_wait_time(&self) -> std::time::Duration {
+        self.pacing.fence_wait
+    }
+
+    pub unsafe fn fill_all_buffers<T>(&mut self, name: &str, data: &Vec<T>) -> Result<()> {
+        for i in 0..self.frames_in_flight {
+            self.fill_buffer(name, data, i)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        unsafe {
+            // A lost device can't be waited on, but everything still has to be destroyed
+            if let Err(e) = self.device.device.device_wait_idle() {
+                eprintln!("Error: Failed to wait for the device before destroying the renderer: {}", e);
+            }
+
+            self.deletion_queue.flush_all(&self.device);
+
+            for layer in &self.layers {
+                layer.destroy(&self.device);
+            }
+
+            if let Some(recording) = &mut self.recording {
+                recording.destroy(&self.device);
+            }
+
+            self.data.destroy(&self.device);
+
+            for frame in &self.frames {
+                frame.destroy(&self.device);
+            }
+
+            if let Some(swapchain) = &self.swapchain {
+                swapchain.destroy();
+            }
+
+            self.device.destroy();
+            self.core.destroy();
         }
     }
 }
\ No newline at end of file
@@ -3,6 +3,7 @@ use std::io::Read;
 
 use crate::math::vec::Vec3;
 use crate::math::vec::Vec4;
+use crate::error::{Error, Result};
 
 pub trait FromObjTri {
     fn from_obj_tri(tri: Tri) -> Self;
@@ -31,10 +32,10 @@ enum ObjParserState {
     Faces(usize, usize),
 }
 
-pub fn parse_obj_as_tris<T: FromObjTri>(tris: &mut Vec<T>, name: &str) {
-    let mut file = fs::File::open(name).expect(&format!("Error: File \"{}\" not found", name));
+pub fn parse_obj_as_tris<T: FromObjTri>(tris: &mut Vec<T>, name: &str) -> Result<()> {
+    let mut file = fs::File::open(name).map_err(|e| Error::Io(name.to_string(), e))?;
     let mut raw = String::new();
-    file.read_to_string(&mut raw).unwrap();
+    file.read_to_string(&mut raw).map_err(|e| Error::Io(name.to_string(), e))?;
 
     let mut state = ObjParserState::Inactive;
 
@@ -62,7 +63,7 @@ pub fn parse_obj_as_tris<T: FromObjTri>(tris: &mut Vec<T>, name: &str) {
                     if start != 0 {
                         let v = vs.last_mut().unwrap();
 
-                        v[count] = raw[start..i].parse::<f32>().unwrap();
+                        v[count] = raw[start..i].parse::<f32>().map_err(|e| Error::Parse(name.to_string(), format!("invalid vertex '{}': {}", &raw[start..i], e)))?;
 
                         if count == 2 {
                             state = ObjParserState::Inactive;
@@ -78,9 +79,9 @@ pub fn parse_obj_as_tris<T: FromObjTri>(tris: &mut Vec<T>, name: &str) {
                 if char::is_whitespace(c) {
                     if start != 0 {
                         let f = fs.last_mut().unwrap();
-                        let vi = raw[start..i].parse::<usize>().unwrap();
+                        let vi = raw[start..i].parse::<usize>().map_err(|e| Error::Parse(name.to_string(), format!("invalid face index '{}': {}", &raw[start..i], e)))?;
 
-                        f[count] = vs[vi - 1];
+                        f[count] = *vi.checked_sub(1).and_then(|vi| vs.get(vi)).ok_or_else(|| Error::Parse(name.to_string(), format!("face index {} out of range", vi)))?;
 
                         if count == 2 {
                             state = ObjParserState::Inactive;
@@ -103,4 +104,6 @@ pub fn parse_obj_as_tris<T: FromObjTri>(tris: &mut Vec<T>, name: &str) {
 
         tris.push(formatted_tri);
     }
+
+    Ok(())
 }
\ No newline at end of file
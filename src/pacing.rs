@@ -0,0 +1,56 @@
+use std::time::{Duration, Instant};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LatencyMode {
+    // The CPU runs ahead of the GPU by up to the number of frames in flight, for the best throughput
+    Throughput,
+    // The CPU waits for the previous frame to finish on the GPU before starting the next, so input is read as late as
+    // possible. Resources are still kept per frame in flight
+    Low,
+}
+
+pub struct FramePacing {
+    pub target_fps: Option<f64>,
+    pub latency_mode: LatencyMode,
+
+    // How long the CPU blocked on in-flight fences before the latest frame
+    pub fence_wait: Duration,
+
+    next_frame: Option<Instant>,
+}
+
+impl FramePacing {
+    pub fn new() -> FramePacing {
+        FramePacing {
+            target_fps: None,
+            latency_mode: LatencyMode::Throughput,
+            fence_wait: Duration::ZERO,
+            next_frame: None,
+        }
+    }
+
+    // Sleeps until a frame interval after the previous frame began. Frames are scheduled from when the last one was due
+    // rather than when it started so sleep overshoot doesn't add up, unless a frame ran late enough to miss a whole interval
+    pub fn limit(&mut self) {
+        let interval = match self.target_fps {
+            Some(fps) if fps > 0.0 => Duration::from_secs_f64(1.0 / fps),
+            _ => {
+                self.next_frame = None;
+                return;
+            },
+        };
+
+        let now = Instant::now();
+
+        let due = match self.next_frame {
+            Some(due) if due + interval > now => due,
+            _ => now,
+        };
+
+        if due > now {
+            std::thread::sleep(due - now);
+        }
+
+        self.next_frame = Some(due + interval);
+    }
+}
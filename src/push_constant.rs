@@ -2,6 +2,8 @@ use std::{ptr, mem};
 
 use ash::vk;
 
+use crate::error::{Error, Result};
+
 pub struct PushConstantBuilder {
     size: usize,
     stage: Option<vk::ShaderStageFlags>,
@@ -31,8 +33,10 @@ impl PushConstantBuilder {
         self
     }
 
-    pub fn build(&self) -> PushConstant {
-        PushConstant::new(self.size, self.stage.unwrap())
+    pub fn build(&self) -> Result<PushConstant> {
+        let stage = self.stage.ok_or(Error::IncompleteBuilder("Push constant builder has no stage".to_string()))?;
+
+        Ok(PushConstant::new(self.size, stage))
     }
 }
 
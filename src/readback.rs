@@ -0,0 +1,134 @@
+use ash::vk;
+
+use crate::allocator::MemoryUsage;
+use crate::buffer::{Buffer, BufferBuilder};
+use crate::commands::Commands;
+use crate::core::Core;
+use crate::device::Device;
+use crate::error::Result;
+use crate::fence::Fence;
+use crate::image::Image;
+
+// Copies a buffer into host memory. Device local buffers go through a temporary staging buffer on the main queue
+pub unsafe fn read_buffer(c: &Core, d: &Device, buffer: &Buffer) -> Result<Vec<u8>> {
+    if buffer.host_visible {
+        return Ok(read_mapped(buffer, buffer.size as usize));
+    }
+
+    let staging = BufferBuilder::new()
+        .size(buffer.size as usize)
+        .usage(vk::BufferUsageFlags::TRANSFER_DST)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .memory_usage(MemoryUsage::GpuToCpu)
+        .build(c, d)?;
+
+    submit_and_wait(d, |b| {
+        let barrier = vk::MemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+
+        d.device.cmd_pipeline_barrier(b, vk::PipelineStageFlags::ALL_COMMANDS, vk::PipelineStageFlags::TRANSFER, vk::DependencyFlags::empty(), &[barrier], &[], &[]);
+
+        let buffer_copy = vk::BufferCopy::default()
+            .size(buffer.size);
+
+        d.device.cmd_copy_buffer(b, buffer.buffer, staging.buffer, &[buffer_copy]);
+    })?;
+
+    let data = read_mapped(&staging, buffer.size as usize);
+    staging.destroy(d);
+
+    Ok(data)
+}
+
+// Copies an image into host memory as tightly packed rows of texels in the image's own format.
+// The image is moved to TRANSFER_SRC_OPTIMAL for the copy and returned to the layout it was in, which is returned alongside the data
+pub unsafe fn read_image(c: &Core, d: &Device, image: &Image, layout: vk::ImageLayout) -> Result<(Vec<u8>, vk::ImageLayout)> {
+    let size = image.extent.width as usize * image.extent.height as usize * image.extent.depth as usize * image.texel_size()?;
+
+    let staging = BufferBuilder::new()
+        .size(size)
+        .usage(vk::BufferUsageFlags::TRANSFER_DST)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .memory_usage(MemoryUsage::GpuToCpu)
+        .build(c, d)?;
+
+    let aspect = image.aspect();
+
+    // An undefined image can't be transitioned back to UNDEFINED, so it is left readable instead
+    let restored_layout = match layout {
+        vk::ImageLayout::UNDEFINED => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        _ => layout,
+    };
+
+    submit_and_wait(d, |b| {
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(aspect)
+            .layer_count(1)
+            .level_count(vk::REMAINING_MIP_LEVELS);
+
+        let to_transfer = vk::ImageMemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .image(image.image)
+            .subresource_range(subresource_range)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED);
+
+        d.device.cmd_pipeline_barrier(b, vk::PipelineStageFlags::ALL_COMMANDS, vk::PipelineStageFlags::TRANSFER, vk::DependencyFlags::empty(), &[], &[], &[to_transfer]);
+
+        let subresource_layers = vk::ImageSubresourceLayers::default()
+            .aspect_mask(aspect)
+            .layer_count(1);
+
+        let copy_region = vk::BufferImageCopy::default()
+            .image_subresource(subresource_layers)
+            .image_extent(image.extent);
+
+        d.device.cmd_copy_image_to_buffer(b, image.image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, staging.buffer, &[copy_region]);
+
+        let from_transfer = vk::ImageMemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::empty())
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(restored_layout)
+            .image(image.image)
+            .subresource_range(subresource_range)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED);
+
+        d.device.cmd_pipeline_barrier(b, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::DependencyFlags::empty(), &[], &[], &[from_transfer]);
+    })?;
+
+    let data = read_mapped(&staging, size);
+    staging.destroy(d);
+
+    Ok((data, restored_layout))
+}
+
+unsafe fn read_mapped(buffer: &Buffer, size: usize) -> Vec<u8> {
+    let mut data = vec![0u8; size];
+    std::ptr::copy(buffer.p_dst.unwrap() as *const u8, data.as_mut_ptr(), size);
+
+    data
+}
+
+unsafe fn submit_and_wait<F: FnOnce(vk::CommandBuffer)>(d: &Device, r: F) -> Result<()> {
+    let commands = Commands::new(d, d.queue_main.1, 1, true)?;
+    let fence = Fence::new(d, false)?;
+
+    commands.record_one(d, 0, r)?;
+
+    let submit_i = vk::SubmitInfo::default()
+        .command_buffers(&commands.buffers);
+
+    let result = d.device.queue_submit(d.queue_main.0, &[submit_i], fence.fence)
+        .and_then(|_| d.device.wait_for_fences(&[fence.fence], true, u64::MAX));
+
+    fence.destroy(d);
+    commands.destroy(d);
+
+    Ok(result?)
+}
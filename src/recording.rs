@@ -0,0 +1,261 @@
+use std::collections::{hash_map::Entry, HashMap};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use ash::vk;
+
+use crate::device::Device;
+use crate::error::{Error, Result};
+use crate::layer::{Layer, LayerRecording, PassRecording};
+
+// Secondary command buffers for one thread and layer, for one frame in flight and swapchain image. The whole pool is
+// reset the first time the layer is recorded again, so buffers are handed out again rather than freed
+struct SecondaryPool {
+    pool: vk::CommandPool,
+    buffers: Vec<vk::CommandBuffer>,
+    used: usize,
+    generation: u64,
+}
+
+// One pass to record, pointing into the device, layers and planned barriers borrowed by RecordingPools::record
+struct Job {
+    index: usize,
+    generation: u64,
+    key: (usize, usize, usize),
+    family: u32,
+    i: usize,
+
+    device: *const Device,
+    layer: *const Layer,
+    pass: *const PassRecording,
+}
+
+enum Message {
+    Record(Job),
+    Shutdown(*const Device),
+}
+
+type JobResult = (usize, thread::Result<Result<vk::CommandBuffer>>);
+
+// Jobs hold raw pointers, so aren't Send on their own. RecordingPools::record doesn't return until every job it sent has
+// reported back, so the pointers never outlive what they point to, and the same goes for destroy with the device.
+// Workers only read through them: the device's functions are safe to call from any thread, nothing in a layer or its
+// planned barriers is written during recording, and each command buffer comes from a pool only its worker touches
+struct Shared<T>(T);
+
+unsafe impl<T> Send for Shared<T> {}
+
+// Command pools can only be used from one thread at a time, so every worker owns its own. Layers keep the secondaries
+// their recorded command buffers execute, so each worker also has its own pool per layer, frame in flight and swapchain
+// image, created the first time it records a pass for them. Workers live as long as the renderer and take passes off
+// one shared queue, so the recording is balanced between them without starting threads every frame
+pub struct RecordingPools {
+    pub threads: usize,
+    generation: u64,
+    jobs: Sender<Shared<Message>>,
+    results: Receiver<JobResult>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl SecondaryPool {
+    unsafe fn new(d: &Device, family: u32, generation: u64) -> Result<SecondaryPool> {
+        let pool_ci = vk::CommandPoolCreateInfo::default()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(family);
+
+        Ok(SecondaryPool {
+            pool: d.device.create_command_pool(&pool_ci, None)?,
+            buffers: Vec::new(),
+            used: 0,
+            generation,
+        })
+    }
+
+    unsafe fn reset(&mut self, d: &Device, generation: u64) -> Result<()> {
+        d.device.reset_command_pool(self.pool, vk::CommandPoolResetFlags::empty())?;
+        self.used = 0;
+        self.generation = generation;
+
+        Ok(())
+    }
+
+    unsafe fn next(&mut self, d: &Device) -> Result<vk::CommandBuffer> {
+        if self.used == self.buffers.len() {
+            let buffer_alloc_i = vk::CommandBufferAllocateInfo::default()
+                .command_pool(self.pool)
+                .level(vk::CommandBufferLevel::SECONDARY)
+                .command_buffer_count(1);
+
+            self.buffers.extend(d.device.allocate_command_buffers(&buffer_alloc_i)?);
+        }
+
+        self.used += 1;
+
+        Ok(self.buffers[self.used - 1])
+    }
+
+    unsafe fn destroy(&self, d: &Device) {
+        d.device.destroy_command_pool(self.pool, None);
+    }
+}
+
+impl Job {
+    unsafe fn run(&self, pools: &mut HashMap<(usize, usize, usize), SecondaryPool>) -> Result<vk::CommandBuffer> {
+        let d = &*self.device;
+
+        let pool = match pools.entry(self.key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(SecondaryPool::new(d, self.family, self.generation)?),
+        };
+
+        // Pools are reset by the first pass recorded into them each time their layer is recorded
+        if pool.generation != self.generation {
+            pool.reset(d, self.generation)?;
+        }
+
+        let b = pool.next(d)?;
+        (*self.layer).record_secondary(d, b, &*self.pass, self.i)?;
+
+        Ok(b)
+    }
+}
+
+impl RecordingPools {
+    pub fn new(threads: usize) -> Result<RecordingPools> {
+        let (jobs, job_receiver) = mpsc::channel::<Shared<Message>>();
+        let (result_sender, results) = mpsc::channel::<JobResult>();
+
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..threads).map(|n| {
+            let (job_receiver, result_sender) = (job_receiver.clone(), result_sender.clone());
+
+            thread::Builder::new()
+                .name(format!("vrg-recording-{}", n))
+                .spawn(move || RecordingPools::work(job_receiver, result_sender))
+                .map_err(|e| Error::Unsupported(format!("Failed to start recording thread: {}", e)))
+        }).collect::<Result<Vec<_>>>()?;
+
+        Ok(RecordingPools {
+            threads,
+            generation: 0,
+            jobs,
+            results,
+            workers,
+        })
+    }
+
+    fn work(jobs: Arc<Mutex<Receiver<Shared<Message>>>>, results: Sender<JobResult>) {
+        let mut pools = HashMap::<(usize, usize, usize), SecondaryPool>::new();
+
+        loop {
+            // The guard is dropped once a message arrives, so other workers can wait for the next one while this records
+            let message = match jobs.lock().map(|receiver| receiver.recv()) {
+                Ok(Ok(Shared(message))) => message,
+                _ => return,
+            };
+
+            match message {
+                Message::Record(job) => {
+                    // Panics are sent back too, as record has to hear from every job before it can return
+                    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe { job.run(&mut pools) }));
+
+                    if results.send((job.index, result)).is_err() {
+                        return;
+                    }
+                },
+                Message::Shutdown(d) => {
+                    for pool in pools.values() {
+                        unsafe { pool.destroy(&*d) };
+                    }
+
+                    return;
+                },
+            }
+        }
+    }
+
+    // Records every planned pass into its own secondary command buffer, with the workers taking passes off the queue
+    // until none are left. Returns the secondaries for each recording in pass order, ready to be executed from the
+    // layer's primary command buffer. Anything the layers previously recorded for this frame and image is replaced
+    pub unsafe fn record(&mut self, d: &Device, layers: &Vec<Layer>, recordings: &Vec<(usize, LayerRecording)>, i: usize, present_index: usize) -> Result<Vec<Vec<vk::CommandBuffer>>> {
+        // Nothing changed since every layer was last recorded, so there's nothing to hand the workers
+        if recordings.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.generation += 1;
+
+        let mut sent = 0;
+        let mut error = None;
+
+        'send: for (layer_ref, recording) in recordings {
+            let layer = &layers[*layer_ref];
+            let family = d.get_queue(layer.exec).1;
+
+            for pass in &recording.passes {
+                let job = Job {
+                    index: sent,
+                    generation: self.generation,
+                    key: (i, present_index, *layer_ref),
+                    family,
+                    i,
+
+                    device: d,
+                    layer,
+                    pass,
+                };
+
+                if self.jobs.send(Shared(Message::Record(job))).is_err() {
+                    error = Some(Error::Unsupported("Recording threads have stopped".to_string()));
+                    break 'send;
+                }
+
+                sent += 1;
+            }
+        }
+
+        // Every job sent has to report back before the borrows they point into end, even when one of them has failed
+        let mut buffers = vec![vk::CommandBuffer::null(); sent];
+        let mut panic = None;
+
+        for _ in 0..sent {
+            match self.results.recv() {
+                Ok((index, Ok(Ok(b)))) => buffers[index] = b,
+                Ok((_, Ok(Err(e)))) => error = error.or(Some(e)),
+                Ok((_, Err(payload))) => panic = panic.or(Some(payload)),
+                // Every worker has gone, so nothing is left holding a job
+                Err(_) => {
+                    error = error.or(Some(Error::Unsupported("Recording threads have stopped".to_string())));
+                    break;
+                },
+            }
+        }
+
+        if let Some(payload) = panic {
+            std::panic::resume_unwind(payload);
+        }
+
+        if let Some(e) = error {
+            return Err(e);
+        }
+
+        let mut buffers = buffers.into_iter();
+
+        Ok(recordings.iter().map(|(_, recording)| {
+            buffers.by_ref().take(recording.passes.len()).collect()
+        }).collect())
+    }
+
+    // Each worker destroys its own pools before stopping, so this waits for all of them to finish
+    pub unsafe fn destroy(&mut self, d: &Device) {
+        for _ in 0..self.workers.len() {
+            let _ = self.jobs.send(Shared(Message::Shutdown(d)));
+        }
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
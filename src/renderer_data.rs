@@ -1,6 +1,9 @@
-use std::{collections::HashMap, os::raw::c_void};
+use std::{collections::{HashMap, HashSet}, os::raw::c_void};
 
-use crate::{buffer::{Buffer, BufferBuilder}, image::{Image, ImageBuilder}, core::Core, device::Device};
+use ash::vk;
+
+use crate::{aliasing::AliasingReport, buffer::{Buffer, BufferBuilder}, image::{Image, ImageBuilder}, core::Core, device::Device};
+use crate::error::{Error, Result};
 
 #[derive(Copy, Clone)]
 pub enum ResourceReference {
@@ -9,6 +12,7 @@ pub enum ResourceReference {
 }
 
 pub struct RendererData {
+    // Resources have one of each per frame in flight, apart from the swapchain images which have one per image
     pub count: usize,
 
     pub buffers: Vec<Vec<Buffer>>,
@@ -16,6 +20,25 @@ pub struct RendererData {
 
     pub buffer_refs: HashMap<String, usize>,
     pub image_refs: HashMap<String, usize>,
+
+    pub image_layouts: HashMap<vk::Image, vk::ImageLayout>,
+    pub relative_image_builders: HashMap<usize, ImageBuilder>,
+    pub present_layout: vk::ImageLayout,
+
+    // Transient images only get shared memory once the graph is compiled, until then each has its own
+    pub transient_image_builders: HashMap<usize, ImageBuilder>,
+    pub alias_groups: Vec<Vec<usize>>,
+    pub aliasing_report: AliasingReport,
+
+    // Resources read from outside the graph, which keep the passes writing them from being culled
+    pub exported: HashSet<String>,
+
+    // Resources shared between queue families, which never need their ownership transferred
+    pub concurrent: HashSet<String>,
+
+    // Ownership released by one queue family and not yet acquired by the other, keyed by handle
+    pub image_transfers: HashMap<vk::Image, (u32, u32)>,
+    pub buffer_transfers: HashMap<vk::Buffer, (u32, u32)>,
 }
 
 impl RendererData {
@@ -26,45 +49,206 @@ impl RendererData {
             images: Vec::new(),
             buffer_refs: HashMap::new(),
             image_refs: HashMap::new(),
+            image_layouts: HashMap::new(),
+            relative_image_builders: HashMap::new(),
+            present_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            transient_image_builders: HashMap::new(),
+            alias_groups: Vec::new(),
+            aliasing_report: AliasingReport::default(),
+            exported: HashSet::new(),
+            concurrent: HashSet::new(),
+            image_transfers: HashMap::new(),
+            buffer_transfers: HashMap::new(),
         }
     }
 
-    pub unsafe fn add_buffers<T>(&mut self, c: &Core, d: &Device, name: &str, builder: BufferBuilder, data: Option<*const T>) {
+    pub unsafe fn add_buffers<T>(&mut self, c: &Core, d: &Device, name: &str, builder: BufferBuilder, data: Option<*const T>) -> Result<()> {
         let new_buffers = if let Some(buffer_data) = data {
-            builder.build_many_with_data(c, d, vec![buffer_data as *const c_void; self.count], self.count)
+            builder.build_many_with_data(c, d, vec![buffer_data as *const c_void; self.count], self.count)?
         } else {
-            builder.build_many(c, d, self.count)
+            builder.build_many(c, d, self.count)?
         };
         self.buffers.push(new_buffers);
         self.buffer_refs.insert(name.to_string(), self.buffers.len() - 1);
+
+        if builder.get_sharing_mode() == Some(vk::SharingMode::CONCURRENT) {
+            self.concurrent.insert(name.to_string());
+        }
+
+        Ok(())
     }
 
-    pub unsafe fn add_images(&mut self, c: &Core, d: &Device, name: &str, builder: ImageBuilder) {
-        self.images.push(builder.build_many(c, d, self.count));
+    pub unsafe fn add_images(&mut self, c: &Core, d: &Device, name: &str, builder: ImageBuilder) -> Result<()> {
+        // Transient contents are discarded every frame and the images are recreated when aliased, so there's nothing to keep
+        if builder.transient && (builder.data.is_some() || builder.data_ptr.is_some() || builder.pre_allocated_images.is_some()) {
+            return Err(Error::Unsupported(format!("Transient image '{}' can't be given data or pre-allocated images", name)));
+        }
+
+        let new_images = builder.clone().resolve_size(d.surface_extent).build_many(c, d, self.count)?;
+        for image in &new_images {
+            self.image_layouts.insert(image.image, image.layout);
+        }
+
+        self.images.push(new_images);
         self.image_refs.insert(name.to_string(), self.images.len() - 1);
+
+        if builder.concurrent {
+            self.concurrent.insert(name.to_string());
+        }
+
+        if builder.transient {
+            self.transient_image_builders.insert(self.images.len() - 1, builder.clone());
+        }
+
+        if builder.relative_size.is_some() {
+            self.relative_image_builders.insert(self.images.len() - 1, builder);
+        }
+
+        Ok(())
+    }
+
+    // Swaps the images registered under a name for new ones, returning the old images so they can be remapped and destroyed
+    pub fn replace_images(&mut self, name: &str, images: Vec<Image>) -> Result<Vec<Image>> {
+        let index = self.get_image_refs(name)?;
+
+        // The swapchain can be recreated with a different number of images, everything else has one per frame in flight
+        let count_changed = images.len() != self.images[index].len();
+
+        if images.is_empty() || (count_changed && name != "swapchain_image") {
+            return Err(Error::Unsupported(format!("Invalid number of images provided to replace '{}'", name)));
+        }
+
+        for image in &images {
+            self.image_layouts.insert(image.image, if image.memory.is_some() { image.layout } else { vk::ImageLayout::UNDEFINED });
+        }
+
+        let old_images = std::mem::replace(&mut self.images[index], images);
+
+        for image in &old_images {
+            self.image_layouts.remove(&image.image);
+            self.image_transfers.remove(&image.image);
+        }
+
+        Ok(old_images)
+    }
+
+    // Rebuilds every image sized relative to the render target, returning (old, new) pairs.
+    // Aliased images are left to alias_transient_images, which rebuilds them into their shared memory
+    pub unsafe fn rebuild_relative_images(&mut self, c: &Core, d: &Device) -> Result<Vec<(Image, Image)>> {
+        let mut rebuilt = Vec::<(Image, Image)>::new();
+
+        for (&index, builder) in &self.relative_image_builders {
+            if self.alias_groups.iter().any(|group| group.contains(&index)) {
+                continue;
+            }
+
+            let new_images = builder.clone().resolve_size(d.surface_extent).build_many(c, d, self.count)?;
+
+            for image in &new_images {
+                self.image_layouts.insert(image.image, image.layout);
+            }
+
+            let old_images = std::mem::replace(&mut self.images[index], new_images);
+
+            for (old_image, new_image) in old_images.iter().zip(self.images[index].iter()) {
+                self.image_layouts.remove(&old_image.image);
+                rebuilt.push((*old_image, *new_image));
+            }
+        }
+
+        Ok(rebuilt)
+    }
+
+    // Recreates the images in each alias group bound to one block of memory per frame in flight, returning (old, new) pairs
+    pub unsafe fn alias_transient_images(&mut self, c: &Core, d: &Device) -> Result<Vec<(Image, Image)>> {
+        let mut rebuilt = Vec::<(Image, Image)>::new();
+        let mut report = AliasingReport::default();
+
+        for group in &self.alias_groups {
+            let builders = group.iter().map(|index| {
+                self.transient_image_builders[index].clone().resolve_size(d.surface_extent)
+            }).collect::<Vec<ImageBuilder>>();
+
+            for i in 0..self.count {
+                let new_images = ImageBuilder::build_aliased(c, d, &builders)?;
+
+                let sizes = new_images.iter().map(|image| d.device.get_image_memory_requirements(image.image).size).collect::<Vec<u64>>();
+                report.transient_bytes += sizes.iter().sum::<u64>();
+                report.aliased_bytes += sizes.iter().max().copied().unwrap_or(0);
+                report.memory_blocks += 1;
+
+                for (&index, new_image) in group.iter().zip(new_images) {
+                    let old_image = std::mem::replace(&mut self.images[index][i], new_image);
+
+                    self.image_layouts.remove(&old_image.image);
+                    self.image_layouts.insert(new_image.image, vk::ImageLayout::UNDEFINED);
+
+                    rebuilt.push((old_image, new_image));
+                }
+            }
+
+            report.transient_images += group.len();
+        }
+
+        report.saved_bytes = report.transient_bytes - report.aliased_bytes;
+        self.aliasing_report = report;
+
+        Ok(rebuilt)
+    }
+
+    // Transient images start every frame with undefined contents, as another image may have used their memory since.
+    // They're per frame in flight, so are indexed by the frame rather than the acquired swapchain image
+    pub fn discard_transient_images(&mut self, frame: usize) {
+        for index in self.transient_image_builders.keys() {
+            if let Some(image) = self.images[*index].get(frame) {
+                self.image_layouts.insert(image.image, vk::ImageLayout::UNDEFINED);
+            }
+        }
     }
 
-    pub unsafe fn add_images_raw(&mut self, c: &Core, d: &Device, name: &str, images: Vec<Image>) {
-        assert!(images.len() == self.count, "Error: invalid number of images provided");
+    pub fn is_transient(&self, image: &Image) -> bool {
+        self.find_image_ref(image).is_some_and(|index| self.transient_image_builders.contains_key(&index))
+    }
+
+    pub fn find_image_ref(&self, image: &Image) -> Option<usize> {
+        self.find_image_handle_ref(image.image)
+    }
+
+    pub fn find_image_handle_ref(&self, image: vk::Image) -> Option<usize> {
+        self.images.iter().position(|images| images.iter().any(|i| i.image == image))
+    }
+
+    pub unsafe fn add_images_raw(&mut self, c: &Core, d: &Device, name: &str, images: Vec<Image>) -> Result<()> {
+        // Swapchain images are indexed by the acquired image rather than the frame in flight, so keep their own count
+        if images.is_empty() {
+            return Err(Error::Unsupported(format!("No images provided for '{}'", name)));
+        }
+
+        // Raw images have undefined contents until something writes to them
+        for image in &images {
+            self.image_layouts.insert(image.image, vk::ImageLayout::UNDEFINED);
+        }
 
         self.images.push(images.clone());
         self.image_refs.insert(name.to_string(), self.images.len() - 1);
+
+        Ok(())
     }
 
-    pub fn get_buffers(&self, name: &str) -> &Vec<Buffer> {
-        &self.buffers[*self.buffer_refs.get(name).unwrap()]
+    pub fn get_buffers(&self, name: &str) -> Result<&Vec<Buffer>> {
+        Ok(&self.buffers[self.get_buffer_refs(name)?])
     }
 
-    pub fn get_images(&self, name: &str) -> &Vec<Image> {
-        &self.images[*self.image_refs.get(name).unwrap()]
+    pub fn get_images(&self, name: &str) -> Result<&Vec<Image>> {
+        Ok(&self.images[self.get_image_refs(name)?])
     }
 
-    pub fn get_buffer_refs(&self, name: &str) -> usize {
-        *self.buffer_refs.get(name).unwrap()
+    pub fn get_buffer_refs(&self, name: &str) -> Result<usize> {
+        self.buffer_refs.get(name).copied().ok_or(Error::MissingResource(name.to_string()))
     }
 
-    pub fn get_image_refs(&self, name: &str) -> usize {
-        *self.image_refs.get(name).unwrap()
+    pub fn get_image_refs(&self, name: &str) -> Result<usize> {
+        self.image_refs.get(name).copied().ok_or(Error::MissingResource(name.to_string()))
     }
 
     pub fn get_buffers_from_ref(&self, index: usize) -> &Vec<Buffer> {
@@ -74,4 +258,35 @@ impl RendererData {
     pub fn get_images_from_ref(&self, index: usize) -> &Vec<Image> {
         &self.images[index]
     }
+
+    pub fn get_reference_name(&self, reference: ResourceReference) -> Option<&String> {
+        let (refs, index) = match reference {
+            ResourceReference::Buffer(index) => (&self.buffer_refs, index),
+            ResourceReference::Image(index) => (&self.image_refs, index),
+        };
+
+        refs.iter().find(|(_, &i)| i == index).map(|(name, _)| name)
+    }
+
+    pub fn get_image_layout(&self, image: &Image) -> vk::ImageLayout {
+        *self.image_layouts.get(&image.image).unwrap_or(&image.layout)
+    }
+
+    pub fn set_image_layout(&mut self, image: &Image, layout: vk::ImageLayout) {
+        self.image_layouts.insert(image.image, layout);
+    }
+
+    pub unsafe fn destroy(&self, d: &Device) {
+        for buffers in &self.buffers {
+            for buffer in buffers {
+                buffer.destroy(d);
+            }
+        }
+
+        for images in &self.images {
+            for image in images {
+                image.destroy(d);
+            }
+        }
+    }
 }
\ No newline at end of file
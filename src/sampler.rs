@@ -2,6 +2,7 @@ use ash::vk;
 
 use crate::core::Core;
 use crate::device::Device;
+use crate::error::Result;
 use crate::image::ImageData;
 
 // TODO: Finish
@@ -21,20 +22,25 @@ pub struct Sampler {
 }
 
 impl Sampler {
-    pub unsafe fn new(c: &Core, d: &Device, img: &ImageData) -> Sampler {
-        let sampler_ci = vk::SamplerCreateInfo::builder()
+    pub unsafe fn new(c: &Core, d: &Device, img: &ImageData) -> Result<Sampler> {
+        let sampler_ci = vk::SamplerCreateInfo::default()
             .address_mode_u(vk::SamplerAddressMode::REPEAT)
             .address_mode_v(vk::SamplerAddressMode::REPEAT)
             .address_mode_w(vk::SamplerAddressMode::REPEAT)
             .mag_filter(vk::Filter::NEAREST)
             .min_filter(vk::Filter::NEAREST);
         
-        let sampler = d.device.create_sampler(&sampler_ci, None).unwrap();
+        let sampler = d.device.create_sampler(&sampler_ci, None)?;
 
-        Sampler {
+        Ok(Sampler {
             sampler,
             view: img.view,
             layout: img.layout,
-        }
+        })
+    }
+
+    // The view belongs to the sampled image, so only the sampler itself is destroyed
+    pub unsafe fn destroy(&self, d: &Device) {
+        d.device.destroy_sampler(self.sampler, None);
     }
 }
\ No newline at end of file
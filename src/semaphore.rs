@@ -1,6 +1,7 @@
 use ash::vk;
 
 use crate::device::Device;
+use crate::error::Result;
 
 #[derive(Copy, Clone)]
 pub struct Semaphore {
@@ -8,13 +9,49 @@ pub struct Semaphore {
 }
 
 impl Semaphore {
-    pub unsafe fn new(d: &Device) -> Semaphore {
-        let semaphore_ci = vk::SemaphoreCreateInfo::builder();
+    pub unsafe fn new(d: &Device) -> Result<Semaphore> {
+        let semaphore_ci = vk::SemaphoreCreateInfo::default();
 
-        let semaphore = d.device.create_semaphore(&semaphore_ci, None).unwrap();
+        let semaphore = d.device.create_semaphore(&semaphore_ci, None)?;
 
-        Semaphore {
+        Ok(Semaphore {
             semaphore
-        }
+        })
+    }
+
+    // Timeline semaphores hold a counter that only goes up, so any number of waits can be made on a value once it is signalled
+    pub unsafe fn new_timeline(d: &Device, initial_value: u64) -> Result<Semaphore> {
+        let mut semaphore_type_ci = vk::SemaphoreTypeCreateInfo::default()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value);
+
+        let semaphore_ci = vk::SemaphoreCreateInfo::default()
+            .push_next(&mut semaphore_type_ci);
+
+        let semaphore = d.device.create_semaphore(&semaphore_ci, None)?;
+
+        Ok(Semaphore {
+            semaphore
+        })
+    }
+
+    pub unsafe fn value(&self, d: &Device) -> Result<u64> {
+        Ok(d.device.get_semaphore_counter_value(self.semaphore)?)
+    }
+
+    // Blocks the calling thread until a timeline semaphore reaches the value
+    pub unsafe fn wait(&self, d: &Device, value: u64) -> Result<()> {
+        let semaphores = [self.semaphore];
+        let values = [value];
+
+        let wait_i = vk::SemaphoreWaitInfo::default()
+            .semaphores(&semaphores)
+            .values(&values);
+
+        Ok(d.device.wait_semaphores(&wait_i, u64::MAX)?)
+    }
+
+    pub unsafe fn destroy(&self, d: &Device) {
+        d.device.destroy_semaphore(self.semaphore, None);
     }
 }
\ No newline at end of file
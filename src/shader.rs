@@ -4,6 +4,7 @@ use ash::util::read_spv;
 use ash::vk;
 
 use crate::device::Device;
+use crate::error::{Error, Result};
 
 #[derive(Copy, Clone)]
 pub enum ShaderType {
@@ -19,16 +20,20 @@ pub struct Shader {
 }
 
 impl Shader {
-    pub unsafe fn new(d: &Device, path: &str, flags: vk::ShaderStageFlags) -> Shader {
-        let mut shader_file = File::open(path).expect(format!("Error: Shader file at {path} doesn't exist").as_str());
-        let bytecode = read_spv(&mut shader_file).expect("Error reading shader");
-        let shader_ci = vk::ShaderModuleCreateInfo::builder().code(&bytecode);
-        let module = d.device.create_shader_module(&shader_ci, None).expect("Error creating shader module");
+    pub unsafe fn new(d: &Device, path: &str, flags: vk::ShaderStageFlags) -> Result<Shader> {
+        let mut shader_file = File::open(path).map_err(|e| Error::Io(path.to_string(), e))?;
+        let bytecode = read_spv(&mut shader_file).map_err(|e| Error::Io(path.to_string(), e))?;
+        let shader_ci = vk::ShaderModuleCreateInfo::default().code(&bytecode);
+        let module = d.device.create_shader_module(&shader_ci, None)?;
 
-        Shader {
+        Ok(Shader {
             module,
             flags,
             bytecode,
-        }
+        })
+    }
+
+    pub unsafe fn destroy(&self, d: &Device) {
+        d.device.destroy_shader_module(self.module, None);
     }
 }
\ No newline at end of file
@@ -0,0 +1,88 @@
+use std::ffi::c_char;
+
+use ash::vk;
+use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
+
+use crate::error::{Error, Result};
+
+// The instance extensions needed to create a surface for windows on this display
+pub fn required_extensions(display: RawDisplayHandle) -> Result<Vec<*const c_char>> {
+    let platform_name = match display {
+        RawDisplayHandle::Windows(_) => ash::khr::win32_surface::NAME,
+        RawDisplayHandle::Wayland(_) => ash::khr::wayland_surface::NAME,
+        RawDisplayHandle::Xlib(_) => ash::khr::xlib_surface::NAME,
+        RawDisplayHandle::Xcb(_) => ash::khr::xcb_surface::NAME,
+        RawDisplayHandle::Android(_) => ash::khr::android_surface::NAME,
+        RawDisplayHandle::AppKit(_) | RawDisplayHandle::UiKit(_) => ash::ext::metal_surface::NAME,
+        _ => return Err(Error::Unsupported(format!("No Vulkan surface support for display {:?}", display))),
+    };
+
+    Ok(vec![ash::khr::surface::NAME.as_ptr(), platform_name.as_ptr()])
+}
+
+// The window and display have to outlive the surface, and the instance needs the extensions from required_extensions
+pub unsafe fn create_surface(entry: &ash::Entry, instance: &ash::Instance, display: RawDisplayHandle, window: RawWindowHandle) -> Result<vk::SurfaceKHR> {
+    let surface = match (display, window) {
+        (RawDisplayHandle::Windows(_), RawWindowHandle::Win32(window)) => {
+            let surface_ci = vk::Win32SurfaceCreateInfoKHR::default()
+                .hinstance(window.hinstance as vk::HINSTANCE)
+                .hwnd(window.hwnd as vk::HWND);
+
+            ash::khr::win32_surface::Instance::new(entry, instance).create_win32_surface(&surface_ci, None)?
+        },
+        (RawDisplayHandle::Wayland(display), RawWindowHandle::Wayland(window)) => {
+            let surface_ci = vk::WaylandSurfaceCreateInfoKHR::default()
+                .display(display.display)
+                .surface(window.surface);
+
+            ash::khr::wayland_surface::Instance::new(entry, instance).create_wayland_surface(&surface_ci, None)?
+        },
+        (RawDisplayHandle::Xlib(display), RawWindowHandle::Xlib(window)) => {
+            let surface_ci = vk::XlibSurfaceCreateInfoKHR::default()
+                .dpy(display.display as *mut vk::Display)
+                .window(window.window);
+
+            ash::khr::xlib_surface::Instance::new(entry, instance).create_xlib_surface(&surface_ci, None)?
+        },
+        (RawDisplayHandle::Xcb(display), RawWindowHandle::Xcb(window)) => {
+            let surface_ci = vk::XcbSurfaceCreateInfoKHR::default()
+                .connection(display.connection)
+                .window(window.window);
+
+            ash::khr::xcb_surface::Instance::new(entry, instance).create_xcb_surface(&surface_ci, None)?
+        },
+        (RawDisplayHandle::Android(_), RawWindowHandle::AndroidNdk(window)) => {
+            let surface_ci = vk::AndroidSurfaceCreateInfoKHR::default()
+                .window(window.a_native_window);
+
+            ash::khr::android_surface::Instance::new(entry, instance).create_android_surface(&surface_ci, None)?
+        },
+        #[cfg(target_os = "macos")]
+        (RawDisplayHandle::AppKit(_), RawWindowHandle::AppKit(window)) => {
+            let layer = match raw_window_metal::appkit::metal_layer_from_handle(window) {
+                raw_window_metal::Layer::Existing(layer) | raw_window_metal::Layer::Allocated(layer) => layer as *const vk::CAMetalLayer,
+                raw_window_metal::Layer::None => return Err(Error::Unsupported("Window has no Metal layer to present to".to_string())),
+            };
+
+            let surface_ci = vk::MetalSurfaceCreateInfoEXT::default()
+                .layer(layer);
+
+            ash::ext::metal_surface::Instance::new(entry, instance).create_metal_surface(&surface_ci, None)?
+        },
+        #[cfg(target_os = "ios")]
+        (RawDisplayHandle::UiKit(_), RawWindowHandle::UiKit(window)) => {
+            let layer = match raw_window_metal::uikit::metal_layer_from_handle(window) {
+                raw_window_metal::Layer::Existing(layer) | raw_window_metal::Layer::Allocated(layer) => layer as *const vk::CAMetalLayer,
+                raw_window_metal::Layer::None => return Err(Error::Unsupported("Window has no Metal layer to present to".to_string())),
+            };
+
+            let surface_ci = vk::MetalSurfaceCreateInfoEXT::default()
+                .layer(layer);
+
+            ash::ext::metal_surface::Instance::new(entry, instance).create_metal_surface(&surface_ci, None)?
+        },
+        _ => return Err(Error::Unsupported(format!("No Vulkan surface support for window {:?}", window))),
+    };
+
+    Ok(surface)
+}
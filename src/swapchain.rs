@@ -2,26 +2,185 @@ use ash::vk;
 
 use crate::{core::Core, image::ImageBuilder};
 use crate::device::Device;
+use crate::error::{Error, Result};
 use crate::image::Image;
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Vsync {
+    // FIFO, which never tears and is always supported
+    On,
+    // MAILBOX where supported, which replaces queued frames rather than waiting without tearing, otherwise FIFO
+    Off,
+    // FIFO_RELAXED where supported, which only tears when a frame misses the vertical blank, otherwise FIFO
+    Adaptive,
+    // IMMEDIATE where supported, which tears but has the least latency, otherwise FIFO
+    Immediate,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SwapchainConfig {
+    pub vsync: Vsync,
+
+    // Tried in order, falling back to the first format the surface supports
+    pub formats: Vec<vk::SurfaceFormatKHR>,
+
+    // One more than the surface's minimum when not given, and clamped to what the surface supports
+    pub image_count: Option<u32>,
+}
+
+// What the surface can be presented with, for picking a SwapchainConfig
+#[derive(Clone, Debug)]
+pub struct SurfaceSupport {
+    pub present_modes: Vec<vk::PresentModeKHR>,
+    pub formats: Vec<vk::SurfaceFormatKHR>,
+    pub min_image_count: u32,
+    pub max_image_count: Option<u32>,
+}
+
 pub struct Swapchain {
-    pub swapchain_init: ash::extensions::khr::Swapchain,
+    pub swapchain_init: ash::khr::swapchain::Device,
     pub swapchain: vk::SwapchainKHR,
 
+    pub config: SwapchainConfig,
+    pub present_mode: vk::PresentModeKHR,
+    // How many images the swapchain was created with, which can be more than the config asked for
     pub image_count: u32,
 }
 
-impl Swapchain {
-    pub unsafe fn new(c: &Core, d: &Device) -> (Swapchain, Vec<Image>) {
-        assert!(d.surface_capabilities.max_image_count >= 2, "Swapchain doesn't support 2 images");
+impl SwapchainConfig {
+    pub fn new() -> SwapchainConfig {
+        SwapchainConfig {
+            vsync: Vsync::Off,
+            formats: vec![vk::SurfaceFormatKHR {
+                format: vk::Format::B8G8R8A8_SRGB,
+                color_space: vk::ColorSpaceKHR::EXTENDED_SRGB_NONLINEAR_EXT,
+            }],
+            image_count: None,
+        }
+    }
 
-        let image_count = if d.surface_capabilities.max_image_count > 0 && d.surface_capabilities.min_image_count + 1 > d.surface_capabilities.max_image_count {
-            d.surface_capabilities.max_image_count
-        } else {
-            d.surface_capabilities.min_image_count + 1
+    pub fn vsync(mut self, vsync: Vsync) -> SwapchainConfig {
+        self.vsync = vsync;
+
+        self
+    }
+
+    // Adds a format to try after the ones already given
+    pub fn format(mut self, format: vk::Format, color_space: vk::ColorSpaceKHR) -> SwapchainConfig {
+        self.formats.push(vk::SurfaceFormatKHR {
+            format,
+            color_space,
+        });
+
+        self
+    }
+
+    // Replaces the formats to try
+    pub fn formats(mut self, formats: Vec<vk::SurfaceFormatKHR>) -> SwapchainConfig {
+        self.formats = formats;
+
+        self
+    }
+
+    // HDR10 is 10 bit PQ encoded Rec. 2020, so shaders have to write encoded values
+    pub fn hdr10(self) -> SwapchainConfig {
+        self.formats(vec![
+            vk::SurfaceFormatKHR { format: vk::Format::A2B10G10R10_UNORM_PACK32, color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT },
+            vk::SurfaceFormatKHR { format: vk::Format::A2R10G10B10_UNORM_PACK32, color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT },
+        ])
+    }
+
+    // scRGB is linear with sRGB primaries, where 1.0 is SDR white and values past it are brighter
+    pub fn scrgb(self) -> SwapchainConfig {
+        self.formats(vec![
+            vk::SurfaceFormatKHR { format: vk::Format::R16G16B16A16_SFLOAT, color_space: vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT },
+        ])
+    }
+
+    pub fn image_count(mut self, image_count: u32) -> SwapchainConfig {
+        self.image_count = Some(image_count);
+
+        self
+    }
+
+    pub fn choose_format(&self, available: &Vec<vk::SurfaceFormatKHR>) -> Result<vk::SurfaceFormatKHR> {
+        self.formats.iter().find(|format| available.contains(format)).or(available.first()).copied().ok_or(Error::Unsupported("Surface supports no formats".to_string()))
+    }
+
+    pub fn choose_present_mode(&self, available: &Vec<vk::PresentModeKHR>) -> vk::PresentModeKHR {
+        let preferred = match self.vsync {
+            Vsync::On => vec![],
+            Vsync::Off => vec![vk::PresentModeKHR::MAILBOX],
+            Vsync::Adaptive => vec![vk::PresentModeKHR::FIFO_RELAXED],
+            Vsync::Immediate => vec![vk::PresentModeKHR::IMMEDIATE],
         };
 
-        //let image_count = 2;
+        preferred.into_iter().find(|present_mode| available.contains(present_mode)).unwrap_or(vk::PresentModeKHR::FIFO)
+    }
+
+    pub fn choose_image_count(&self, capabilities: &vk::SurfaceCapabilitiesKHR) -> u32 {
+        let image_count = self.image_count.unwrap_or(capabilities.min_image_count + 1).max(capabilities.min_image_count);
+
+        match capabilities.max_image_count {
+            0 => image_count,
+            max_image_count => image_count.min(max_image_count),
+        }
+    }
+}
+
+impl Swapchain {
+    pub unsafe fn new(c: &Core, d: &Device, config: SwapchainConfig) -> Result<(Swapchain, Vec<Image>)> {
+        let swapchain_init = ash::khr::swapchain::Device::new(&c.instance, &d.device);
+        let (swapchain, images, present_mode, image_count) = Swapchain::create(c, d, &swapchain_init, &config, vk::SwapchainKHR::null())?;
+
+        Ok((Swapchain {
+            swapchain_init,
+            swapchain,
+
+            config,
+            present_mode,
+            image_count,
+        }, images))
+    }
+
+    // The old swapchain is handed to the new one so the driver can reuse its resources, then destroyed straight away.
+    // Callers wait for the device to go idle first, so nothing is still presenting from it
+    pub unsafe fn recreate(&mut self, c: &Core, d: &Device) -> Result<Vec<Image>> {
+        let (swapchain, images, present_mode, image_count) = Swapchain::create(c, d, &self.swapchain_init, &self.config, self.swapchain)?;
+
+        self.swapchain_init.destroy_swapchain(self.swapchain, None);
+
+        self.swapchain = swapchain;
+        self.present_mode = present_mode;
+        self.image_count = image_count;
+
+        Ok(images)
+    }
+
+    pub unsafe fn supported(d: &Device) -> Result<SurfaceSupport> {
+        let surface = d.surface.ok_or(Error::Unsupported("Device has no surface to query".to_string()))?;
+
+        let capabilities = d.surface_init.get_physical_device_surface_capabilities(d.physical_device, surface)?;
+
+        Ok(SurfaceSupport {
+            present_modes: d.surface_init.get_physical_device_surface_present_modes(d.physical_device, surface)?,
+            formats: d.surface_init.get_physical_device_surface_formats(d.physical_device, surface)?,
+            min_image_count: capabilities.min_image_count,
+            max_image_count: match capabilities.max_image_count {
+                0 => None,
+                max_image_count => Some(max_image_count),
+            },
+        })
+    }
+
+    unsafe fn create(c: &Core, d: &Device, swapchain_init: &ash::khr::swapchain::Device, config: &SwapchainConfig, old_swapchain: vk::SwapchainKHR) -> Result<(vk::SwapchainKHR, Vec<Image>, vk::PresentModeKHR, u32)> {
+        let surface = d.surface.ok_or(Error::Unsupported("Device has no surface to create a swapchain for".to_string()))?;
+
+        if d.surface_capabilities.max_image_count > 0 && d.surface_capabilities.max_image_count < 2 {
+            return Err(Error::Unsupported("Swapchain doesn't support 2 images".to_string()));
+        }
+
+        let min_image_count = config.choose_image_count(&d.surface_capabilities);
 
         let (queue_family_indices, sharing_mode) = if d.queue_present.1 == d.queue_main.1 {
             (vec![d.queue_present.1], vk::SharingMode::EXCLUSIVE)
@@ -29,29 +188,29 @@ impl Swapchain {
             (vec![d.queue_present.1, d.queue_main.1], vk::SharingMode::CONCURRENT)
         };
 
-        let present_mode = d.surface_init.get_physical_device_surface_present_modes(d.physical_device, d.surface).unwrap().iter().cloned().find(|&pm| pm == vk::PresentModeKHR::MAILBOX).unwrap_or(vk::PresentModeKHR::FIFO);
-        //let present_mode = vk::PresentModeKHR::IMMEDIATE;
+        let present_mode = config.choose_present_mode(&d.surface_init.get_physical_device_surface_present_modes(d.physical_device, surface)?);
 
-        let swapchain_init = ash::extensions::khr::Swapchain::new(&c.instance, &d.device);
-
-        let swapchain_ci = vk::SwapchainCreateInfoKHR::builder()
-            .surface(d.surface)
-            .min_image_count(image_count)
+        let swapchain_ci = vk::SwapchainCreateInfoKHR::default()
+            .surface(surface)
+            .min_image_count(min_image_count)
             .image_format(d.surface_format.format)
             .image_color_space(d.surface_format.color_space)
             .image_extent(d.surface_extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
             .image_sharing_mode(sharing_mode)
             .queue_family_indices(&queue_family_indices)
             .pre_transform(d.surface_capabilities.current_transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
             .present_mode(present_mode)
-            .clipped(true);
+            .clipped(true)
+            .old_swapchain(old_swapchain);
 
-        let swapchain = swapchain_init.create_swapchain(&swapchain_ci, None).unwrap();
+        let swapchain = swapchain_init.create_swapchain(&swapchain_ci, None)?;
 
-        let image_handles = swapchain_init.get_swapchain_images(swapchain).unwrap();
+        // The count asked for is only a minimum, the presentation engine is free to create more images
+        let image_handles = swapchain_init.get_swapchain_images(swapchain)?;
+        let image_count = image_handles.len();
 
         let images = ImageBuilder::new()
             .width(d.surface_extent.width)
@@ -60,13 +219,12 @@ impl Swapchain {
             .usage(vk::ImageUsageFlags::empty())
             .layout(vk::ImageLayout::PRESENT_SRC_KHR)
             .pre_allocated_images(image_handles)
-            .build_many(c, d, image_count as usize);
+            .build_many(c, d, image_count)?;
 
-        (Swapchain {
-            swapchain_init,
-            swapchain,
+        Ok((swapchain, images, present_mode, image_count as u32))
+    }
 
-            image_count,
-        }, images)
+    pub unsafe fn destroy(&self) {
+        self.swapchain_init.destroy_swapchain(self.swapchain, None);
     }
 }
\ No newline at end of file
@@ -0,0 +1,334 @@
+use std::collections::HashSet;
+
+use ash::vk;
+
+use crate::{buffer::Buffer, image::Image, layer::{Layer, Pass, ResourceAccess, ResourceUsage}, renderer_data::RendererData};
+use crate::device::Device;
+use crate::error::{Error, Result};
+
+// Resources are copied from the start of each. Buffer to buffer and image to image copies stop at the size of the smaller
+// one, while a buffer copied into an image has to hold the whole image
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransferOperation {
+    CopyBuffer { src: String, dst: String },
+    CopyBufferToImage { src: String, dst: String },
+    CopyImage { src: String, dst: String },
+    BlitImage { src: String, dst: String, filter: vk::Filter },
+    FillBuffer { dst: String, value: u32 },
+    GenerateMips { image: String },
+}
+
+// An operation with its resources looked up for the frame being recorded
+pub enum ResolvedTransfer {
+    CopyBuffer(Buffer, Buffer),
+    CopyBufferToImage(Buffer, Image),
+    CopyImage(Image, Image),
+    BlitImage(Image, Image, vk::Filter),
+    FillBuffer(Buffer, u32),
+    GenerateMips(Image),
+}
+
+pub struct TransferPassBuilder {
+    operations: Vec<TransferOperation>,
+}
+
+pub struct TransferPass {
+    pub operations: Vec<TransferOperation>,
+    pub accesses: Vec<ResourceAccess>,
+}
+
+impl Pass for TransferPass {
+    fn accesses(&self) -> &Vec<ResourceAccess> {
+        &self.accesses
+    }
+}
+
+impl TransferPassBuilder {
+    pub fn new() -> TransferPassBuilder {
+        TransferPassBuilder {
+            operations: Vec::new(),
+        }
+    }
+
+    pub fn operation(mut self, operation: TransferOperation) -> TransferPassBuilder {
+        self.operations.push(operation);
+
+        self
+    }
+
+    pub fn copy_buffer(self, src: &str, dst: &str) -> TransferPassBuilder {
+        self.operation(TransferOperation::CopyBuffer { src: src.to_string(), dst: dst.to_string() })
+    }
+
+    pub fn copy_buffer_to_image(self, src: &str, dst: &str) -> TransferPassBuilder {
+        self.operation(TransferOperation::CopyBufferToImage { src: src.to_string(), dst: dst.to_string() })
+    }
+
+    pub fn copy_image(self, src: &str, dst: &str) -> TransferPassBuilder {
+        self.operation(TransferOperation::CopyImage { src: src.to_string(), dst: dst.to_string() })
+    }
+
+    // Blits need a queue with graphics support, so they can't be used in layers on a transfer-only queue
+    pub fn blit_image(self, src: &str, dst: &str, filter: vk::Filter) -> TransferPassBuilder {
+        self.operation(TransferOperation::BlitImage { src: src.to_string(), dst: dst.to_string(), filter })
+    }
+
+    pub fn fill_buffer(self, dst: &str, value: u32) -> TransferPassBuilder {
+        self.operation(TransferOperation::FillBuffer { dst: dst.to_string(), value })
+    }
+
+    // Fills every level below the first by blitting down from the one above, so has the same queue needs as a blit
+    pub fn generate_mips(self, image: &str) -> TransferPassBuilder {
+        self.operation(TransferOperation::GenerateMips { image: image.to_string() })
+    }
+
+    // Barriers are placed between passes rather than operations, so a resource can't be both read and written in one pass
+    pub fn build(self) -> Result<TransferPass> {
+        if self.operations.is_empty() {
+            return Err(Error::IncompleteBuilder("Transfer pass builder has no operations".to_string()));
+        }
+
+        let mut accesses = Vec::<ResourceAccess>::new();
+
+        for operation in &self.operations {
+            let (src, dst) = operation.resources();
+
+            if let Some(src) = src {
+                accesses.push(ResourceAccess::new(src, ResourceUsage::Transfer, false));
+            }
+
+            accesses.push(ResourceAccess::new(dst, ResourceUsage::Transfer, true));
+        }
+
+        if let Some(access) = accesses.iter().find(|access| !access.write && accesses.iter().any(|other| other.write && other.name == access.name)) {
+            return Err(Error::Unsupported(format!("Transfer pass both reads and writes '{}', which needs separate passes", access.name)));
+        }
+
+        // Nothing orders the operations within a pass either, so only one of them can write each resource
+        let mut written = HashSet::<&str>::new();
+
+        if let Some(access) = accesses.iter().filter(|access| access.write).find(|access| !written.insert(&access.name)) {
+            return Err(Error::Unsupported(format!("Transfer pass writes '{}' more than once, which needs separate passes", access.name)));
+        }
+
+        Ok(TransferPass {
+            operations: self.operations,
+            accesses,
+        })
+    }
+}
+
+impl TransferOperation {
+    // The resource read, if any, and the resource written
+    pub fn resources(&self) -> (Option<&str>, &str) {
+        match self {
+            TransferOperation::CopyBuffer { src, dst } => (Some(src), dst),
+            TransferOperation::CopyBufferToImage { src, dst } => (Some(src), dst),
+            TransferOperation::CopyImage { src, dst } => (Some(src), dst),
+            TransferOperation::BlitImage { src, dst, .. } => (Some(src), dst),
+            TransferOperation::FillBuffer { dst, .. } => (None, dst),
+            TransferOperation::GenerateMips { image } => (None, image),
+        }
+    }
+
+    pub fn needs_graphics(&self) -> bool {
+        matches!(self, TransferOperation::BlitImage { .. } | TransferOperation::GenerateMips { .. })
+    }
+}
+
+impl TransferPass {
+    pub fn needs_graphics(&self) -> bool {
+        self.operations.iter().any(|operation| operation.needs_graphics())
+    }
+
+    pub fn resolve(&self, resources: &RendererData, i: usize, present_index: usize) -> Result<Vec<ResolvedTransfer>> {
+        let buffer = |name: &str| -> Result<Buffer> { Ok(resources.get_buffers(name)?[i]) };
+        let image = |name: &str| -> Result<Image> { Ok(resources.get_images(name)?[Layer::resource_index(name, i, present_index)]) };
+
+        self.operations.iter().map(|operation| {
+            Ok(match operation {
+                TransferOperation::CopyBuffer { src, dst } => ResolvedTransfer::CopyBuffer(buffer(src)?, buffer(dst)?),
+                TransferOperation::CopyBufferToImage { src, dst } => {
+                    let (src_buffer, dst_image) = (buffer(src)?, image(dst)?);
+                    let extent = dst_image.extent;
+                    let size = dst_image.texel_size()? as u64 * extent.width as u64 * extent.height as u64 * extent.depth as u64;
+
+                    // The copy covers the whole image, so a smaller buffer would be read past its end
+                    if src_buffer.size < size {
+                        return Err(Error::Unsupported(format!("Buffer '{}' is {} bytes, but copying it into '{}' needs {}", src, src_buffer.size, dst, size)));
+                    }
+
+                    ResolvedTransfer::CopyBufferToImage(src_buffer, dst_image)
+                },
+                TransferOperation::CopyImage { src, dst } => ResolvedTransfer::CopyImage(image(src)?, image(dst)?),
+                TransferOperation::BlitImage { src, dst, filter } => ResolvedTransfer::BlitImage(image(src)?, image(dst)?, *filter),
+                TransferOperation::FillBuffer { dst, value } => ResolvedTransfer::FillBuffer(buffer(dst)?, *value),
+                TransferOperation::GenerateMips { image: name } => ResolvedTransfer::GenerateMips(image(name)?),
+            })
+        }).collect()
+    }
+
+    // Sources are expected in TRANSFER_SRC_OPTIMAL and destinations in TRANSFER_DST_OPTIMAL, which the barriers before
+    // the pass see to
+    pub unsafe fn record(d: &Device, b: vk::CommandBuffer, transfers: &Vec<ResolvedTransfer>) {
+        for operation in transfers {
+            match operation {
+                ResolvedTransfer::CopyBuffer(src, dst) => {
+                    let region = vk::BufferCopy::default()
+                        .size(src.size.min(dst.size));
+
+                    d.device.cmd_copy_buffer(b, src.buffer, dst.buffer, &[region]);
+                },
+                ResolvedTransfer::CopyBufferToImage(src, dst) => {
+                    let region = vk::BufferImageCopy::default()
+                        .image_subresource(TransferPass::subresource(dst, 0))
+                        .image_extent(dst.extent);
+
+                    d.device.cmd_copy_buffer_to_image(b, src.buffer, dst.image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[region]);
+                },
+                ResolvedTransfer::CopyImage(src, dst) => {
+                    let region = vk::ImageCopy::default()
+                        .src_subresource(TransferPass::subresource(src, 0))
+                        .dst_subresource(TransferPass::subresource(dst, 0))
+                        .extent(vk::Extent3D {
+                            width: src.extent.width.min(dst.extent.width),
+                            height: src.extent.height.min(dst.extent.height),
+                            depth: src.extent.depth.min(dst.extent.depth),
+                        });
+
+                    d.device.cmd_copy_image(b, src.image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, dst.image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[region]);
+                },
+                ResolvedTransfer::BlitImage(src, dst, filter) => {
+                    let region = vk::ImageBlit::default()
+                        .src_subresource(TransferPass::subresource(src, 0))
+                        .src_offsets(TransferPass::level_offsets(src.extent, 0))
+                        .dst_subresource(TransferPass::subresource(dst, 0))
+                        .dst_offsets(TransferPass::level_offsets(dst.extent, 0));
+
+                    d.device.cmd_blit_image(b, src.image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, dst.image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[region], *filter);
+                },
+                ResolvedTransfer::FillBuffer(dst, value) => {
+                    d.device.cmd_fill_buffer(b, dst.buffer, 0, vk::WHOLE_SIZE, *value);
+                },
+                ResolvedTransfer::GenerateMips(image) => TransferPass::record_mips(d, b, image),
+            }
+        }
+    }
+
+    // Each level is moved to TRANSFER_SRC_OPTIMAL once written and blitted into the next. Every level is returned to
+    // TRANSFER_DST_OPTIMAL at the end, as the graph tracks one layout for the whole image
+    unsafe fn record_mips(d: &Device, b: vk::CommandBuffer, image: &Image) {
+        let barrier = |level: u32, level_count: u32, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout, src_access: vk::AccessFlags, dst_access: vk::AccessFlags| {
+            let subresource_range = vk::ImageSubresourceRange::default()
+                .aspect_mask(image.aspect())
+                .base_mip_level(level)
+                .level_count(level_count)
+                .layer_count(1);
+
+            vk::ImageMemoryBarrier::default()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .image(image.image)
+                .subresource_range(subresource_range)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                
+        };
+
+        for level in 1..image.mip_levels {
+            let to_src = barrier(level - 1, 1, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::TRANSFER_READ);
+            d.device.cmd_pipeline_barrier(b, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::TRANSFER, vk::DependencyFlags::empty(), &[], &[], &[to_src]);
+
+            let region = vk::ImageBlit::default()
+                .src_subresource(TransferPass::subresource(image, level - 1))
+                .src_offsets(TransferPass::level_offsets(image.extent, level - 1))
+                .dst_subresource(TransferPass::subresource(image, level))
+                .dst_offsets(TransferPass::level_offsets(image.extent, level));
+
+            d.device.cmd_blit_image(b, image.image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, image.image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[region], vk::Filter::LINEAR);
+        }
+
+        if image.mip_levels > 1 {
+            let to_dst = barrier(0, image.mip_levels - 1, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::AccessFlags::TRANSFER_READ, vk::AccessFlags::TRANSFER_WRITE);
+            d.device.cmd_pipeline_barrier(b, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::TRANSFER, vk::DependencyFlags::empty(), &[], &[], &[to_dst]);
+        }
+    }
+
+    fn subresource(image: &Image, level: u32) -> vk::ImageSubresourceLayers {
+        vk::ImageSubresourceLayers::default()
+            .aspect_mask(image.aspect())
+            .mip_level(level)
+            .layer_count(1)
+            
+    }
+
+    fn level_offsets(extent: vk::Extent3D, level: u32) -> [vk::Offset3D; 2] {
+        [
+            vk::Offset3D { x: 0, y: 0, z: 0 },
+            vk::Offset3D {
+                x: (extent.width >> level).max(1) as i32,
+                y: (extent.height >> level).max(1) as i32,
+                z: (extent.depth >> level).max(1) as i32,
+            },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accesses(pass: &TransferPass) -> Vec<(&str, bool)> {
+        pass.accesses.iter().map(|access| (access.name.as_str(), access.write)).collect()
+    }
+
+    #[test]
+    fn operations_declare_their_accesses() {
+        let pass = TransferPassBuilder::new()
+            .copy_buffer("staging", "vertices")
+            .fill_buffer("counters", 0)
+            .generate_mips("texture")
+            .build()
+            .unwrap();
+
+        assert_eq!(accesses(&pass), vec![("staging", false), ("vertices", true), ("counters", true), ("texture", true)]);
+    }
+
+    #[test]
+    fn empty_pass_is_incomplete() {
+        assert!(matches!(TransferPassBuilder::new().build(), Err(Error::IncompleteBuilder(_))));
+    }
+
+    #[test]
+    fn reading_a_written_resource_is_rejected() {
+        let result = TransferPassBuilder::new()
+            .copy_image("a", "b")
+            .copy_image("b", "c")
+            .build();
+
+        assert!(matches!(result, Err(Error::Unsupported(message)) if message.contains("'b'")));
+    }
+
+    #[test]
+    fn writing_a_resource_twice_is_rejected() {
+        let result = TransferPassBuilder::new()
+            .copy_buffer_to_image("pixels", "texture")
+            .generate_mips("texture")
+            .build();
+
+        assert!(matches!(result, Err(Error::Unsupported(message)) if message.contains("'texture' more than once")));
+    }
+
+    #[test]
+    fn one_source_can_be_read_by_many_operations() {
+        let pass = TransferPassBuilder::new()
+            .copy_image("src", "a")
+            .blit_image("src", "b", vk::Filter::LINEAR)
+            .build()
+            .unwrap();
+
+        assert_eq!(accesses(&pass), vec![("src", false), ("a", true), ("src", false), ("b", true)]);
+    }
+}
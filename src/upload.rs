@@ -0,0 +1,260 @@
+use std::os::raw::c_void;
+
+use ash::vk;
+
+use crate::allocator::MemoryUsage;
+use crate::buffer::Buffer;
+use crate::commands::Commands;
+use crate::device::Device;
+use crate::error::Result;
+use crate::fence::Fence;
+
+const STAGING_RING_SIZE: usize = 16 * 1024 * 1024;
+const STAGING_ALIGNMENT: usize = 16;
+
+// Copies CPU data into device local memory through a persistently mapped staging ring.
+// Uploads are recorded into one command buffer on the main queue, which is submitted straight away unless a batch is open
+pub struct Uploader {
+    commands: Option<Commands>,
+    fence: Option<Fence>,
+    staging: Option<Buffer>,
+    oversized_staging: Vec<Buffer>,
+
+    head: usize,
+    recording: bool,
+    batching: bool,
+}
+
+impl Uploader {
+    pub fn new() -> Uploader {
+        Uploader {
+            commands: None,
+            fence: None,
+            staging: None,
+            oversized_staging: Vec::new(),
+
+            head: 0,
+            recording: false,
+            batching: false,
+        }
+    }
+
+    pub fn begin_batch(&mut self) {
+        self.batching = true;
+    }
+
+    pub unsafe fn end_batch(&mut self, d: &Device) -> Result<()> {
+        self.batching = false;
+        self.flush(d)
+    }
+
+    pub unsafe fn upload_buffer(&mut self, d: &Device, dst: vk::Buffer, dst_offset: u64, data: *const c_void, size: usize) -> Result<()> {
+        let (src, src_offset) = self.stage(d, data, size)?;
+        let b = self.begin(d)?;
+
+        let buffer_copy = vk::BufferCopy::default()
+            .src_offset(src_offset)
+            .dst_offset(dst_offset)
+            .size(size as u64);
+
+        // Earlier submissions may still be reading the buffer, so the copy waits for them
+        Uploader::record_buffer_barrier(d, b, dst, dst_offset, size as u64, vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE, vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::ALL_COMMANDS, vk::PipelineStageFlags::TRANSFER);
+
+        d.device.cmd_copy_buffer(b, src, dst, &[buffer_copy]);
+
+        Uploader::record_buffer_barrier(d, b, dst, dst_offset, size as u64, vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::ALL_COMMANDS);
+
+        self.end_upload(d)
+    }
+
+    pub unsafe fn upload_image(&mut self, d: &Device, image: vk::Image, extent: vk::Extent3D, aspect: vk::ImageAspectFlags, data: *const c_void, size: usize, layout: vk::ImageLayout) -> Result<()> {
+        let (src, src_offset) = self.stage(d, data, size)?;
+
+        self.copy_buffer_to_image(d, src, src_offset, image, extent, aspect, layout)
+    }
+
+    pub unsafe fn copy_buffer_to_image(&mut self, d: &Device, src: vk::Buffer, src_offset: u64, image: vk::Image, extent: vk::Extent3D, aspect: vk::ImageAspectFlags, layout: vk::ImageLayout) -> Result<()> {
+        let b = self.begin(d)?;
+
+        // The old contents are discarded, but earlier submissions may still be reading the image
+        Uploader::record_image_barrier(d, b, image, aspect, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE, vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::ALL_COMMANDS, vk::PipelineStageFlags::TRANSFER);
+
+        let subresource_layers = vk::ImageSubresourceLayers::default()
+            .aspect_mask(aspect)
+            .layer_count(1);
+
+        let copy_region = vk::BufferImageCopy::default()
+            .buffer_offset(src_offset)
+            .image_subresource(subresource_layers)
+            .image_extent(extent);
+
+        d.device.cmd_copy_buffer_to_image(b, src, image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[copy_region]);
+
+        Uploader::record_image_barrier(d, b, image, aspect, vk::ImageLayout::TRANSFER_DST_OPTIMAL, layout, vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::ALL_COMMANDS);
+
+        self.end_upload(d)
+    }
+
+    pub unsafe fn transition_image(&mut self, d: &Device, image: vk::Image, aspect: vk::ImageAspectFlags, layout: vk::ImageLayout) -> Result<()> {
+        let b = self.begin(d)?;
+
+        Uploader::record_image_barrier(d, b, image, aspect, vk::ImageLayout::UNDEFINED, layout, vk::AccessFlags::empty(), vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE, vk::PipelineStageFlags::TOP_OF_PIPE, vk::PipelineStageFlags::ALL_COMMANDS);
+
+        self.end_upload(d)
+    }
+
+    // Submits everything recorded so far and waits for it, after which the staging ring can be reused from the start
+    pub unsafe fn flush(&mut self, d: &Device) -> Result<()> {
+        if !self.recording {
+            return Ok(());
+        }
+
+        let b = self.commands.as_ref().unwrap().buffers[0];
+        let fence = self.fence.unwrap().fence;
+
+        d.device.end_command_buffer(b)?;
+
+        let buffers = [b];
+        let submit_i = vk::SubmitInfo::default()
+            .command_buffers(&buffers);
+
+        d.device.queue_submit(d.queue_main.0, &[submit_i], fence)?;
+        d.device.wait_for_fences(&[fence], true, u64::MAX)?;
+        d.device.reset_fences(&[fence])?;
+
+        for buffer in self.oversized_staging.drain(..) {
+            buffer.destroy(d);
+        }
+
+        self.head = 0;
+        self.recording = false;
+
+        Ok(())
+    }
+
+    // A failed flush can't be recovered from while tearing down, the resources are destroyed regardless
+    pub unsafe fn destroy(&mut self, d: &Device) {
+        let _ = self.flush(d);
+
+        if let Some(commands) = self.commands.take() {
+            commands.destroy(d);
+        }
+
+        if let Some(fence) = self.fence.take() {
+            fence.destroy(d);
+        }
+
+        if let Some(staging) = self.staging.take() {
+            staging.destroy(d);
+        }
+    }
+
+    unsafe fn begin(&mut self, d: &Device) -> Result<vk::CommandBuffer> {
+        if self.commands.is_none() {
+            self.commands = Some(Commands::new(d, d.queue_main.1, 1, true)?);
+            self.fence = Some(Fence::new(d, false)?);
+        }
+
+        let b = self.commands.as_ref().unwrap().buffers[0];
+
+        if !self.recording {
+            d.device.reset_command_buffer(b, vk::CommandBufferResetFlags::RELEASE_RESOURCES)?;
+
+            let buffer_bi = vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+            d.device.begin_command_buffer(b, &buffer_bi)?;
+
+            self.recording = true;
+        }
+
+        Ok(b)
+    }
+
+    unsafe fn end_upload(&mut self, d: &Device) -> Result<()> {
+        match self.batching {
+            true => Ok(()),
+            false => self.flush(d),
+        }
+    }
+
+    // Uploads larger than the ring get a staging buffer of their own, freed once they have been submitted
+    unsafe fn stage(&mut self, d: &Device, data: *const c_void, size: usize) -> Result<(vk::Buffer, u64)> {
+        if size > STAGING_RING_SIZE {
+            let staging = Uploader::create_staging_buffer(d, size)?;
+            staging.fill_from_ptr(d, data, size)?;
+
+            self.oversized_staging.push(staging);
+
+            return Ok((staging.buffer, 0));
+        }
+
+        if self.staging.is_none() {
+            self.staging = Some(Uploader::create_staging_buffer(d, STAGING_RING_SIZE)?);
+        }
+
+        let mut offset = self.head.next_multiple_of(STAGING_ALIGNMENT);
+
+        if offset + size > STAGING_RING_SIZE {
+            self.flush(d)?;
+            offset = 0;
+        }
+
+        let staging = self.staging.unwrap();
+        std::ptr::copy(data as *const u8, (staging.p_dst.unwrap() as *mut u8).add(offset), size);
+
+        self.head = offset + size;
+
+        Ok((staging.buffer, offset as u64))
+    }
+
+    unsafe fn create_staging_buffer(d: &Device, size: usize) -> Result<Buffer> {
+        let buffer_ci = vk::BufferCreateInfo::default()
+            .size(size as u64)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let buffer = d.device.create_buffer(&buffer_ci, None)?;
+        let allocation = d.allocator.allocate_buffer(&d.device, buffer, MemoryUsage::CpuToGpu)?;
+
+        Ok(Buffer {
+            buffer,
+            memory: allocation.memory,
+            size: size as u64,
+            p_dst: allocation.p_mapped,
+            host_visible: true,
+        })
+    }
+
+    unsafe fn record_buffer_barrier(d: &Device, b: vk::CommandBuffer, buffer: vk::Buffer, offset: u64, size: u64, src_access: vk::AccessFlags, dst_access: vk::AccessFlags, src_stage: vk::PipelineStageFlags, dst_stage: vk::PipelineStageFlags) {
+        let barrier = vk::BufferMemoryBarrier::default()
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .buffer(buffer)
+            .offset(offset)
+            .size(size)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED);
+
+        d.device.cmd_pipeline_barrier(b, src_stage, dst_stage, vk::DependencyFlags::empty(), &[], &[barrier], &[]);
+    }
+
+    unsafe fn record_image_barrier(d: &Device, b: vk::CommandBuffer, image: vk::Image, aspect: vk::ImageAspectFlags, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout, src_access: vk::AccessFlags, dst_access: vk::AccessFlags, src_stage: vk::PipelineStageFlags, dst_stage: vk::PipelineStageFlags) {
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(aspect)
+            .layer_count(1)
+            .level_count(vk::REMAINING_MIP_LEVELS);
+
+        let barrier = vk::ImageMemoryBarrier::default()
+            .image(image)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(subresource_range);
+
+        d.device.cmd_pipeline_barrier(b, src_stage, dst_stage, vk::DependencyFlags::empty(), &[], &[], &[barrier]);
+    }
+}
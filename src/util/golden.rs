@@ -0,0 +1,190 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use ash::vk;
+
+use crate::{capture, Error, Renderer, Result};
+
+// Set to write the rendered images as the new references instead of comparing against them
+pub const UPDATE_GOLDEN_ENV_VAR: &str = "VRG_UPDATE_GOLDEN";
+
+// Renders a graph offscreen and compares one of its images against a stored PNG.
+// A missing reference is an error, references are only written when VRG_UPDATE_GOLDEN is set
+pub struct GoldenTest {
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+    pub frames: usize,
+    pub image: String,
+    pub tolerance: u8,
+    pub reference: PathBuf,
+    pub diff: Option<PathBuf>,
+    pub debug: bool,
+}
+
+pub struct GoldenResult {
+    pub pixels: usize,
+    pub mismatched: usize,
+    pub max_difference: u8,
+    pub updated: bool,
+}
+
+impl GoldenTest {
+    pub fn new(reference: &str) -> GoldenTest {
+        GoldenTest {
+            extent: vk::Extent2D { width: 256, height: 256 },
+            format: vk::Format::R8G8B8A8_UNORM,
+            frames: 1,
+            image: "swapchain_image".to_string(),
+            tolerance: 0,
+            reference: PathBuf::from(reference),
+            diff: None,
+            debug: false,
+        }
+    }
+
+    pub fn extent(mut self, width: u32, height: u32) -> GoldenTest {
+        self.extent = vk::Extent2D { width, height };
+        self
+    }
+
+    pub fn format(mut self, format: vk::Format) -> GoldenTest {
+        self.format = format;
+        self
+    }
+
+    pub fn frames(mut self, frames: usize) -> GoldenTest {
+        self.frames = frames;
+        self
+    }
+
+    pub fn image(mut self, name: &str) -> GoldenTest {
+        self.image = name.to_string();
+        self
+    }
+
+    // Largest per-channel difference a pixel can have and still match
+    pub fn tolerance(mut self, tolerance: u8) -> GoldenTest {
+        self.tolerance = tolerance;
+        self
+    }
+
+    // Where the per-pixel difference is written when the comparison fails
+    pub fn diff(mut self, path: &str) -> GoldenTest {
+        self.diff = Some(PathBuf::from(path));
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> GoldenTest {
+        self.debug = debug;
+        self
+    }
+
+    // `setup` adds the layers and passes under test, the graph is then drawn for the configured number of frames
+    pub unsafe fn run<F: FnOnce(&mut Renderer) -> Result<()>>(&self, setup: F) -> Result<GoldenResult> {
+        let update = std::env::var_os(UPDATE_GOLDEN_ENV_VAR).is_some();
+
+        // Checked before anything is rendered, so a typo in the path doesn't wait on a whole run to be reported
+        if !update && !self.reference.exists() {
+            return Err(Error::Io(self.reference.to_string_lossy().to_string(), std::io::Error::new(std::io::ErrorKind::NotFound,
+                format!("Golden image is missing, set {} to write it from this run", UPDATE_GOLDEN_ENV_VAR))));
+        }
+
+        let mut renderer = Renderer::new_headless(self.extent, self.format, self.debug)?;
+
+        setup(&mut renderer)?;
+
+        // The compared image is read back outside the graph, so the passes writing it mustn't be culled
+        renderer.mark_exported(&self.image)?;
+
+        for _ in 0..self.frames {
+            renderer.pre_draw()?;
+            renderer.draw()?;
+        }
+
+        let frame = renderer.latest_image_index(&self.image);
+        let image = renderer.get_images(&self.image)?[frame];
+        let data = renderer.read_image(&self.image, frame)?;
+
+        let (color_type, pixels) = capture::png_pixels(image.format, &data)?;
+
+        if update {
+            capture::write_image(&self.reference, image.width, image.height, image.format, &data)?;
+
+            return Ok(GoldenResult {
+                pixels: (image.width * image.height) as usize,
+                mismatched: 0,
+                max_difference: 0,
+                updated: true,
+            });
+        }
+
+        let (reference_color_type, reference_width, reference_height, reference_pixels) = read_png(&self.reference)?;
+
+        if reference_color_type != color_type || reference_width != image.width || reference_height != image.height {
+            return Err(Error::Unsupported(format!("Golden image {:?} is {}x{} {:?}, rendered image is {}x{} {:?}",
+                self.reference, reference_width, reference_height, reference_color_type, image.width, image.height, color_type)));
+        }
+
+        let channels = color_type.samples();
+
+        let mut mismatched = 0;
+        let mut max_difference = 0;
+        let mut difference = Vec::<u8>::with_capacity(pixels.len());
+
+        for (pixel, reference_pixel) in pixels.chunks_exact(channels).zip(reference_pixels.chunks_exact(channels)) {
+            let pixel_difference = pixel.iter().zip(reference_pixel).map(|(a, b)| a.abs_diff(*b)).max().unwrap();
+
+            if pixel_difference > self.tolerance {
+                mismatched += 1;
+            }
+            max_difference = max_difference.max(pixel_difference);
+
+            // Alpha is kept opaque so the difference is visible in any viewer
+            difference.extend(pixel.iter().zip(reference_pixel).map(|(a, b)| a.abs_diff(*b)));
+            if color_type == png::ColorType::Rgba {
+                *difference.last_mut().unwrap() = 255;
+            }
+        }
+
+        if mismatched > 0 {
+            if let Some(diff) = &self.diff {
+                capture::encode_png(diff, image.width, image.height, color_type, false, &difference)?;
+            }
+        }
+
+        Ok(GoldenResult {
+            pixels: pixels.len() / channels,
+            mismatched,
+            max_difference,
+            updated: false,
+        })
+    }
+}
+
+impl GoldenResult {
+    pub fn passed(&self) -> bool {
+        self.mismatched == 0
+    }
+
+    pub fn assert_passed(&self) {
+        assert!(self.passed(), "Error: {} of {} pixels differ from the golden image (max difference {})", self.mismatched, self.pixels, self.max_difference);
+    }
+}
+
+fn read_png(path: &Path) -> Result<(png::ColorType, u32, u32, Vec<u8>)> {
+    let io_error = |e: std::io::Error| Error::Io(path.to_string_lossy().to_string(), e);
+
+    let file = File::open(path).map_err(io_error)?;
+
+    let mut reader = png::Decoder::new(file).read_info().map_err(|e| io_error(e.into()))?;
+
+    let mut pixels = vec![0u8; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).map_err(|e| io_error(e.into()))?;
+    pixels.truncate(info.buffer_size());
+
+    if info.bit_depth != png::BitDepth::Eight {
+        return Err(Error::Unsupported(format!("Golden image {:?} must be 8 bit", path)));
+    }
+
+    Ok((info.color_type, info.width, info.height, pixels))
+}
@@ -1,4 +1,7 @@
-use std::collections::{HashMap, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+use crate::error::{Error, Result};
 
 pub struct Node<T> {
     pub name: String,
@@ -12,6 +15,51 @@ pub struct Edge<U: Copy> {
     pub info: U,
 }
 
+// Describes node and edge data when a graph is exported, see Graph::to_dot and Graph::to_json
+pub trait GraphLabel {
+    fn label(&self) -> String;
+}
+
+impl GraphLabel for usize {
+    fn label(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl GraphLabel for () {
+    fn label(&self) -> String {
+        String::new()
+    }
+}
+
+impl<L: GraphLabel> GraphLabel for Option<L> {
+    fn label(&self) -> String {
+        match self {
+            Some(data) => data.label(),
+            None => String::new(),
+        }
+    }
+}
+
+// Quotes a string for DOT or JSON output, both of which share the same escapes for what labels contain
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
 pub struct Graph<T, U: Copy> {
     nodes: Vec<Node<T>>,
     edges: Vec<Edge<U>>,
@@ -43,28 +91,44 @@ impl <T, U: Copy> Graph<T, U> {
         self.root = Some(name.clone());
     }
 
-    pub fn get_root(&self) -> String {
-        assert!(!self.empty(), "Error: Graph is empty");
+    pub fn get_root(&self) -> Result<String> {
+        if self.empty() {
+            return Err(Error::InvalidGraph("Graph is empty".to_string()));
+        }
         
         if let Some(root_pass_name) = &self.root {
-            root_pass_name.clone()
+            Ok(root_pass_name.clone())
         } else {
-            self.node_refs.keys().next().unwrap().to_string()
+            // Without a root, the first node nothing depends on is used
+            let end_nodes = self.end_nodes();
+            Ok(end_nodes.first().unwrap_or(&&self.nodes[0]).name.clone())
         }
     }
 
-    pub fn add_node(&mut self, name: &str, data: T) {
+    // Nodes with no outgoing edges, in the order they were added
+    pub fn end_nodes(&self) -> Vec<&Node<T>> {
+        self.nodes.iter().filter(|node| self.src_edge_refs[&node.name].is_empty()).collect()
+    }
+
+    // Nodes are looked up by name, so a second node with the same name is rejected rather than replacing the first
+    pub fn add_node(&mut self, name: &str, data: T) -> Result<()> {
+        if self.node_refs.contains_key(name) {
+            return Err(Error::InvalidGraph(format!("Node '{}' was added more than once", name)));
+        }
+
         self.nodes.push(Node { name: name.to_string(), data });
         self.node_refs.insert(name.to_string(), self.nodes.len() - 1);
 
         self.src_edge_refs.insert(name.to_string(), Vec::new());
         self.dst_edge_refs.insert(name.to_string(), Vec::new());
+
+        Ok(())
     }
 
-    pub fn add_edge(&mut self, src: &str, dst: &str, info: U) {
+    pub fn add_edge(&mut self, src: &str, dst: &str, info: U) -> Result<()> {
         self.edges.push(Edge { 
-            src: *self.node_refs.get(src).unwrap(),
-            dst: *self.node_refs.get(dst).unwrap(),
+            src: self.get_node_index(src)?,
+            dst: self.get_node_index(dst)?,
 
             info,
         });
@@ -74,10 +138,24 @@ impl <T, U: Copy> Graph<T, U> {
 
         self.src_edge_refs.get_mut(src).unwrap().push((src_edge_ref, self.edges.len() - 1));
         self.dst_edge_refs.get_mut(dst).unwrap().push((dst_edge_ref, self.edges.len() - 1));
+
+        Ok(())
     }
 
-    pub fn get_node(&self, name: &str) -> &Node<T> {
-        &self.nodes[*self.node_refs.get(name).expect(&format!("Error: No node with name '{}' found", name))]
+    pub fn get_node(&self, name: &str) -> Result<&Node<T>> {
+        Ok(&self.nodes[self.get_node_index(name)?])
+    }
+
+    pub fn get_node_index(&self, name: &str) -> Result<usize> {
+        self.node_refs.get(name).copied().ok_or(Error::MissingNode(name.to_string()))
+    }
+
+    pub fn get_nodes(&self) -> &Vec<Node<T>> {
+        &self.nodes
+    }
+
+    pub fn get_edges(&self) -> &Vec<Edge<U>> {
+        &self.edges
     }
 
     pub fn get_src_node(&self, edge: &Edge<U>) -> &Node<T> {
@@ -148,13 +226,13 @@ impl <T, U: Copy> Graph<T, U> {
         }).collect()
     }
 
-    pub fn breadth_first_forwards(&self, root_name: Option<&str>) -> Vec<&Node<T>> {
-        let root_default = self.get_root();
+    pub fn breadth_first_forwards(&self, root_name: Option<&str>) -> Result<Vec<&Node<T>>> {
+        let root_default = self.get_root()?;
         let root = root_name.unwrap_or(&root_default);
         
         const MAX_ITERATIONS: u32 = 1000;
 
-        let mut tree = vec![self.get_node(root)];
+        let mut tree = vec![self.get_node(root)?];
         let mut tree_refs = vec![self.get_node_ref(root)];
 
         let open_node_refs = self.get_next_node_refs(root);
@@ -180,16 +258,16 @@ impl <T, U: Copy> Graph<T, U> {
             }
         }
 
-        tree
+        Ok(tree)
     }
 
-    pub fn breadth_first_backwards(&self, root_name: Option<&str>) -> Vec<&Node<T>> {
-        let root_default = self.get_root();
+    pub fn breadth_first_backwards(&self, root_name: Option<&str>) -> Result<Vec<&Node<T>>> {
+        let root_default = self.get_root()?;
         let root = root_name.unwrap_or(&root_default);
         
         const MAX_ITERATIONS: u32 = 1000;
 
-        let mut tree = vec![self.get_node(root)];
+        let mut tree = vec![self.get_node(root)?];
         let mut tree_refs = vec![self.get_node_ref(root)];
 
         let open_node_refs = self.get_prev_node_refs(root);
@@ -215,9 +293,355 @@ impl <T, U: Copy> Graph<T, U> {
             }
         }
 
-        tree
+        Ok(tree)
+    }
+
+    // Walks the graph depth first, returning the first cycle found as a list of node names ending where it started
+    pub fn find_cycle(&self) -> Option<Vec<String>> {
+        // 0 is unvisited, 1 is on the current path, 2 is finished
+        let mut states = vec![0u8; self.nodes.len()];
+        let mut path = Vec::<usize>::new();
+
+        for start in 0..self.nodes.len() {
+            if states[start] != 0 {
+                continue;
+            }
+
+            let mut stack = vec![(start, 0usize)];
+            states[start] = 1;
+            path.push(start);
+
+            while let Some((node_ref, next)) = stack.last_mut() {
+                let next_node_refs = self.get_next_node_refs(&self.nodes[*node_ref].name);
+
+                if *next < next_node_refs.len() {
+                    let next_ref = next_node_refs[*next];
+                    *next += 1;
+
+                    match states[next_ref] {
+                        0 => {
+                            states[next_ref] = 1;
+                            path.push(next_ref);
+                            stack.push((next_ref, 0));
+                        },
+                        1 => {
+                            let cycle_start = path.iter().position(|&r| r == next_ref).unwrap();
+                            let mut cycle = path[cycle_start..].iter().map(|&r| self.nodes[r].name.clone()).collect::<Vec<String>>();
+                            cycle.push(self.nodes[next_ref].name.clone());
+
+                            return Some(cycle);
+                        },
+                        _ => {},
+                    }
+                } else {
+                    states[*node_ref] = 2;
+                    path.pop();
+                    stack.pop();
+                }
+            }
+        }
+
+        None
+    }
+
+    // Marks every node the root can be reached from, including the root itself
+    fn reaching_refs(&self, root: &str) -> Result<Vec<bool>> {
+        let mut reached = vec![false; self.nodes.len()];
+        reached[self.get_node_index(root)?] = true;
+
+        let mut open_node_refs_queue = VecDeque::from(self.get_prev_node_refs(root));
+        while let Some(node_ref) = open_node_refs_queue.pop_front() {
+            if !reached[node_ref] {
+                reached[node_ref] = true;
+                open_node_refs_queue.extend(self.get_prev_node_refs(&self.nodes[node_ref].name));
+            }
+        }
+
+        Ok(reached)
+    }
+
+    // Whether there is a path of one or more edges from src to dst
+    pub fn reaches(&self, src: &str, dst: &str) -> Result<bool> {
+        let src_ref = self.get_node_index(src)?;
+
+        Ok(src != dst && self.reaching_refs(dst)?[src_ref])
+    }
+
+    // Nodes that the root can't be reached from, which a backwards walk from the root never visits
+    pub fn unreachable_nodes(&self, root: &str) -> Result<Vec<&Node<T>>> {
+        let reached = self.reaching_refs(root)?;
+
+        Ok(self.nodes.iter().zip(reached).filter(|(_, reached)| !reached).map(|(node, _)| node).collect())
+    }
+
+    // Kahn's algorithm over the nodes leading to the root, so every node comes after all of its predecessors.
+    // Ties between nodes that are ready at the same time go to the one added first, keeping the order stable
+    pub fn topological_order(&self, root_name: Option<&str>) -> Result<Vec<&Node<T>>> {
+        let root_default = self.get_root()?;
+        let root = root_name.unwrap_or(&root_default);
+
+        let included = self.reaching_refs(root)?;
+
+        let mut in_degrees = vec![0usize; self.nodes.len()];
+        for edge in &self.edges {
+            if included[edge.src] && included[edge.dst] {
+                in_degrees[edge.dst] += 1;
+            }
+        }
+
+        let mut ready = (0..self.nodes.len()).filter(|&r| included[r] && in_degrees[r] == 0).map(Reverse).collect::<BinaryHeap<Reverse<usize>>>();
+        let mut order = Vec::<&Node<T>>::with_capacity(self.nodes.len());
+
+        while let Some(Reverse(node_ref)) = ready.pop() {
+            order.push(&self.nodes[node_ref]);
+
+            for next_ref in self.get_next_node_refs(&self.nodes[node_ref].name) {
+                if included[next_ref] {
+                    in_degrees[next_ref] -= 1;
+
+                    if in_degrees[next_ref] == 0 {
+                        ready.push(Reverse(next_ref));
+                    }
+                }
+            }
+        }
+
+        if order.len() != included.iter().filter(|&&included| included).count() {
+            let cycle = self.find_cycle().map(|cycle| cycle.join(" -> ")).unwrap_or_default();
+            return Err(Error::InvalidGraph(format!("Graph contains the cycle {}", cycle)));
+        }
+
+        Ok(order)
+    }
+
+    // Pairs each node in topological order with its level, the length of the longest chain of predecessors before it.
+    // Nodes on the same level don't depend on each other
+    pub fn levels(&self, root_name: Option<&str>) -> Result<Vec<(&Node<T>, usize)>> {
+        let order = self.topological_order(root_name)?;
+
+        let mut levels = HashMap::<&str, usize>::new();
+        for node in &order {
+            let level = self.get_prev_nodes(&node.name).iter().filter_map(|prev| levels.get(prev.name.as_str())).map(|level| level + 1).max().unwrap_or(0);
+            levels.insert(&node.name, level);
+        }
+
+        Ok(order.into_iter().map(|node| (node, levels[node.name.as_str()])).collect())
+    }
+
+    // The most expensive chain of nodes ending at the root, with its total cost, for finding what bounds a frame's length
+    pub fn critical_path<F: Fn(&Node<T>) -> f32>(&self, root_name: Option<&str>, cost: F) -> Result<(Vec<&Node<T>>, f32)> {
+        let order = self.topological_order(root_name)?;
+
+        // Total cost of the most expensive chain ending at each node, and the predecessor it came through
+        let mut totals = HashMap::<&str, (f32, Option<&Node<T>>)>::new();
+        for node in &order {
+            let best_prev = self.get_prev_nodes(&node.name).into_iter().filter_map(|prev| {
+                totals.get(prev.name.as_str()).map(|&(total, _)| (total, prev))
+            }).max_by(|a, b| a.0.total_cmp(&b.0));
+
+            let total = best_prev.map(|(total, _)| total).unwrap_or(0.0) + cost(node);
+            totals.insert(&node.name, (total, best_prev.map(|(_, prev)| prev)));
+        }
+
+        let root = match order.last() {
+            Some(root) => *root,
+            None => return Ok((Vec::new(), 0.0)),
+        };
+
+        let mut path = vec![root];
+        while let Some(prev) = totals[path.last().unwrap().name.as_str()].1 {
+            path.push(prev);
+        }
+        path.reverse();
+
+        Ok((path, totals[root.name.as_str()].0))
+    }
+
+    // Describes everything that would stop the graph being walked back from its root, without stopping at the first problem
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::<String>::new();
+
+        if self.empty() {
+            problems.push("graph is empty".to_string());
+            return problems;
+        }
+
+        if let Some(cycle) = self.find_cycle() {
+            problems.push(format!("contains the cycle {}", cycle.join(" -> ")));
+        }
+
+        // Without a root the first end node is used, which is only what was meant when there is a single one
+        let root = match &self.root {
+            Some(root) if !self.node_refs.contains_key(root) => {
+                problems.push(format!("root '{}' is not in the graph", root));
+                return problems;
+            },
+            Some(root) => root.clone(),
+            None => {
+                let end_nodes = self.end_nodes();
+
+                if end_nodes.len() != 1 {
+                    let names = end_nodes.iter().map(|node| format!("'{}'", node.name)).collect::<Vec<String>>();
+                    problems.push(format!("has no root set and {} possible end nodes: {}", end_nodes.len(), names.join(", ")));
+                    return problems;
+                }
+
+                end_nodes[0].name.clone()
+            }
+        };
+
+        if let Ok(unreachable) = self.unreachable_nodes(&root) {
+            for node in unreachable {
+                problems.push(format!("'{}' never leads to the root '{}' and will not run", node.name, root));
+            }
+        }
+
+        problems
+    }
+
+    pub fn to_dot(&self, name: &str) -> String where T: GraphLabel, U: GraphLabel {
+        self.to_dot_with(name, |node| node.data.label(), |edge| edge.info.label())
+    }
+
+    pub fn to_dot_with<N: Fn(&Node<T>) -> String, E: Fn(&Edge<U>) -> String>(&self, name: &str, node_label: N, edge_label: E) -> String {
+        format!("digraph {} {{\n{}}}\n", quote(name), self.dot_statements("    ", "", node_label, edge_label))
+    }
+
+    // Node and edge statements without the enclosing digraph, so several graphs can be written into one file.
+    // Node ids are prefixed to keep them unique, while labels keep the plain names. The root is drawn with a double border
+    pub fn dot_statements<N: Fn(&Node<T>) -> String, E: Fn(&Edge<U>) -> String>(&self, indent: &str, prefix: &str, node_label: N, edge_label: E) -> String {
+        let root = self.get_root().ok();
+        let mut dot = String::new();
+
+        for node in &self.nodes {
+            let label = match node_label(node) {
+                label if label.is_empty() => node.name.clone(),
+                label => format!("{}\n{}", node.name, label),
+            };
+            let border = if root.as_ref() == Some(&node.name) { ", peripheries=2" } else { "" };
+
+            dot.push_str(&format!("{}{} [label={}{}];\n", indent, quote(&format!("{}{}", prefix, node.name)), quote(&label), border));
+        }
+
+        for edge in &self.edges {
+            let src = quote(&format!("{}{}", prefix, self.nodes[edge.src].name));
+            let dst = quote(&format!("{}{}", prefix, self.nodes[edge.dst].name));
+
+            match edge_label(edge) {
+                label if label.is_empty() => dot.push_str(&format!("{}{} -> {};\n", indent, src, dst)),
+                label => dot.push_str(&format!("{}{} -> {} [label={}];\n", indent, src, dst, quote(&label))),
+            }
+        }
+
+        dot
+    }
+
+    pub fn to_json(&self) -> String where T: GraphLabel, U: GraphLabel {
+        self.to_json_with(|node| node.data.label(), |edge| edge.info.label())
+    }
+
+    pub fn to_json_with<N: Fn(&Node<T>) -> String, E: Fn(&Edge<U>) -> String>(&self, node_label: N, edge_label: E) -> String {
+        let root = match self.get_root() {
+            Ok(root) => quote(&root),
+            Err(_) => "null".to_string(),
+        };
+
+        let nodes = self.nodes.iter().map(|node| {
+            format!("{{\"name\":{},\"label\":{}}}", quote(&node.name), quote(&node_label(node)))
+        }).collect::<Vec<String>>();
+
+        let edges = self.edges.iter().map(|edge| {
+            format!("{{\"src\":{},\"dst\":{},\"label\":{}}}", quote(&self.nodes[edge.src].name), quote(&self.nodes[edge.dst].name), quote(&edge_label(edge)))
+        }).collect::<Vec<String>>();
+
+        format!("{{\"root\":{},\"nodes\":[{}],\"edges\":[{}]}}", root, nodes.join(","), edges.join(","))
     }
 
     pub fn node_count(&self) -> usize { self.nodes.len() }
     pub fn edge_count(&self) -> usize { self.edges.len() }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(nodes: &[(&str, f32)], edges: &[(&str, &str)]) -> Graph<f32, ()> {
+        let mut graph = Graph::new();
+
+        for (name, cost) in nodes {
+            graph.add_node(name, *cost).unwrap();
+        }
+
+        for (src, dst) in edges {
+            graph.add_edge(src, dst, ()).unwrap();
+        }
+
+        graph
+    }
+
+    fn names<T>(nodes: &[&Node<T>]) -> Vec<String> {
+        nodes.iter().map(|node| node.name.clone()).collect()
+    }
+
+    #[test]
+    fn topological_order_breaks_ties_by_insertion_order() {
+        let graph = graph(&[("c", 1.0), ("a", 1.0), ("b", 1.0), ("end", 1.0)], &[("b", "end"), ("a", "end"), ("c", "end")]);
+
+        assert_eq!(names(&graph.topological_order(None).unwrap()), vec!["c", "a", "b", "end"]);
+    }
+
+    #[test]
+    fn topological_order_follows_edges_before_insertion_order() {
+        let graph = graph(&[("late", 1.0), ("early", 1.0), ("end", 1.0)], &[("early", "late"), ("late", "end")]);
+
+        assert_eq!(names(&graph.topological_order(None).unwrap()), vec!["early", "late", "end"]);
+    }
+
+    #[test]
+    fn find_cycle_returns_the_path_around_it() {
+        let graph = graph(&[("a", 1.0), ("b", 1.0), ("c", 1.0), ("end", 1.0)], &[("a", "b"), ("b", "c"), ("c", "a"), ("c", "end")]);
+
+        assert_eq!(graph.find_cycle().unwrap(), vec!["a", "b", "c", "a"]);
+
+        match graph.topological_order(Some("end")) {
+            Err(Error::InvalidGraph(message)) => assert!(message.contains("a -> b -> c -> a"), "{}", message),
+            _ => panic!("Expected the cycle to be reported"),
+        }
+    }
+
+    #[test]
+    fn find_cycle_finds_nothing_in_a_dag() {
+        let graph = graph(&[("a", 1.0), ("b", 1.0), ("c", 1.0)], &[("a", "b"), ("a", "c"), ("b", "c")]);
+
+        assert!(graph.find_cycle().is_none());
+    }
+
+    #[test]
+    fn critical_path_takes_the_most_expensive_chain() {
+        let graph = graph(&[("cheap", 1.0), ("expensive", 5.0), ("end", 1.0)], &[("cheap", "end"), ("expensive", "end")]);
+
+        let (path, cost) = graph.critical_path(None, |node| node.data).unwrap();
+
+        assert_eq!(names(&path), vec!["expensive", "end"]);
+        assert_eq!(cost, 6.0);
+    }
+
+    #[test]
+    fn empty_graph() {
+        let graph = Graph::<f32, ()>::new();
+
+        assert!(graph.find_cycle().is_none());
+        assert!(matches!(graph.topological_order(None), Err(Error::InvalidGraph(_))));
+        assert!(matches!(graph.critical_path(None, |node| node.data), Err(Error::InvalidGraph(_))));
+        assert_eq!(graph.validate(), vec!["graph is empty"]);
+    }
+
+    #[test]
+    fn duplicate_node_names_are_rejected() {
+        let mut graph = graph(&[("a", 1.0)], &[]);
+
+        assert!(matches!(graph.add_node("a", 2.0), Err(Error::InvalidGraph(_))));
+        assert_eq!(graph.node_count(), 1);
+        assert_eq!(graph.get_node("a").unwrap().data, 1.0);
+    }
+}
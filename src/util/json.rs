@@ -0,0 +1,392 @@
+use crate::util::graph::quote;
+
+// Just enough JSON for graph description files, objects keep their keys in file order so saved files stay readable
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    text: &'a str,
+}
+
+impl JsonValue {
+    pub fn parse(text: &str) -> Result<JsonValue, String> {
+        let mut parser = Parser {
+            chars: text.char_indices().peekable(),
+            text,
+        };
+
+        let value = parser.value()?;
+
+        parser.skip_whitespace();
+        match parser.chars.peek() {
+            Some(&(i, _)) => Err(parser.error(i, "trailing characters after the document")),
+            None => Ok(value),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsonValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<JsonValue>> {
+        match self {
+            JsonValue::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, JsonValue::Null)
+    }
+
+    // Two space indentation, with arrays of plain values kept on one line
+    pub fn to_string_pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out.push('\n');
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize) {
+        match self {
+            JsonValue::Null => out.push_str("null"),
+            JsonValue::Bool(b) => out.push_str(&b.to_string()),
+            JsonValue::Number(n) => out.push_str(&n.to_string()),
+            JsonValue::String(s) => out.push_str(&quote(s)),
+            JsonValue::Array(values) if values.iter().all(|value| !matches!(value, JsonValue::Array(_) | JsonValue::Object(_))) => {
+                out.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    value.write_pretty(out, indent);
+                }
+                out.push(']');
+            },
+            JsonValue::Array(values) => {
+                out.push_str("[\n");
+                for (i, value) in values.iter().enumerate() {
+                    out.push_str(&"  ".repeat(indent + 1));
+                    value.write_pretty(out, indent + 1);
+                    out.push_str(if i + 1 < values.len() { ",\n" } else { "\n" });
+                }
+                out.push_str(&"  ".repeat(indent));
+                out.push(']');
+            },
+            JsonValue::Object(fields) if fields.is_empty() => out.push_str("{}"),
+            JsonValue::Object(fields) => {
+                out.push_str("{\n");
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    out.push_str(&"  ".repeat(indent + 1));
+                    out.push_str(&quote(name));
+                    out.push_str(": ");
+                    value.write_pretty(out, indent + 1);
+                    out.push_str(if i + 1 < fields.len() { ",\n" } else { "\n" });
+                }
+                out.push_str(&"  ".repeat(indent));
+                out.push('}');
+            },
+        }
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, offset: usize, message: &str) -> String {
+        let line = self.text[..offset].matches('\n').count() + 1;
+        let column = offset - self.text[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+
+        format!("{} at line {}, column {}", message, line, column)
+    }
+
+    fn end_error(&self, message: &str) -> String {
+        self.error(self.text.len(), message)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(&(_, c)) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((i, c)) => Err(self.error(i, &format!("expected '{}', found '{}'", expected, c))),
+            None => Err(self.end_error(&format!("expected '{}', found the end of the file", expected))),
+        }
+    }
+
+    fn value(&mut self) -> Result<JsonValue, String> {
+        self.skip_whitespace();
+
+        let (start, c) = match self.chars.peek() {
+            Some(&(start, c)) => (start, c),
+            None => return Err(self.end_error("expected a value, found the end of the file")),
+        };
+
+        match c {
+            '{' => self.object(),
+            '[' => self.array(),
+            '"' => Ok(JsonValue::String(self.string()?)),
+            't' => self.keyword("true", JsonValue::Bool(true)),
+            'f' => self.keyword("false", JsonValue::Bool(false)),
+            'n' => self.keyword("null", JsonValue::Null),
+            '-' | '0'..='9' => self.number(),
+            _ => Err(self.error(start, &format!("unexpected '{}'", c))),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: JsonValue) -> Result<JsonValue, String> {
+        let start = self.chars.peek().unwrap().0;
+
+        if self.text[start..].starts_with(keyword) {
+            for _ in 0..keyword.len() {
+                self.chars.next();
+            }
+            Ok(value)
+        } else {
+            Err(self.error(start, "unexpected identifier"))
+        }
+    }
+
+    fn number(&mut self) -> Result<JsonValue, String> {
+        let start = self.chars.peek().unwrap().0;
+        let mut end = start;
+
+        while let Some(&(i, c)) = self.chars.peek() {
+            if !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+                break;
+            }
+            end = i + c.len_utf8();
+            self.chars.next();
+        }
+
+        self.text[start..end].parse::<f64>().map(JsonValue::Number).map_err(|_| self.error(start, &format!("invalid number '{}'", &self.text[start..end])))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(s),
+                Some((i, '\\')) => match self.chars.next() {
+                    Some((_, '"')) => s.push('"'),
+                    Some((_, '\\')) => s.push('\\'),
+                    Some((_, '/')) => s.push('/'),
+                    Some((_, 'n')) => s.push('\n'),
+                    Some((_, 't')) => s.push('\t'),
+                    Some((_, 'r')) => s.push('\r'),
+                    Some((_, 'b')) => s.push('\u{8}'),
+                    Some((_, 'f')) => s.push('\u{c}'),
+                    Some((_, 'u')) => s.push(self.unicode_escape(i)?),
+                    _ => return Err(self.error(i, "invalid escape")),
+                },
+                Some((_, c)) => s.push(c),
+                None => return Err(self.end_error("unterminated string")),
+            }
+        }
+    }
+
+    // Characters outside the basic multilingual plane are escaped as a UTF-16 surrogate pair
+    fn unicode_escape(&mut self, start: usize) -> Result<char, String> {
+        let high = self.hex_digits(start)?;
+
+        let code = match high {
+            0xd800..=0xdbff => {
+                if self.chars.next().map(|(_, c)| c) != Some('\\') || self.chars.next().map(|(_, c)| c) != Some('u') {
+                    return Err(self.error(start, "unpaired surrogate in unicode escape"));
+                }
+
+                match self.hex_digits(start)? {
+                    low @ 0xdc00..=0xdfff => 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00),
+                    _ => return Err(self.error(start, "unpaired surrogate in unicode escape")),
+                }
+            },
+            _ => high,
+        };
+
+        char::from_u32(code).ok_or_else(|| self.error(start, "invalid unicode escape"))
+    }
+
+    fn hex_digits(&mut self, start: usize) -> Result<u32, String> {
+        let hex = (0..4).filter_map(|_| self.chars.next().map(|(_, c)| c)).collect::<String>();
+
+        match hex.len() == 4 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            true => Ok(u32::from_str_radix(&hex, 16).unwrap()),
+            false => Err(self.error(start, "invalid unicode escape")),
+        }
+    }
+
+    fn array(&mut self) -> Result<JsonValue, String> {
+        self.expect('[')?;
+
+        let mut values = Vec::<JsonValue>::new();
+
+        self.skip_whitespace();
+        if let Some(&(_, ']')) = self.chars.peek() {
+            self.chars.next();
+            return Ok(JsonValue::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, ']')) => return Ok(JsonValue::Array(values)),
+                Some((i, c)) => return Err(self.error(i, &format!("expected ',' or ']', found '{}'", c))),
+                None => return Err(self.end_error("unterminated array")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<JsonValue, String> {
+        self.expect('{')?;
+
+        let mut fields = Vec::<(String, JsonValue)>::new();
+
+        self.skip_whitespace();
+        if let Some(&(_, '}')) = self.chars.peek() {
+            self.chars.next();
+            return Ok(JsonValue::Object(fields));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.expect(':')?;
+            fields.push((name, self.value()?));
+
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, '}')) => return Ok(JsonValue::Object(fields)),
+                Some((i, c)) => return Err(self.error(i, &format!("expected ',' or '}}', found '{}'", c))),
+                None => return Err(self.end_error("unterminated object")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> String {
+        JsonValue::parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn parses_every_kind_of_value() {
+        let json = JsonValue::parse(r#"{"null": null, "bool": true, "number": 2, "string": "s", "array": [false, []], "object": {}}"#).unwrap();
+
+        assert_eq!(json, JsonValue::Object(vec![
+            ("null".to_string(), JsonValue::Null),
+            ("bool".to_string(), JsonValue::Bool(true)),
+            ("number".to_string(), JsonValue::Number(2.0)),
+            ("string".to_string(), JsonValue::String("s".to_string())),
+            ("array".to_string(), JsonValue::Array(vec![JsonValue::Bool(false), JsonValue::Array(Vec::new())])),
+            ("object".to_string(), JsonValue::Object(Vec::new())),
+        ]));
+    }
+
+    #[test]
+    fn keeps_object_keys_in_file_order() {
+        let json = JsonValue::parse(r#"{"b": 1, "a": 2}"#).unwrap();
+
+        match json {
+            JsonValue::Object(fields) => assert_eq!(fields.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["b", "a"]),
+            _ => panic!("Expected an object"),
+        }
+    }
+
+    #[test]
+    fn parses_numbers() {
+        for (text, expected) in [("0", 0.0), ("-12", -12.0), ("3.25", 3.25), ("1e3", 1000.0), ("-1.5E-2", -0.015)] {
+            assert_eq!(JsonValue::parse(text).unwrap(), JsonValue::Number(expected), "{}", text);
+        }
+
+        assert!(parse_error("1.2.3").contains("invalid number '1.2.3'"));
+        assert!(parse_error("-").contains("invalid number '-'"));
+    }
+
+    #[test]
+    fn parses_escapes() {
+        let json = JsonValue::parse(r#""\"\\\/\n\t\r\b\f\u00e9\ud83d\ude00""#).unwrap();
+
+        assert_eq!(json.as_str(), Some("\"\\/\n\t\r\u{8}\u{c}\u{e9}\u{1f600}"));
+    }
+
+    #[test]
+    fn rejects_invalid_escapes() {
+        assert!(parse_error(r#""\q""#).contains("invalid escape"));
+        assert!(parse_error(r#""\u00g0""#).contains("invalid unicode escape"));
+        assert!(parse_error(r#""\u12""#).contains("invalid unicode escape"));
+        assert!(parse_error(r#""\ud83d""#).contains("unpaired surrogate"));
+        assert!(parse_error(r#""\ud83dA""#).contains("unpaired surrogate"));
+    }
+
+    #[test]
+    fn reports_where_malformed_input_goes_wrong() {
+        assert_eq!(parse_error("{\n  \"a\": tru\n}"), "unexpected identifier at line 2, column 8");
+        assert_eq!(parse_error("[1 2]"), "expected ',' or ']', found '2' at line 1, column 4");
+        assert_eq!(parse_error(r#"{"a" 1}"#), "expected ':', found '1' at line 1, column 6");
+        assert_eq!(parse_error(r#"{"a": 1,}"#), "expected '\"', found '}' at line 1, column 9");
+        assert_eq!(parse_error("{} {}"), "trailing characters after the document at line 1, column 4");
+    }
+
+    #[test]
+    fn reports_unexpected_end_of_file() {
+        assert!(parse_error("").contains("expected a value, found the end of the file"));
+        assert!(parse_error("[1, 2").contains("unterminated array"));
+        assert!(parse_error(r#"{"a": 1"#).contains("unterminated object"));
+        assert!(parse_error(r#""abc"#).contains("unterminated string"));
+    }
+
+    #[test]
+    fn pretty_output_parses_back_to_the_same_value() {
+        let json = JsonValue::parse(r#"{"name": "a \"quoted\" name", "values": [1, 2.5, null], "nested": [{"x": true}], "empty": {}}"#).unwrap();
+
+        assert_eq!(JsonValue::parse(&json.to_string_pretty()).unwrap(), json);
+    }
+}
@@ -1,4 +1,6 @@
 pub mod window;
 pub mod frametime;
 pub mod graph;
-pub mod draw_to_screen;
\ No newline at end of file
+pub mod draw_to_screen;
+pub mod golden;
+pub mod json;
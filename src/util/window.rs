@@ -3,6 +3,8 @@ use winit::{
     window::WindowBuilder,
 };
 
+use crate::{Error, Result};
+
 pub struct Window {
     pub window: winit::window::Window,
     pub focused: bool,
@@ -10,20 +12,20 @@ pub struct Window {
 }
 
 impl Window {
-    pub fn new(el: &EventLoop<()>) -> Window {
+    pub fn new(el: &EventLoop<()>) -> Result<Window> {
         let window = WindowBuilder::new()
             .with_inner_size(winit::dpi::PhysicalSize::new(1280, 720))
             .with_title("Raytracer")
             .build(el)
-            .unwrap();
+            .map_err(|e| Error::Unsupported(format!("Failed to create window: {}", e)))?;
 
-        window.set_cursor_grab(winit::window::CursorGrabMode::Confined).unwrap();
+        window.set_cursor_grab(winit::window::CursorGrabMode::Confined).map_err(|e| Error::Unsupported(format!("Failed to grab cursor: {}", e)))?;
         window.set_cursor_visible(false);
 
-        Window {
+        Ok(Window {
             window: window,
             focused: true,
             res: (1280, 720),
-        }
+        })
     }
 }
\ No newline at end of file
@@ -6,6 +6,8 @@ use ash::vk;
 use crate::{core::Core, math::vec::Vec4};
 use crate::device::Device;
 use crate::buffer::{self, Buffer, BufferBuilder};
+use crate::allocator::MemoryUsage;
+use crate::error::{Error, Result};
 
 pub struct VertexAttribute {
     pub format: vk::Format,
@@ -37,34 +39,32 @@ pub struct VertexBuffer {
     pub attrib_descs: Vec<vk::VertexInputAttributeDescription>,
     pub vertex_buffer: Option<Buffer>,
     pub index_buffer: Option<Buffer>,
-    pub buffer_memory_flags: vk::MemoryPropertyFlags,
+    pub memory_usage: MemoryUsage,
     pub resizable: bool,
     pub index_size: Option<vk::IndexType>,
 }
 
 impl VertexBuffer {
-    pub unsafe fn new<T: VertexAttributes, U>(c: &Core, d: &Device, verts: Option<&Vec<T>>, indices: Option<&Vec<U>>, resizable: bool) -> VertexBuffer {
-        let binding_desc = vk::VertexInputBindingDescription::builder()
+    pub unsafe fn new<T: VertexAttributes, U>(c: &Core, d: &Device, verts: Option<&Vec<T>>, indices: Option<&Vec<U>>, resizable: bool) -> Result<VertexBuffer> {
+        let binding_desc = vk::VertexInputBindingDescription::default()
             .binding(0)
             .stride(std::mem::size_of::<T>() as u32)
-            .input_rate(vk::VertexInputRate::VERTEX)
-            .build();
+            .input_rate(vk::VertexInputRate::VERTEX);
 
         let vertex_attribs = T::get_attribute_data();
         let mut attrib_descs: Vec<vk::VertexInputAttributeDescription> = Vec::with_capacity(vertex_attribs.len());
 
         for (i, a) in vertex_attribs.iter().enumerate() {
-            attrib_descs.push(vk::VertexInputAttributeDescription::builder()
+            attrib_descs.push(vk::VertexInputAttributeDescription::default()
                 .binding(0)
                 .location(i as u32)
                 .format(a.format)
-                .offset(a.offset as u32)
-                .build());
+                .offset(a.offset as u32));
         }
 
-        let buffer_memory_flags = match resizable {
-            true => vk::MemoryPropertyFlags::HOST_VISIBLE,
-            false => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        let memory_usage = match resizable {
+            true => MemoryUsage::CpuToGpu,
+            false => MemoryUsage::GpuOnly,
         };
 
         let vertex_buffer = if let Some(vs) = verts {
@@ -72,9 +72,9 @@ impl VertexBuffer {
                 .size(mem::size_of::<T>() * vs.len())
                 .usage(vk::BufferUsageFlags::VERTEX_BUFFER)
                 .sharing_mode(vk::SharingMode::EXCLUSIVE)
-                .properties(buffer_memory_flags);
+                .memory_usage(memory_usage);
 
-            Some(buffer_builder.build_with_data(c, d, vs.as_ptr() as *const c_void))
+            Some(buffer_builder.build_with_data(c, d, vs.as_ptr() as *const c_void)?)
         } else {
             None
         };
@@ -84,51 +84,60 @@ impl VertexBuffer {
                 .size(mem::size_of::<U>() * is.len())
                 .usage(vk::BufferUsageFlags::INDEX_BUFFER)
                 .sharing_mode(vk::SharingMode::EXCLUSIVE)
-                .properties(buffer_memory_flags);
+                .memory_usage(memory_usage);
 
             (Some(match resizable {
-                true => index_buffer_builder.build(c, d),
-                false => index_buffer_builder.build_with_data(c, d, is.as_ptr() as *const c_void),
-            }), Some(match mem::size_of::<U>() {
-                2 => vk::IndexType::UINT16,
-                4 => vk::IndexType::UINT32,
-                _ => panic!("Error: incompatible type of index buffer"),
-            }))
+                true => index_buffer_builder.build(c, d)?,
+                false => index_buffer_builder.build_with_data(c, d, is.as_ptr() as *const c_void)?,
+            }), Some(VertexBuffer::index_type::<U>()?))
         } else {
             (None, None)
         };
 
-        VertexBuffer {
+        Ok(VertexBuffer {
             binding_desc,
             attrib_descs,
             vertex_buffer,
             index_buffer,
-            buffer_memory_flags,
+            memory_usage,
             resizable,
             index_size,
+        })
+    }
+
+    fn index_type<U>() -> Result<vk::IndexType> {
+        match mem::size_of::<U>() {
+            2 => Ok(vk::IndexType::UINT16),
+            4 => Ok(vk::IndexType::UINT32),
+            _ => Err(Error::Unsupported("Incompatible type of index buffer, indices must be 2 or 4 bytes".to_string())),
         }
     }
 
     // TODO: This shouldn't create new buffers
-    pub unsafe fn update<T: VertexAttributes, U>(&mut self, c: &Core, d: &Device, verts: Option<&Vec<T>>, indices: Option<&Vec<U>>) {
-        assert!(self.resizable, "Error: vertex buffer is not resizable");
+    // The replaced buffers are returned rather than destroyed, as frames in flight may still be reading them
+    pub unsafe fn update<T: VertexAttributes, U>(&mut self, c: &Core, d: &Device, verts: Option<&Vec<T>>, indices: Option<&Vec<U>>) -> Result<Vec<Buffer>> {
+        if !self.resizable {
+            return Err(Error::Unsupported("Vertex buffer is not resizable".to_string()));
+        }
+
+        let mut replaced = Vec::<Buffer>::new();
 
         if let Some(vs) = verts {
             if let Some(vb) = self.vertex_buffer {
-                //d.device.destroy_buffer(vb.buffer, None)
+                replaced.push(vb);
             }
 
             self.vertex_buffer = Some(BufferBuilder::new()
                 .size(mem::size_of::<T>() * vs.len())
                 .usage(vk::BufferUsageFlags::VERTEX_BUFFER)
                 .sharing_mode(vk::SharingMode::EXCLUSIVE)
-                .properties(self.buffer_memory_flags)
-                .build_with_data(c, d, vs.as_ptr() as *const c_void));
+                .memory_usage(self.memory_usage)
+                .build_with_data(c, d, vs.as_ptr() as *const c_void)?);
         }
 
         if let Some(is) = indices {
             if let Some(ib) = self.index_buffer {
-                //d.device.destroy_buffer(ib.buffer, None)
+                replaced.push(ib);
             }
 
             //assert!(self.index_buffer.is_some(), "Error: no index buffer is present to be updated");
@@ -137,14 +146,22 @@ impl VertexBuffer {
                 .size(mem::size_of::<U>() * is.len())
                 .usage(vk::BufferUsageFlags::INDEX_BUFFER)
                 .sharing_mode(vk::SharingMode::EXCLUSIVE)
-                .properties(self.buffer_memory_flags)
-                .build_with_data(c, d, is.as_ptr() as *const c_void));
-
-            self.index_size = Some(match mem::size_of::<U>() {
-                2 => vk::IndexType::UINT16,
-                4 => vk::IndexType::UINT32,
-                _ => panic!("Error: incompatible type of index buffer"),
-            });
+                .memory_usage(self.memory_usage)
+                .build_with_data(c, d, is.as_ptr() as *const c_void)?);
+
+            self.index_size = Some(VertexBuffer::index_type::<U>()?);
+        }
+
+        Ok(replaced)
+    }
+
+    pub unsafe fn destroy(&self, d: &Device) {
+        if let Some(vb) = self.vertex_buffer {
+            vb.destroy(d);
+        }
+
+        if let Some(ib) = self.index_buffer {
+            ib.destroy(d);
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,151 @@
+use std::ffi::c_void;
+
+use ash::vk;
+
+use vrg::compute_pass::{ComputePassBuilder, ComputePassDispatchInfo};
+use vrg::descriptors::CreationReference;
+use vrg::image::ImageBuilder;
+use vrg::layer::{LayerExecution, ResourceUsage};
+use vrg::math::vec::Vec4;
+use vrg::transfer_pass::TransferPassBuilder;
+use vrg::util::draw_to_screen::draw_to_screen;
+use vrg::util::golden::{GoldenTest, UPDATE_GOLDEN_ENV_VAR};
+use vrg::{Renderer, Result};
+
+// References live in tests/golden, rendered images are written there too when VRG_UPDATE_GOLDEN is set.
+// Anything that renders needs a Vulkan device, so those tests only run with `cargo test -- --ignored`
+
+fn reference(name: &str) -> String {
+    format!("{}/tests/golden/{}.png", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+unsafe fn add_source_image(renderer: &mut Renderer, name: &str, width: u32, height: u32, texels: &[[u8; 4]]) -> Result<()> {
+    renderer.add_images(name, ImageBuilder::new()
+        .width(width)
+        .height(height)
+        .format(vk::Format::R8G8B8A8_UNORM)
+        .usage(vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+        .layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .data_from_ptr(texels.as_ptr() as *const c_void, texels.len() * 4))
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn copy_image() {
+    let gradient = (0..64).flat_map(|y| (0..64).map(move |x| [x as u8 * 4, y as u8 * 4, 128, 255])).collect::<Vec<_>>();
+
+    let result = unsafe {
+        GoldenTest::new(&reference("copy_image")).extent(64, 64).run(|renderer| {
+            add_source_image(renderer, "gradient", 64, 64, &gradient)?;
+
+            renderer.add_layer("main", true, LayerExecution::Main)?;
+            renderer.add_transfer_pass("main", "copy", TransferPassBuilder::new().copy_image("gradient", "swapchain_image"))
+        })
+    };
+
+    result.unwrap().assert_passed();
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn blit_nearest() {
+    let checkerboard = (0..8).flat_map(|y| (0..8).map(move |x| match (x + y) % 2 {
+        0 => [255, 255, 255, 255],
+        _ => [0, 0, 0, 255],
+    })).collect::<Vec<_>>();
+
+    let result = unsafe {
+        GoldenTest::new(&reference("blit_nearest")).extent(64, 64).run(|renderer| {
+            add_source_image(renderer, "checkerboard", 8, 8, &checkerboard)?;
+
+            renderer.add_layer("main", true, LayerExecution::Main)?;
+            renderer.add_transfer_pass("main", "blit", TransferPassBuilder::new().blit_image("checkerboard", "swapchain_image", vk::Filter::NEAREST))
+        })
+    };
+
+    result.unwrap().assert_passed();
+}
+
+// 16x16 texels, so each covers a 4x4 block of a 64x64 target
+fn blocks() -> Vec<[u8; 4]> {
+    (0..16).flat_map(|y| (0..16).map(move |x| [x as u8 * 16, y as u8 * 16, 255 - (x + y) as u8 * 8, 255])).collect()
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn draw_to_screen_nearest() {
+    let blocks = blocks();
+
+    let result = unsafe {
+        GoldenTest::new(&reference("draw_to_screen")).extent(64, 64).run(|renderer| {
+            add_source_image(renderer, "blocks", 16, 16, &blocks)?;
+
+            renderer.add_layer("main", true, LayerExecution::Main)?;
+            renderer.add_graphics_pass("main", "draw", draw_to_screen(renderer, "blocks", "swapchain_image")?)
+        })
+    };
+
+    result.unwrap().assert_passed();
+}
+
+// Transparent texels leave the clear colour, half transparent red ones are blended over it
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn clear_and_blend() {
+    let checkerboard = (0..16).flat_map(|y| (0..16).map(move |x| match (x + y) % 2 {
+        0 => [255, 255, 255, 0],
+        _ => [255, 0, 0, 128],
+    })).collect::<Vec<_>>();
+
+    let result = unsafe {
+        GoldenTest::new(&reference("clear_and_blend")).extent(64, 64).tolerance(1).run(|renderer| {
+            add_source_image(renderer, "checkerboard", 16, 16, &checkerboard)?;
+
+            renderer.add_layer("main", true, LayerExecution::Main)?;
+            renderer.add_graphics_pass("main", "blend", draw_to_screen(renderer, "checkerboard", "swapchain_image")?
+                .clear_col(Vec4::new(0.2, 0.4, 0.6, 1.0)))
+        })
+    };
+
+    result.unwrap().assert_passed();
+}
+
+// tests/shaders/gradient.comp writes (x * 4, y * 4, 0, 255) to each texel
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn compute_gradient() {
+    let shader = format!("{}/tests/shaders/gradient.comp.spv", env!("CARGO_MANIFEST_DIR"));
+
+    let result = unsafe {
+        GoldenTest::new(&reference("compute_gradient")).extent(64, 64).image("output").run(|renderer| {
+            renderer.add_images("output", ImageBuilder::new()
+                .width(64)
+                .height(64)
+                .format(vk::Format::R8G8B8A8_UNORM)
+                .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC)
+                .layout(vk::ImageLayout::GENERAL))?;
+
+            renderer.add_layer("main", true, LayerExecution::Main)?;
+            renderer.add_compute_pass("main", "gradient", ComputePassBuilder::new()
+                .compute_shader(&shader)
+                .descriptors(vec![CreationReference::Image("output".to_string())], &renderer.data)
+                .dispatch_info(ComputePassDispatchInfo::new(4, 4, 1))
+                .writes("output", ResourceUsage::Storage))
+        })
+    };
+
+    result.unwrap().assert_passed();
+}
+
+#[test]
+fn missing_reference_fails() {
+    if std::env::var_os(UPDATE_GOLDEN_ENV_VAR).is_some() {
+        return;
+    }
+
+    let result = unsafe {
+        GoldenTest::new(&reference("missing")).run(|_| panic!("Nothing should be rendered without a reference"))
+    };
+
+    assert!(result.is_err());
+}